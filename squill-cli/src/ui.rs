@@ -0,0 +1,305 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use sqlx::PgConnection;
+
+use squill::config::Config;
+use squill::error_chain;
+use squill::migrate::MigrationDirectory;
+use squill::status::{Status, StatusEntry};
+
+const HELP: &str =
+    "↑/↓ select · enter view up.sql · d view down.sql · a apply · u undo · r refresh · q quit";
+
+pub async fn ui(config: &Config) -> anyhow::Result<()> {
+    let mut conn = config.connect().await?;
+    let mut app = App::new(Status::new(config).await?);
+
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal, config, &mut conn).await;
+    ratatui::restore();
+
+    result
+}
+
+struct App {
+    status: Status,
+    entries: Vec<StatusEntry>,
+    table: TableState,
+    screen: Screen,
+    message: Option<String>,
+}
+
+enum Screen {
+    List,
+    Sql {
+        title: String,
+        sql: String,
+        scroll: u16,
+    },
+    Confirm(Action),
+}
+
+enum Action {
+    Apply(MigrationDirectory),
+    Undo(MigrationDirectory),
+}
+
+impl App {
+    fn new(status: Status) -> Self {
+        let mut app = Self {
+            status,
+            entries: Vec::new(),
+            table: TableState::default(),
+            screen: Screen::List,
+            message: None,
+        };
+        app.refresh_entries();
+        app
+    }
+
+    async fn run(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        config: &Config,
+        conn: &mut PgConnection,
+    ) -> anyhow::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            // Nothing else runs while the screen waits for a key, so blocking here is fine.
+            let Event::Key(key) = tokio::task::block_in_place(event::read)? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            self.screen = match std::mem::replace(&mut self.screen, Screen::List) {
+                Screen::List => {
+                    self.message = None;
+
+                    match key.code {
+                        KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                        KeyCode::Down | KeyCode::Char('j') => self.select(1),
+                        KeyCode::Up | KeyCode::Char('k') => self.select(-1),
+                        KeyCode::Enter | KeyCode::Char('v') => self.view(false),
+                        KeyCode::Char('d') => self.view(true),
+                        KeyCode::Char('a') => self.confirm(false),
+                        KeyCode::Char('u') => self.confirm(true),
+                        KeyCode::Char('r') => {
+                            self.reload(config).await?;
+                            Screen::List
+                        }
+                        _ => Screen::List,
+                    }
+                }
+
+                Screen::Sql { title, sql, scroll } => match key.code {
+                    KeyCode::Down | KeyCode::Char('j') => Screen::Sql {
+                        title,
+                        sql,
+                        scroll: scroll.saturating_add(1),
+                    },
+                    KeyCode::Up | KeyCode::Char('k') => Screen::Sql {
+                        title,
+                        sql,
+                        scroll: scroll.saturating_sub(1),
+                    },
+                    _ => Screen::List,
+                },
+
+                Screen::Confirm(action) => {
+                    if key.code == KeyCode::Char('y') {
+                        self.perform(action, config, conn).await?;
+                    } else {
+                        self.message = Some(String::from("Cancelled."));
+                    }
+                    Screen::List
+                }
+            };
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, footer] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+
+        if let Screen::Sql { title, sql, scroll } = &self.screen {
+            let sql = Paragraph::new(sql.as_str())
+                .block(Block::bordered().title(title.as_str()))
+                .scroll((*scroll, 0));
+            frame.render_widget(sql, main);
+            frame.render_widget(
+                Paragraph::new("↑/↓ scroll · any other key to go back"),
+                footer,
+            );
+            return;
+        }
+
+        let rows = self.entries.iter().map(|e| {
+            Row::new([
+                e.id.to_string(),
+                e.name.clone(),
+                state(e).to_owned(),
+                e.run_at.map(|t| t.to_string()).unwrap_or_default(),
+            ])
+        });
+        let widths = [
+            Constraint::Length(20),
+            Constraint::Fill(1),
+            Constraint::Length(13),
+            Constraint::Length(29),
+        ];
+        let table = Table::new(rows, widths)
+            .header(Row::new(["id", "name", "state", "run_at"]).bold())
+            .block(Block::bordered().title(" squill "))
+            .row_highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(table, main, &mut self.table);
+
+        let footer_text = match (&self.screen, &self.message) {
+            (Screen::Confirm(Action::Apply(m)), _) => format!("Apply {m}? (y/n)"),
+            (Screen::Confirm(Action::Undo(m)), _) => {
+                format!("Run the down migration for {m}? (y/n)")
+            }
+            (_, Some(message)) => message.clone(),
+            (_, None) => HELP.to_owned(),
+        };
+        frame.render_widget(Paragraph::new(footer_text), footer);
+    }
+
+    fn select(&mut self, delta: isize) -> Screen {
+        if let Some(last) = self.entries.len().checked_sub(1) {
+            let current = self.table.selected().unwrap_or(0) as isize;
+            let next = (current + delta).clamp(0, last as isize);
+            self.table.select(Some(next as usize));
+        }
+        Screen::List
+    }
+
+    fn selected(&self) -> Option<&StatusEntry> {
+        self.table.selected().and_then(|i| self.entries.get(i))
+    }
+
+    fn selected_migration(&mut self) -> Option<MigrationDirectory> {
+        let id = self.selected()?.id;
+
+        let migration = self.status.available.get(id).cloned();
+        if migration.is_none() {
+            self.message = Some(format!("The files for migration {id} are missing."));
+        }
+        migration
+    }
+
+    fn view(&mut self, down: bool) -> Screen {
+        let Some(migration) = self.selected_migration() else {
+            return Screen::List;
+        };
+
+        let path = if down {
+            &migration.down_path
+        } else {
+            &migration.up_path
+        };
+
+        match std::fs::read_to_string(path) {
+            Ok(sql) => Screen::Sql {
+                title: format!(" {} ", path.to_string_lossy()),
+                sql,
+                scroll: 0,
+            },
+            Err(err) => {
+                self.message = Some(format!("Failed to read {}: {err}", path.to_string_lossy()));
+                Screen::List
+            }
+        }
+    }
+
+    fn confirm(&mut self, undo: bool) -> Screen {
+        let Some(applied) = self.selected().map(|e| e.run_at.is_some()) else {
+            return Screen::List;
+        };
+        let Some(migration) = self.selected_migration() else {
+            return Screen::List;
+        };
+
+        match (undo, applied) {
+            (false, false) => Screen::Confirm(Action::Apply(migration)),
+            (true, true) => Screen::Confirm(Action::Undo(migration)),
+            (false, true) => {
+                self.message = Some(format!("{migration} is already applied."));
+                Screen::List
+            }
+            (true, false) => {
+                self.message = Some(format!("{migration} isn't applied."));
+                Screen::List
+            }
+        }
+    }
+
+    async fn perform(
+        &mut self,
+        action: Action,
+        config: &Config,
+        conn: &mut PgConnection,
+    ) -> anyhow::Result<()> {
+        let opts = config.migrate_options();
+
+        self.message = Some(match action {
+            Action::Apply(m) => match apply(config, &self.status, conn, &m).await {
+                Ok(()) => format!("Applied {m}"),
+                Err(err) => format!("Failed to apply {m}: {err:#}"),
+            },
+            Action::Undo(m) => match m.down_with(&mut *conn, &opts).await {
+                Ok(()) => format!("Undid {m}"),
+                Err(err) => format!("Failed to undo {m}: {}", error_chain(&err)),
+            },
+        });
+
+        self.reload(config).await
+    }
+
+    async fn reload(&mut self, config: &Config) -> anyhow::Result<()> {
+        self.status = Status::new(config).await?;
+        self.refresh_entries();
+        Ok(())
+    }
+
+    fn refresh_entries(&mut self) {
+        self.entries = self
+            .status
+            .full_status()
+            .into_values()
+            .filter(|e| e.retired_by.is_none())
+            .collect();
+
+        let selected = self.table.selected().unwrap_or(0);
+        let last = self.entries.len().checked_sub(1);
+        self.table.select(last.map(|last| selected.min(last)));
+    }
+}
+
+async fn apply(
+    config: &Config,
+    status: &Status,
+    conn: &mut PgConnection,
+    migration: &MigrationDirectory,
+) -> anyhow::Result<()> {
+    // Picking a migration from the list is like `squill migrate --only`.
+    status.check_order(std::slice::from_ref(migration), config.out_of_order)?;
+
+    migration
+        .up_with(&mut *conn, &config.migrate_options())
+        .await?;
+    Ok(())
+}
+
+fn state(entry: &StatusEntry) -> &'static str {
+    match (entry.run_at, &entry.directory) {
+        (Some(_), Some(_)) => "applied",
+        (Some(_), None) => "missing files",
+        (None, _) => "pending",
+    }
+}
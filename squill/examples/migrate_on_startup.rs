@@ -0,0 +1,36 @@
+//! Apply pending migrations when an application starts, before it serves any requests.
+//!
+//! ```bash
+//! DATABASE_URL=postgres://localhost/app cargo run --example migrate_on_startup -- migrations
+//! ```
+
+use sqlx::postgres::PgPoolOptions;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let url = std::env::var("DATABASE_URL")?;
+    let migrations_dir = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from("migrations"));
+
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .connect(&url)
+        .await?;
+
+    // Every instance of the app can do this at once. One of them applies the migrations while the
+    // others wait, and then they all continue.
+    let report = squill::run_pending_migrations(&pool, migrations_dir).await?;
+    for applied in &report.applied {
+        let migration = &applied.migration;
+        println!(
+            "Applied {}-{} in {} ms",
+            migration.id, migration.name, applied.duration_ms
+        );
+    }
+
+    // Start the app with the same pool here.
+
+    pool.close().await;
+    Ok(())
+}
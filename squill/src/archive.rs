@@ -0,0 +1,203 @@
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+/// The file name endings that make migrations_dir an archive instead of a directory.
+const ARCHIVE_SUFFIXES: [&str; 4] = [".tar", ".tar.gz", ".tgz", ".zip"];
+
+/// Where a migrations directory setting reads migrations from.
+///
+/// An archive (like a `migrations.tar.gz` baked into a deploy artifact) is unpacked into the
+/// cache dir, so everything that reads a migrations directory works the same way with one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrationSource {
+    /// A directory of migrations.
+    Directory(PathBuf),
+
+    /// A `.tar`, `.tar.gz`, `.tgz`, or `.zip` file with the migrations at its top level.
+    Archive(PathBuf),
+}
+
+impl MigrationSource {
+    /// Pick the kind of source from the path's file name.
+    pub fn new(path: &Path) -> Self {
+        let is_archive = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| ARCHIVE_SUFFIXES.iter().any(|s| name.ends_with(s)));
+
+        if is_archive {
+            Self::Archive(path.to_path_buf())
+        } else {
+            Self::Directory(path.to_path_buf())
+        }
+    }
+
+    /// The directory to read the migrations from, unpacking an archive into the cache dir first.
+    ///
+    /// Each archive is unpacked once per version of its contents, so a new build of the same
+    /// archive gets a fresh copy.
+    pub fn dir(&self, cache_dir: &Path) -> Result<PathBuf, ArchiveError> {
+        match self {
+            Self::Directory(dir) => Ok(dir.clone()),
+            Self::Archive(path) => unpack(path, cache_dir),
+        }
+    }
+}
+
+fn unpack(archive: &Path, cache_dir: &Path) -> Result<PathBuf, ArchiveError> {
+    let content = std::fs::read(archive).map_err(|err| ArchiveError::Read {
+        path: archive.to_path_buf(),
+        err,
+    })?;
+    let digest: String = Sha256::digest(content)
+        .iter()
+        .take(8)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    let archives_cache = cache_dir.join("archives");
+    let dir = archives_cache.join(&digest);
+
+    if dir.is_dir() {
+        return Ok(dir);
+    }
+
+    tracing::info!("Unpacking migrations from {}", archive.to_string_lossy());
+
+    // Work in a scratch directory so a failed unpack doesn't leave a partial copy behind.
+    let partial = archives_cache.join(format!("{digest}.partial"));
+    if partial.exists() {
+        std::fs::remove_dir_all(&partial).map_err(|err| ArchiveError::Cache {
+            path: partial.clone(),
+            err,
+        })?;
+    }
+    std::fs::create_dir_all(&partial).map_err(|err| ArchiveError::Cache {
+        path: partial.clone(),
+        err,
+    })?;
+
+    // The system tools already handle every format (and compression) that matters, so this uses
+    // them instead of reimplementing them.
+    let mut command = if archive.to_string_lossy().ends_with(".zip") {
+        let mut command = std::process::Command::new("unzip");
+        command.arg("-q").arg(archive).arg("-d").arg(&partial);
+        command
+    } else {
+        let mut command = std::process::Command::new("tar");
+        command.arg("-xf").arg(archive).arg("-C").arg(&partial);
+        command
+    };
+
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command.output().map_err(|err| ArchiveError::Spawn {
+        program: program.clone(),
+        err,
+    })?;
+
+    if !output.status.success() {
+        return Err(ArchiveError::Unpack {
+            path: archive.to_path_buf(),
+            program,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        });
+    }
+
+    std::fs::rename(&partial, &dir).map_err(|err| ArchiveError::Cache {
+        path: dir.clone(),
+        err,
+    })?;
+
+    Ok(dir)
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum ArchiveError {
+    #[error("failed to read migrations archive: {path}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+
+    #[error("failed to run {program}")]
+    Spawn {
+        program: String,
+        #[source]
+        err: std::io::Error,
+    },
+
+    #[error("{program} failed to unpack {path}: {stderr}")]
+    Unpack {
+        path: PathBuf,
+        program: String,
+        stderr: String,
+    },
+
+    #[error("failed to update archive cache: {path}")]
+    Cache {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::{CreateMigrationError, MigrationIndex};
+    use crate::status::Status;
+    use crate::testing::*;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn tar_archive() {
+        let env = TestEnv::initialized().await.unwrap();
+        let mut config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let _ = index.create(fake_migration(1, "one")).unwrap();
+
+        let artifact = tempfile::tempdir().unwrap();
+        let archive = artifact.path().join("migrations.tar.gz");
+        let status = std::process::Command::new("tar")
+            .arg("-czf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(&config.migrations_dir)
+            .arg(".")
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        assert_eq!(
+            MigrationSource::Directory(config.migrations_dir.clone()),
+            MigrationSource::new(&config.migrations_dir)
+        );
+        assert_eq!(
+            MigrationSource::Archive(archive.clone()),
+            MigrationSource::new(&archive)
+        );
+
+        config.migrations_dir = archive.clone();
+        config.cache_dir = artifact.path().join("cache");
+
+        let status = Status::new(&config).await.unwrap();
+        let pending: Vec<_> = status.pending().iter().map(|m| m.id.as_i64()).collect();
+        assert_eq!(vec![1], pending);
+
+        // The second read comes straight from the cache.
+        let dir = MigrationSource::new(&archive)
+            .dir(&config.cache_dir)
+            .unwrap();
+        assert_eq!(dir, status.available.dir);
+
+        let mut index = config.migration_index().unwrap();
+        match index.create(fake_migration(2, "two")) {
+            Err(CreateMigrationError::Archive(path)) => assert_eq!(archive, path),
+            res => panic!("Unexpected result: {res:?}"),
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use sqlx::{Executor, PgConnection};
+
+use crate::config::Config;
+use crate::error_chain;
+use crate::migrate::{MigrationDirectory, MigrationId};
+
+/// One attempt to apply a migration.
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq)]
+pub struct AttemptRecord {
+    pub attempt_id: i64,
+    pub id: i64,
+    pub name: String,
+    pub started_at: time::PrimitiveDateTime,
+
+    /// When the attempt finished, or `None` if it's still running (or the runner died).
+    pub finished_at: Option<time::PrimitiveDateTime>,
+    pub success: Option<bool>,
+    pub error: Option<String>,
+}
+
+impl AttemptRecord {
+    pub fn migration_id(&self) -> MigrationId {
+        MigrationId(self.id)
+    }
+}
+
+const CREATE_ATTEMPTS_TABLE: &str = r#"
+create table if not exists schema_migration_attempts (
+    attempt_id bigserial primary key,
+    id bigint not null,
+    name text not null,
+    started_at timestamp not null default clock_timestamp(),
+    finished_at timestamp,
+    success boolean,
+    error text
+)
+"#;
+
+/// Records each attempt to apply a migration in the schema_migration_attempts table.
+///
+/// The records are written over a second connection, so they're kept even when the migration's
+/// transaction rolls back, and an attempt that's still running (or whose runner died) shows up
+/// as unfinished. Failing to write a record is logged instead of failing the migration.
+#[derive(Debug)]
+pub struct AttemptLog {
+    conn: PgConnection,
+}
+
+impl AttemptLog {
+    /// Open the second connection if record_attempts is on, creating the table if it doesn't
+    /// exist yet.
+    pub async fn open(config: &Config) -> Option<Self> {
+        if !config.record_attempts {
+            return None;
+        }
+
+        let opened = async {
+            let mut conn = config.connect().await.map_err(|err| error_chain(&err))?;
+            (&mut conn)
+                .execute(CREATE_ATTEMPTS_TABLE)
+                .await
+                .map_err(|err| error_chain(&err))?;
+            Ok::<_, String>(conn)
+        }
+        .await;
+
+        match opened {
+            Ok(conn) => Some(Self { conn }),
+            Err(err) => {
+                tracing::warn!("not recording attempts: {}", err);
+                None
+            }
+        }
+    }
+
+    /// Record the start of an attempt, returning its ID for [`AttemptLog::finish`].
+    pub async fn start(&mut self, migration: &MigrationDirectory) -> Option<i64> {
+        let started = sqlx::query_scalar(
+            r#"
+            insert into schema_migration_attempts (id, name)
+            values ($1, $2)
+            returning attempt_id
+            "#,
+        )
+        .bind(migration.id.as_i64())
+        .bind(&migration.name)
+        .fetch_one(&mut self.conn)
+        .await;
+
+        match started {
+            Ok(attempt_id) => Some(attempt_id),
+            Err(err) => {
+                tracing::warn!(
+                    id = %migration.id,
+                    "failed to record attempt of migration {}: {}",
+                    migration,
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// Record how an attempt ended, with the error if it failed.
+    pub async fn finish(&mut self, attempt_id: Option<i64>, error: Option<&str>) {
+        let Some(attempt_id) = attempt_id else {
+            return;
+        };
+
+        let finished = sqlx::query(
+            r#"
+            update schema_migration_attempts
+            set finished_at = clock_timestamp(), success = $2, error = $3
+            where attempt_id = $1
+            "#,
+        )
+        .bind(attempt_id)
+        .bind(error.is_none())
+        .bind(error)
+        .execute(&mut self.conn)
+        .await;
+
+        if let Err(err) = finished {
+            tracing::warn!(
+                "failed to record the end of attempt {}: {}",
+                attempt_id,
+                err
+            );
+        }
+    }
+}
+
+/// Load the most recent attempts that didn't succeed (because they failed or never finished),
+/// newest first.
+pub async fn unsuccessful_attempts(
+    conn: &mut PgConnection,
+    limit: i64,
+) -> sqlx::Result<Vec<AttemptRecord>> {
+    let exists: bool =
+        sqlx::query_scalar("select to_regclass('schema_migration_attempts') is not null")
+            .fetch_one(&mut *conn)
+            .await?;
+
+    if !exists {
+        return Ok(Vec::new());
+    }
+
+    sqlx::query_as(
+        r#"
+        select * from schema_migration_attempts
+        where success is not true
+        order by started_at desc, attempt_id desc
+        limit $1
+        "#,
+    )
+    .bind(limit)
+    .fetch_all(conn)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::{MigrationIndex, MigrationParams};
+    use crate::testing::*;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn record_attempts() {
+        let env = TestEnv::initialized().await.unwrap();
+        let mut config = env.config();
+
+        assert!(AttemptLog::open(&config).await.is_none());
+        config.record_attempts = true;
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+        let two = index
+            .create(MigrationParams {
+                id: MigrationId(2),
+                name: String::from("broken"),
+                up_sql: String::from("select * from missing;"),
+                down_sql: None,
+            })
+            .unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        assert!(unsuccessful_attempts(&mut conn, 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        crate::migrate_all(&config).await.unwrap_err();
+
+        let attempts = unsuccessful_attempts(&mut conn, 10).await.unwrap();
+        assert_eq!(1, attempts.len());
+        assert_eq!(two.id, attempts[0].migration_id());
+        assert_eq!(Some(false), attempts[0].success);
+        assert!(attempts[0].finished_at.is_some());
+        assert!(
+            attempts[0].error.as_deref().unwrap().contains("missing"),
+            "{attempts:?}"
+        );
+
+        let succeeded: i64 = sqlx::query_scalar(
+            "select count(*) from schema_migration_attempts where id = $1 and success",
+        )
+        .bind(one.id.as_i64())
+        .fetch_one(&mut conn)
+        .await
+        .unwrap();
+        assert_eq!(1, succeeded);
+    }
+}
@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Iso8601;
+
+use crate::db::MigrationLog;
+use crate::index::MigrationIndex;
+use crate::manifest::{file_digest, ManifestError};
+
+/// One applied migration, as exported for an audit.
+///
+/// This is the export format, so the fields (and their order, which is the CSV column order) only
+/// change in a new major version.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub id: i64,
+    pub name: String,
+
+    /// When the migration was applied, in ISO 8601 without a time zone, as the database stored it.
+    pub run_at: String,
+
+    /// How long the migration took to apply, if the migration log recorded it.
+    pub duration_ms: Option<u64>,
+
+    /// The database user that applied the migration, if the migration log recorded it.
+    pub applied_by: Option<String>,
+
+    /// SHA-256 of the up.sql file as it is now, in hex, or None if the files are gone.
+    pub up_checksum: Option<String>,
+
+    /// SHA-256 of the down.sql file as it is now, in hex, or None if there isn't one.
+    pub down_checksum: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditFormat {
+    Csv,
+    Json,
+}
+
+const CSV_HEADER: [&str; 7] = [
+    "id",
+    "name",
+    "run_at",
+    "duration_ms",
+    "applied_by",
+    "up_checksum",
+    "down_checksum",
+];
+
+/// Build an audit record for every applied migration, in ID order.
+///
+/// The checksums come from the migration files in the index, so an applied migration whose files
+/// were removed has none.
+pub fn audit_records(
+    log: &MigrationLog,
+    index: &MigrationIndex,
+) -> Result<Vec<AuditRecord>, AuditError> {
+    let mut records = Vec::new();
+
+    for record in log.iter() {
+        let migration = index.get(record.id);
+
+        let up_checksum = match migration {
+            Some(m) if m.up_path.exists() => {
+                Some(file_digest(&m.up_path).map_err(AuditError::Digest)?)
+            }
+            _ => None,
+        };
+        let down_checksum = match migration {
+            Some(m) if m.is_reversible() => {
+                Some(file_digest(&m.down_path).map_err(AuditError::Digest)?)
+            }
+            _ => None,
+        };
+
+        records.push(AuditRecord {
+            id: record.id.as_i64(),
+            name: record.name.clone(),
+            run_at: record
+                .run_at
+                .format(&Iso8601::DEFAULT)
+                .map_err(AuditError::Timestamp)?,
+            duration_ms: record
+                .duration
+                .map(|d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX)),
+            applied_by: record.applied_by.clone(),
+            up_checksum,
+            down_checksum,
+        });
+    }
+
+    Ok(records)
+}
+
+/// Write the records as a CSV file (with a header row) or a JSON array.
+pub fn render_audit(records: &[AuditRecord], format: AuditFormat) -> Result<String, AuditError> {
+    match format {
+        AuditFormat::Json => serde_json::to_string_pretty(records)
+            .map(|json| json + "\n")
+            .map_err(AuditError::Serialize),
+        AuditFormat::Csv => {
+            let mut out = CSV_HEADER.join(",");
+            out.push('\n');
+
+            for r in records {
+                let fields = [
+                    r.id.to_string(),
+                    csv_field(&r.name),
+                    csv_field(&r.run_at),
+                    r.duration_ms.map(|ms| ms.to_string()).unwrap_or_default(),
+                    r.applied_by.as_deref().map(csv_field).unwrap_or_default(),
+                    r.up_checksum.clone().unwrap_or_default(),
+                    r.down_checksum.clone().unwrap_or_default(),
+                ];
+                out.push_str(&fields.join(","));
+                out.push('\n');
+            }
+
+            Ok(out)
+        }
+    }
+}
+
+/// Quote a CSV field if it has a comma, quote, or line break (RFC 4180).
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum AuditError {
+    #[error(transparent)]
+    Digest(ManifestError),
+
+    #[error("failed to format timestamp")]
+    Timestamp(#[source] time::error::Format),
+
+    #[error("failed to serialize audit records")]
+    Serialize(#[source] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn export_applied() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = config.migration_index().unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+        let mut params = fake_migration(2, "forward, only");
+        params.down_sql = None;
+        let two = index.create(params).unwrap();
+        let _pending = index.create(fake_migration(3, "three")).unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        one.up(&mut conn).await.unwrap();
+        two.up(&mut conn).await.unwrap();
+
+        let log = MigrationLog::new(&mut conn).await.unwrap();
+        let records = audit_records(&log, &index).unwrap();
+
+        let ids: Vec<_> = records.iter().map(|r| r.id).collect();
+        assert_eq!(vec![0, 1, 2], ids);
+        assert_eq!(
+            Some(file_digest(&one.up_path).unwrap()),
+            records[1].up_checksum
+        );
+        assert!(records[1].down_checksum.is_some());
+        assert_eq!(None, records[2].down_checksum);
+
+        let csv = render_audit(&records, AuditFormat::Csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(CSV_HEADER.join(","), lines[0]);
+        assert_eq!(4, lines.len(), "{csv}");
+        assert!(lines[3].starts_with("2,\"forward, only\","), "{csv}");
+
+        let json = render_audit(&records, AuditFormat::Json).unwrap();
+        let parsed: Vec<AuditRecord> = serde_json::from_str(&json).unwrap();
+        assert_eq!(records, parsed);
+
+        // Records don't depend on the files still being there.
+        std::fs::remove_dir_all(&one.dir).unwrap();
+        let index = config.migration_index().unwrap();
+        let records = audit_records(&log, &index).unwrap();
+        assert_eq!(None, records[1].up_checksum);
+        assert_eq!(1, records[1].id);
+    }
+}
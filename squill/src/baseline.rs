@@ -0,0 +1,143 @@
+use sqlx::{Acquire, Postgres};
+
+use crate::db::{MigrationLog, MigrationRecord, QueryError};
+use crate::index::MigrationIndex;
+use crate::migrate::{claim, set_applied_with, MigrateError, MigrationDirectory, MigrationId};
+
+/// The pending migrations that [`baseline`] would mark as applied.
+pub fn baseline_targets(
+    log: &MigrationLog,
+    index: &MigrationIndex,
+    up_to: MigrationId,
+) -> Vec<MigrationDirectory> {
+    index
+        .iter()
+        .filter(|m| m.id <= up_to && log.get(m.id).is_none())
+        .cloned()
+        .collect()
+}
+
+/// Mark every pending migration up to (and including) this ID as applied without running it.
+///
+/// Use this when adopting Squill for a database whose schema already matches those migrations.
+/// The init migration is the exception: if it hasn't been applied, it's run for real first,
+/// since the migration log can't exist without it.
+///
+/// This returns the records for the migrations that were marked.
+pub async fn baseline<'c, A>(
+    conn: A,
+    index: &MigrationIndex,
+    up_to: MigrationId,
+) -> Result<Vec<MigrationRecord>, BaselineError>
+where
+    A: Acquire<'c, Database = Postgres>,
+{
+    let mut conn = conn.acquire().await.map_err(BaselineError::Execute)?;
+
+    let log = MigrationLog::new(&mut *conn)
+        .await
+        .map_err(BaselineError::Query)?;
+    let targets = baseline_targets(&log, index, up_to);
+
+    mark_applied(&mut *conn, &targets).await
+}
+
+/// Mark these migrations as applied without running them, except for the init migration.
+///
+/// The init migration is run for real if it's one of the targets, since the migration log can't
+/// exist without it. The rest are claimed together in one transaction.
+///
+/// This returns the records for the migrations that were marked.
+pub async fn mark_applied<'c, A>(
+    conn: A,
+    targets: &[MigrationDirectory],
+) -> Result<Vec<MigrationRecord>, BaselineError>
+where
+    A: Acquire<'c, Database = Postgres>,
+{
+    let mut conn = conn.acquire().await.map_err(BaselineError::Execute)?;
+
+    // The init migration manages its own transaction, so it has to run separately.
+    if let Some(init) = targets.iter().find(|m| m.id == MigrationId(0)) {
+        tracing::info!("Running init migration before baseline: {}", init);
+        init.up(&mut *conn).await.map_err(BaselineError::Migrate)?;
+    }
+
+    let mut tx = (&mut *conn).begin().await.map_err(BaselineError::Execute)?;
+    set_applied_with(&mut tx, true)
+        .await
+        .map_err(BaselineError::Execute)?;
+
+    for migration in targets.iter().filter(|m| m.id != MigrationId(0)) {
+        tracing::info!("Marking migration as applied: {}", migration);
+        claim(&mut *tx, migration.id, &migration.name)
+            .await
+            .map_err(BaselineError::Execute)?;
+    }
+
+    tx.commit().await.map_err(BaselineError::Execute)?;
+
+    let log = MigrationLog::new(&mut *conn)
+        .await
+        .map_err(BaselineError::Query)?;
+
+    Ok(targets
+        .iter()
+        .filter_map(|m| log.get(m.id).cloned())
+        .collect())
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum BaselineError {
+    #[error(transparent)]
+    Query(QueryError),
+
+    #[error("failed to mark migrations as applied")]
+    Execute(#[source] sqlx::Error),
+
+    #[error(transparent)]
+    Migrate(MigrateError),
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::Executor;
+
+    use crate::status::Status;
+    use crate::testing::*;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn existing_database() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+
+        crate::create_init_migration(&config).unwrap();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let _ = index.create(fake_migration(1, "one")).unwrap();
+        let _ = index.create(fake_migration(2, "two")).unwrap();
+        let _ = index.create(fake_migration(3, "three")).unwrap();
+
+        // The database already has the schema from the first two migrations.
+        let mut conn = config.connect().await.unwrap();
+        conn.execute("create table tbl_one (id_1 int); create table tbl_two (id_2 int);")
+            .await
+            .unwrap();
+
+        let claimed = baseline(&mut conn, &index, MigrationId(2)).await.unwrap();
+
+        let ids: Vec<_> = claimed.iter().map(|r| r.id.as_i64()).collect();
+        assert_eq!(vec![0, 1, 2], ids);
+
+        let status = Status::new(&config).await.unwrap();
+        let pending: Vec<_> = status.pending().iter().map(|m| m.id.as_i64()).collect();
+        assert_eq!(vec![3], pending);
+
+        // Running it again has nothing left to do.
+        let claimed = baseline(&mut conn, &index, MigrationId(2)).await.unwrap();
+        assert!(claimed.is_empty(), "{claimed:?}");
+    }
+}
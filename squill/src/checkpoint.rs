@@ -0,0 +1,177 @@
+use sha2::{Digest, Sha256};
+use sqlx::{Executor, PgConnection};
+
+use crate::migrate::MigrationId;
+
+/// A statement of a no-transaction migration that finished running.
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// The statement's position in the file, starting from 0.
+    pub statement: i32,
+
+    /// A digest of the statement, so a resumed run can tell if the file changed.
+    pub checksum: String,
+
+    pub completed_at: time::PrimitiveDateTime,
+}
+
+const CREATE_CHECKPOINTS_TABLE: &str = r#"
+create table if not exists schema_migration_checkpoints (
+    id bigint not null,
+    statement integer not null,
+    checksum text not null,
+    completed_at timestamp not null default current_timestamp,
+    primary key (id, statement)
+)
+"#;
+
+pub fn statement_checksum(sql: &str) -> String {
+    let digest = Sha256::digest(sql.trim());
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Record that this statement of the migration finished.
+///
+/// The init migration creates the schema_migration_checkpoints table. Migration logs created
+/// before that get it the first time a checkpoint is saved.
+pub async fn save_checkpoint(
+    conn: &mut PgConnection,
+    id: MigrationId,
+    statement: usize,
+    sql: &str,
+) -> sqlx::Result<()> {
+    (&mut *conn).execute(CREATE_CHECKPOINTS_TABLE).await?;
+
+    let query = sqlx::query(
+        r#"
+        insert into schema_migration_checkpoints (id, statement, checksum)
+        values ($1, $2, $3)
+        on conflict (id, statement) do update set
+            checksum = excluded.checksum,
+            completed_at = current_timestamp
+        "#,
+    )
+    .bind(id.as_i64())
+    .bind(statement as i32)
+    .bind(statement_checksum(sql));
+
+    conn.execute(query).await?;
+    Ok(())
+}
+
+/// Load the finished statements of the migration from an earlier run, in file order.
+pub async fn load_checkpoints(
+    conn: &mut PgConnection,
+    id: MigrationId,
+) -> sqlx::Result<Vec<Checkpoint>> {
+    if !checkpoints_exist(conn).await? {
+        return Ok(Vec::new());
+    }
+
+    sqlx::query_as(
+        r#"
+        select statement, checksum, completed_at
+        from schema_migration_checkpoints
+        where id = $1
+        order by statement
+        "#,
+    )
+    .bind(id.as_i64())
+    .fetch_all(conn)
+    .await
+}
+
+/// Forget the progress of the migration, after it finishes or before starting it over.
+pub async fn clear_checkpoints(conn: &mut PgConnection, id: MigrationId) -> sqlx::Result<()> {
+    if !checkpoints_exist(conn).await? {
+        return Ok(());
+    }
+
+    let query =
+        sqlx::query("delete from schema_migration_checkpoints where id = $1").bind(id.as_i64());
+
+    conn.execute(query).await?;
+    Ok(())
+}
+
+/// The migrations with progress saved from a run that didn't finish, in ID order.
+pub async fn checkpointed_migrations(conn: &mut PgConnection) -> sqlx::Result<Vec<MigrationId>> {
+    if !checkpoints_exist(conn).await? {
+        return Ok(Vec::new());
+    }
+
+    let ids: Vec<i64> =
+        sqlx::query_scalar("select distinct id from schema_migration_checkpoints order by id")
+            .fetch_all(conn)
+            .await?;
+
+    Ok(ids.into_iter().map(MigrationId).collect())
+}
+
+async fn checkpoints_exist(conn: &mut PgConnection) -> sqlx::Result<bool> {
+    let exists: (bool,) =
+        sqlx::query_as("select to_regclass('schema_migration_checkpoints') is not null")
+            .fetch_one(&mut *conn)
+            .await?;
+
+    Ok(exists.0)
+}
+
+/// How many statements at the start of the file can be skipped, given the checkpoints from an
+/// earlier run.
+///
+/// This returns the position of the first statement that doesn't match its checkpoint, if the
+/// file changed since then.
+pub fn resume_point(statements: &[&str], checkpoints: &[Checkpoint]) -> Result<usize, usize> {
+    let mut done = 0;
+
+    for checkpoint in checkpoints {
+        // Only an unbroken run of finished statements can be skipped.
+        if checkpoint.statement as usize != done {
+            break;
+        }
+
+        match statements.get(done) {
+            Some(sql) if statement_checksum(sql) == checkpoint.checksum => done += 1,
+            _ => return Err(done),
+        }
+    }
+
+    Ok(done)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkpoint(statement: i32, sql: &str) -> Checkpoint {
+        Checkpoint {
+            statement,
+            checksum: statement_checksum(sql),
+            completed_at: time::macros::datetime!(2024-01-01 0:00),
+        }
+    }
+
+    #[test]
+    fn resume_points() {
+        let statements = ["create index concurrently a on t (a);", "create index b;"];
+
+        assert_eq!(Ok(0), resume_point(&statements, &[]));
+        assert_eq!(
+            Ok(1),
+            resume_point(&statements, &[checkpoint(0, statements[0])])
+        );
+
+        // A gap means the later checkpoint can't be trusted.
+        assert_eq!(
+            Ok(0),
+            resume_point(&statements, &[checkpoint(1, statements[1])])
+        );
+
+        // The first statement was edited since it ran.
+        assert_eq!(
+            Err(0),
+            resume_point(&statements, &[checkpoint(0, "create index a;")])
+        );
+    }
+}
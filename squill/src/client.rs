@@ -0,0 +1,382 @@
+use std::path::PathBuf;
+use std::time::Instant;
+
+use sqlx::postgres::{PgConnectOptions, PgConnection};
+
+use crate::config::{Config, ConnectError};
+use crate::db::{MigrationLog, MigrationRecord};
+use crate::events::{MigrationEvent, MigrationObserver};
+use crate::hooks::Hooks;
+use crate::index::{IndexError, MigrationIndex};
+use crate::lint::check_lint;
+use crate::migrate::{Direction, MigrateError, MigrationDirectory, MigrationId};
+use crate::report::MigrateReport;
+use crate::status::{save_state, Status, StatusError};
+use crate::{
+    apply_pending, new_migration_params, with_remaining, ApplyOptions, MigrateAllError,
+    NewMigrationError,
+};
+
+/// A long-lived handle for applications that embed Squill.
+///
+/// Unlike the free functions in this crate, this keeps the migration index and database
+/// connection around between calls instead of rebuilding them every time.
+pub struct Squill {
+    config: Config,
+    index: MigrationIndex,
+    conn: Option<PgConnection>,
+}
+
+impl Squill {
+    pub fn builder() -> SquillBuilder {
+        SquillBuilder::default()
+    }
+
+    pub fn new(config: Config) -> Result<Self, IndexError> {
+        let index = config.migration_index()?;
+
+        Ok(Self {
+            config,
+            index,
+            conn: None,
+        })
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn index(&self) -> &MigrationIndex {
+        &self.index
+    }
+
+    /// Re-read the migrations directory to pick up changes made outside of this client.
+    pub fn refresh(&mut self) -> Result<(), IndexError> {
+        self.index = self.config.migration_index()?;
+        Ok(())
+    }
+
+    async fn conn(&mut self) -> Result<&mut PgConnection, ConnectError> {
+        if self.conn.is_none() {
+            self.conn = Some(self.config.connect().await?);
+        }
+
+        Ok(self.conn.as_mut().expect("connection was just set"))
+    }
+
+    pub async fn status(&mut self) -> Result<Status, StatusError> {
+        let conn = self.conn().await.map_err(StatusError::Connect)?;
+
+        let applied = MigrationLog::new(conn).await.map_err(StatusError::Query)?;
+        save_state(&self.config, &applied);
+
+        Ok(Status {
+            applied,
+            available: self.index.clone(),
+        })
+    }
+
+    pub async fn migrate(&mut self) -> Result<MigrateReport, MigrateAllError> {
+        self.migrate_next(usize::MAX).await
+    }
+
+    /// Apply only the next `count` pending migrations. The report lists the rest in `remaining`.
+    pub async fn migrate_next(&mut self, count: usize) -> Result<MigrateReport, MigrateAllError> {
+        let status = self.status().await.map_err(MigrateAllError::Status)?;
+
+        let mut pending = status.pending();
+        let later = pending.split_off(count.min(pending.len()));
+        status
+            .check_order(&pending, self.config.out_of_order)
+            .map_err(MigrateAllError::OutOfOrder)?;
+
+        check_lint(&pending, self.config.lint).map_err(MigrateAllError::Lint)?;
+
+        let config = self.config.clone();
+        let conn = self.conn().await.map_err(MigrateAllError::Connect)?;
+
+        let result = apply_pending(&config, conn, pending, &ApplyOptions::default()).await;
+        with_remaining(result, &later)
+    }
+
+    /// Run the down migration for the most recently applied migration.
+    pub async fn undo(&mut self) -> Result<MigrationDirectory, UndoError> {
+        let status = self.status().await.map_err(UndoError::Status)?;
+
+        let Some(record) = status.applied.last() else {
+            return Err(UndoError::NothingApplied);
+        };
+
+        let Some(migration) = self.index.get(record.id).cloned() else {
+            return Err(UndoError::MissingFiles(record));
+        };
+
+        let opts = self.config.migrate_options();
+        let observers = self.config.observers.clone();
+        let conn = self.conn().await.map_err(UndoError::Connect)?;
+
+        observers.emit(MigrationEvent::Started {
+            migration: &migration,
+            direction: Direction::Down,
+        });
+
+        let started = Instant::now();
+        if let Err(err) = migration.down_with(conn, &opts).await {
+            observers.emit(MigrationEvent::Failed {
+                migration: &migration,
+                direction: Direction::Down,
+                error: &err,
+            });
+            return Err(UndoError::Migrate(err));
+        }
+
+        observers.emit(MigrationEvent::Finished {
+            migration: &migration,
+            direction: Direction::Down,
+            duration: started.elapsed(),
+        });
+
+        Ok(migration)
+    }
+
+    pub fn new_migration(
+        &mut self,
+        template: Option<impl Into<String>>,
+        id: MigrationId,
+        name: impl AsRef<str>,
+    ) -> Result<MigrationDirectory, NewMigrationError> {
+        let params = new_migration_params(&self.config, template, id, name)?;
+
+        self.index.create(params).map_err(NewMigrationError::Create)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum UndoError {
+    #[error(transparent)]
+    Status(StatusError),
+
+    #[error(transparent)]
+    Connect(ConnectError),
+
+    #[error("no migration to undo")]
+    NothingApplied,
+
+    #[error("could not find files for migration ID {} ({})", .0.id, .0.name)]
+    MissingFiles(MigrationRecord),
+
+    #[error(transparent)]
+    Migrate(MigrateError),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SquillBuilder {
+    config: Config,
+}
+
+impl From<Config> for SquillBuilder {
+    fn from(config: Config) -> Self {
+        Self { config }
+    }
+}
+
+impl SquillBuilder {
+    pub fn database_connect_options(mut self, opts: PgConnectOptions) -> Self {
+        self.config.database_connect_options = Some(opts);
+        self
+    }
+
+    pub fn migrations_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.migrations_dir = dir.into();
+        self
+    }
+
+    pub fn templates_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.templates_dir = Some(dir.into());
+        self
+    }
+
+    pub fn only_up(mut self, only_up: bool) -> Self {
+        self.config.only_up = only_up;
+        self
+    }
+
+    pub fn state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.state_file = Some(path.into());
+        self
+    }
+
+    pub fn auto_no_transaction(mut self, auto_no_transaction: bool) -> Self {
+        self.config.auto_no_transaction = auto_no_transaction;
+        self
+    }
+
+    pub fn hooks(mut self, hooks: Hooks) -> Self {
+        self.config.hooks = hooks;
+        self
+    }
+
+    /// Call this observer as each migration starts, finishes, fails, or is skipped.
+    pub fn observer(mut self, observer: impl MigrationObserver + 'static) -> Self {
+        self.config.observers.add(observer);
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn build(self) -> Result<Squill, IndexError> {
+        Squill::new(self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::MigrationParams;
+    use crate::lint::LintPolicy;
+    use crate::testing::*;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn builder_config() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+
+        let squill = Squill::builder()
+            .database_connect_options(config.database_connect_options.clone().unwrap())
+            .migrations_dir(&config.migrations_dir)
+            .only_up(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(config.migrations_dir, squill.config().migrations_dir);
+        assert_eq!(None, squill.config().templates_dir);
+        assert!(squill.config().only_up);
+    }
+
+    #[tokio::test]
+    async fn migrate_and_undo() {
+        let env = TestEnv::initialized().await.unwrap();
+        let mut config = env.config();
+        config.only_up = false;
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let _ = index.create(fake_migration(1, "one")).unwrap();
+        let _ = index.create(fake_migration(2, "two")).unwrap();
+
+        let mut squill = Squill::new(config).unwrap();
+
+        let report = squill.migrate().await.unwrap();
+        assert_eq!(vec![MigrationId(1), MigrationId(2)], report.applied_ids());
+
+        let status = squill.status().await.unwrap();
+        assert!(status.pending().is_empty());
+
+        let undone = squill.undo().await.unwrap();
+        assert_eq!(MigrationId(2), undone.id);
+
+        let status = squill.status().await.unwrap();
+        assert_eq!(vec![undone], status.pending());
+    }
+
+    #[tokio::test]
+    async fn migrate_lint_error() {
+        let env = TestEnv::initialized().await.unwrap();
+        let mut config = env.config();
+        config.lint = LintPolicy::Error;
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let _ = index
+            .create(MigrationParams {
+                id: MigrationId(1),
+                name: String::from("blocking_index"),
+                // Lint fails the run first, so the table doesn't have to exist.
+                up_sql: String::from("create index on users (email);"),
+                down_sql: None,
+            })
+            .unwrap();
+
+        let mut squill = Squill::new(config).unwrap();
+
+        let err = squill.migrate().await.unwrap_err();
+        assert!(matches!(err, MigrateAllError::Lint(_)), "{err:?}");
+
+        let status = squill.status().await.unwrap();
+        assert_eq!(1, status.pending().len());
+    }
+
+    #[tokio::test]
+    async fn observer_events() {
+        let env = TestEnv::initialized().await.unwrap();
+        let mut config = env.config();
+        config.only_up = false;
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let _ = index.create(fake_migration(1, "one")).unwrap();
+
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = events.clone();
+
+        let mut squill = SquillBuilder::from(config)
+            .observer(move |event: &MigrationEvent| {
+                let kind = match event {
+                    MigrationEvent::Started { direction, .. } => format!("started {direction:?}"),
+                    MigrationEvent::Finished { direction, .. } => format!("finished {direction:?}"),
+                    MigrationEvent::Failed { direction, .. } => format!("failed {direction:?}"),
+                    MigrationEvent::Skipped { .. } => String::from("skipped"),
+                };
+                let id = event.migration().id.as_i64();
+                seen.lock().unwrap().push(format!("{kind} {id}"));
+            })
+            .build()
+            .unwrap();
+
+        squill.migrate().await.unwrap();
+        squill.undo().await.unwrap();
+
+        assert_eq!(
+            vec![
+                "started Up 1",
+                "finished Up 1",
+                "started Down 1",
+                "finished Down 1",
+            ],
+            *events.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn undo_only_up() {
+        let env = TestEnv::initialized().await.unwrap();
+
+        let mut squill = Squill::new(env.config()).unwrap();
+
+        match squill.undo().await {
+            Err(UndoError::Migrate(MigrateError::OnlyUp)) => (),
+
+            Err(err) => panic!("Unexpected error: {:?}", err),
+            Ok(m) => panic!("Unexpected success: {m}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn new_migration_updates_index() {
+        let env = TestEnv::initialized().await.unwrap();
+
+        let mut squill = Squill::new(env.config()).unwrap();
+        assert!(squill.index().get(MigrationId(123)).is_none());
+
+        squill
+            .new_migration(None::<String>, MigrationId(123), "create_users")
+            .unwrap();
+
+        let migration = squill.index().get(MigrationId(123)).unwrap();
+        assert_eq!("create_users", &migration.name);
+
+        let status = squill.status().await.unwrap();
+        assert_eq!(1, status.pending().len());
+    }
+}
@@ -1,39 +1,448 @@
+use std::collections::BTreeMap;
+use std::future::Future;
 use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
-use sqlx::{postgres::PgConnectOptions, ConnectOptions, PgConnection};
+use regex::Regex;
+use sqlx::{postgres::PgConnectOptions, ConnectOptions, PgConnection, PgPool};
+
+use crate::archive::MigrationSource;
+use crate::events::Observers;
+use crate::hooks::Hooks;
+use crate::index::{IdStrategy, IndexError, MigrationIndex};
+use crate::lint::LintPolicy;
+use crate::migrate::MigrateOptions;
+use crate::naming::MigrationNaming;
+use crate::notify::NotificationTarget;
+use crate::remote::TemplateRepo;
+use crate::status::OutOfOrderPolicy;
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub database_connect_options: Option<PgConnectOptions>,
 
     pub migrations_dir: PathBuf,
+
+    /// More directories to read migrations from, in addition to migrations_dir. New migrations
+    /// are always created in migrations_dir.
+    pub extra_migrations_dirs: Vec<PathBuf>,
+
     pub templates_dir: Option<PathBuf>,
 
+    /// A git repository to load shared templates from.
+    pub templates_repo: Option<TemplateRepo>,
+
+    /// Where to keep downloaded files, like templates_repo checkouts.
+    pub cache_dir: PathBuf,
+
     /// Only allow up migrations to run.
     pub only_up: bool,
+
+    /// Where to cache the last known migration log for offline status checks.
+    pub state_file: Option<PathBuf>,
+
+    /// Run migrations that can't be in a transaction without requiring the no-transaction
+    /// directive.
+    pub auto_no_transaction: bool,
+
+    /// Extra steps to run before and after applying migrations.
+    pub hooks: Hooks,
+
+    /// Command to open new migration files with, instead of $VISUAL or $EDITOR.
+    pub editor: Option<String>,
+
+    /// Record server statistics (rows and buffers) for each applied migration.
+    pub collect_stats: bool,
+
+    /// Where `squill schema dump` writes the schema (and where migrate keeps it up to date).
+    pub schema_file: Option<PathBuf>,
+
+    /// Where to send a summary after migrating (or failing to).
+    pub notifications: Vec<NotificationTarget>,
+
+    /// The active environment, like "staging" or "production". Notification targets can be limited
+    /// to certain profiles.
+    pub profile: Option<String>,
+
+    /// Extra regular expressions to scrub from output, in addition to database passwords.
+    pub redact_patterns: Vec<String>,
+
+    /// How `squill new` picks an ID when one isn't given.
+    pub id_strategy: IdStrategy,
+
+    /// What to do about pending migrations that are older than an applied one.
+    pub out_of_order: OutOfOrderPolicy,
+
+    /// Record the host, user, and CI job that applied each migration.
+    pub record_metadata: bool,
+
+    /// How many times to retry connecting when the database isn't reachable yet.
+    pub connect_retries: u32,
+
+    /// How long to wait before the first connection retry. The wait doubles after each attempt.
+    pub connect_backoff: Duration,
+
+    /// Keep retrying failed connections until this much time has passed, instead of stopping
+    /// after connect_retries.
+    pub connect_wait: Option<Duration>,
+
+    /// Record each failed migration's error in the schema_migration_failures table.
+    pub record_failures: bool,
+
+    /// Extra variables for migration templates, like a schema name or owner role.
+    pub template_vars: BTreeMap<String, String>,
+
+    /// How many digits `squill align-ids` pads IDs to. Without this, it uses the widest existing ID.
+    pub id_width: Option<usize>,
+
+    /// Skip (with a warning) migrations that another runner applied while this one was running,
+    /// instead of failing with an AlreadyApplied error.
+    pub skip_applied: bool,
+
+    /// Generate the down file for new migrations by reversing the up file, when it only makes
+    /// simple changes (see [`invert`](crate::invert::invert)).
+    pub auto_down: bool,
+
+    /// Whether `squill migrate` lints pending migrations first, and whether findings stop it.
+    pub lint: LintPolicy,
+
+    /// Called as each migration starts, finishes, fails, or is skipped. These can only be set from
+    /// code, not from squill.toml.
+    pub observers: Observers,
+
+    /// Named databases for `squill migrate --all-databases`, like one per tenant. Each one gets the
+    /// same migrations.
+    pub databases: BTreeMap<String, PgConnectOptions>,
+
+    /// The schema to set as the search_path on every connection, so migrations (and the migration
+    /// log) go there instead of public. Templates can use it as `{{ schema }}`.
+    pub schema: Option<String>,
+
+    /// Don't write a down file for new migrations, so they're forward-only.
+    pub no_down: bool,
+
+    /// How migration directories are named, like `{id}-{name}` (the default) or `V{id}__{name}`.
+    pub naming: MigrationNaming,
+
+    /// Cancel any migration statement that runs longer than this.
+    pub statement_timeout: Option<Duration>,
+
+    /// Stop waiting for a lock in a migration after this long.
+    pub lock_timeout: Option<Duration>,
+
+    /// Require confirmation before running down migrations, like for a production database.
+    pub protected: bool,
+
+    /// Regular expressions for databases that are protected even without `protected`. Each one
+    /// is matched against the database's `host:port/name`.
+    pub protected_patterns: Vec<String>,
+
+    /// Record each attempt to apply a migration in the schema_migration_attempts table, over a
+    /// second connection so failed attempts are kept.
+    pub record_attempts: bool,
+
+    /// Refuse to use a connection unless `current_database()` is this, so a copy-pasted
+    /// DATABASE_URL can't point squill at the wrong database.
+    pub expected_database_name: Option<String>,
+
+    /// Refuse to use a connection unless the server's system identifier (from
+    /// `pg_control_system()`) is this, like expected_database_name for the whole server.
+    pub expected_server_fingerprint: Option<String>,
+
+    /// The author name that new migration templates get as `{{ author }}`. Without this, it's
+    /// `git config user.name`.
+    pub author: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            database_connect_options: None,
+            // Same as the CLI default
+            migrations_dir: PathBuf::from("migrations"),
+            extra_migrations_dirs: Vec::new(),
+            templates_dir: None,
+            templates_repo: None,
+            cache_dir: PathBuf::from(".squill/cache"),
+            only_up: false,
+            state_file: None,
+            auto_no_transaction: false,
+            hooks: Hooks::default(),
+            editor: None,
+            collect_stats: false,
+            schema_file: None,
+            notifications: Vec::new(),
+            profile: None,
+            redact_patterns: Vec::new(),
+            id_strategy: IdStrategy::default(),
+            out_of_order: OutOfOrderPolicy::default(),
+            record_metadata: false,
+            connect_retries: 0,
+            connect_backoff: Duration::from_millis(500),
+            connect_wait: None,
+            record_failures: false,
+            template_vars: BTreeMap::new(),
+            id_width: None,
+            skip_applied: false,
+            auto_down: false,
+            lint: LintPolicy::Off,
+            observers: Observers::default(),
+            databases: BTreeMap::new(),
+            schema: None,
+            no_down: false,
+            naming: MigrationNaming::default(),
+            statement_timeout: None,
+            lock_timeout: None,
+            protected: false,
+            protected_patterns: Vec::new(),
+            record_attempts: false,
+            expected_database_name: None,
+            expected_server_fingerprint: None,
+            author: None,
+        }
+    }
 }
 
 impl Config {
+    pub fn migrate_options(&self) -> MigrateOptions {
+        MigrateOptions {
+            only_up: self.only_up,
+            auto_no_transaction: self.auto_no_transaction,
+            progress: None,
+            collect_stats: self.collect_stats,
+            record_metadata: self.record_metadata,
+            resume: false,
+            statement_timeout: self.statement_timeout,
+            lock_timeout: self.lock_timeout,
+            profile: self.profile.clone(),
+        }
+    }
+
+    /// Read every configured migrations directory into one index.
+    ///
+    /// Any of them can be an archive instead, which is unpacked into the cache dir. The index
+    /// can't create new migrations in an archive.
+    pub fn migration_index(&self) -> Result<MigrationIndex, IndexError> {
+        let unpack = |path: &PathBuf| {
+            MigrationSource::new(path)
+                .dir(&self.cache_dir)
+                .map_err(IndexError::Archive)
+        };
+
+        let primary = unpack(&self.migrations_dir)?;
+        let extra = self
+            .extra_migrations_dirs
+            .iter()
+            .map(unpack)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut index = MigrationIndex::from_dirs_named(&primary, &extra, &self.naming)?;
+        if let MigrationSource::Archive(archive) = MigrationSource::new(&self.migrations_dir) {
+            index.archive = Some(archive);
+        }
+
+        Ok(index)
+    }
+
+    /// Connect to the database, retrying transient failures according to connect_retries,
+    /// connect_backoff, and connect_wait.
+    ///
+    /// The connection is checked against expected_database_name and expected_server_fingerprint.
     pub async fn connect(&self) -> Result<PgConnection, ConnectError> {
-        if let Some(opts) = &self.database_connect_options {
-            opts.connect().await.map_err(ConnectError::Connect)
+        if let Some(opts) = &self.connect_options() {
+            let mut conn = self.retry(|| opts.connect()).await?;
+            self.check_identity(&mut conn).await?;
+            Ok(conn)
         } else {
             Err(ConnectError::NotConfigured)
         }
     }
+
+    /// Connect to the database with a connection pool instead of a single connection.
+    pub async fn pool(&self) -> Result<PgPool, ConnectError> {
+        if let Some(opts) = &self.connect_options() {
+            let pool = self.retry(|| PgPool::connect_with(opts.clone())).await?;
+
+            // Every connection in the pool goes to the same place, so checking one is enough.
+            let mut conn = pool.acquire().await.map_err(ConnectError::Connect)?;
+            self.check_identity(&mut conn).await?;
+            drop(conn);
+
+            Ok(pool)
+        } else {
+            Err(ConnectError::NotConfigured)
+        }
+    }
+
+    /// Check that this connection goes to the expected database (and server), if either one is
+    /// configured.
+    pub async fn check_identity(&self, conn: &mut PgConnection) -> Result<(), ConnectError> {
+        if let Some(expected) = &self.expected_database_name {
+            let actual: String = sqlx::query_scalar("select current_database()")
+                .fetch_one(&mut *conn)
+                .await
+                .map_err(ConnectError::Identify)?;
+
+            if &actual != expected {
+                return Err(ConnectError::WrongDatabase {
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+
+        if let Some(expected) = &self.expected_server_fingerprint {
+            let actual: String =
+                sqlx::query_scalar("select system_identifier::text from pg_control_system()")
+                    .fetch_one(&mut *conn)
+                    .await
+                    .map_err(ConnectError::Identify)?;
+
+            if &actual != expected {
+                return Err(ConnectError::WrongServer {
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The name of the database, if it's protected by `protected` or `protected_patterns`.
+    pub fn protected_database(&self) -> Result<Option<String>, ProtectedError> {
+        let Some(opts) = &self.database_connect_options else {
+            return Ok(None);
+        };
+
+        // Postgres uses the user name when no database is given.
+        let name = opts.get_database().unwrap_or(opts.get_username());
+        let target = format!("{}:{}/{}", opts.get_host(), opts.get_port(), name);
+
+        let mut protected = self.protected;
+        for pattern in &self.protected_patterns {
+            let re = Regex::new(pattern).map_err(|err| ProtectedError::Pattern {
+                pattern: pattern.clone(),
+                err,
+            })?;
+            protected |= re.is_match(&target);
+        }
+
+        Ok(protected.then(|| name.to_owned()))
+    }
+
+    /// Check that a down migration is allowed. A protected database needs its name as the
+    /// confirmation, so that nobody undoes a migration in production by accident.
+    pub fn check_protected(&self, confirmation: Option<&str>) -> Result<(), ProtectedError> {
+        match self.protected_database()? {
+            Some(database) if confirmation != Some(database.as_str()) => {
+                Err(ProtectedError::NotConfirmed { database })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn connect_options(&self) -> Option<PgConnectOptions> {
+        let opts = self.database_connect_options.clone()?;
+
+        // Keep public after the schema, so extensions installed there (and their functions) still
+        // resolve without being schema-qualified. New objects still go in the schema. The name is
+        // quoted like the init migration's `create schema`, so it's used exactly as written. There's
+        // no space after the comma, since spaces separate the startup options.
+        match &self.schema {
+            Some(schema) => {
+                let search_path = format!("\"{}\",public", schema.replace('"', "\"\""));
+                Some(opts.options([("search_path", search_path)]))
+            }
+            None => Some(opts),
+        }
+    }
+
+    async fn retry<T, F, Fut>(&self, mut connect: F) -> Result<T, ConnectError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = sqlx::Result<T>>,
+    {
+        let started = Instant::now();
+        let mut delay = self.connect_backoff;
+        let mut attempts = 0;
+
+        loop {
+            let err = match connect().await {
+                Ok(conn) => return Ok(conn),
+                Err(err) => err,
+            };
+
+            let retry = match self.connect_wait {
+                Some(wait) => started.elapsed() + delay <= wait,
+                None => attempts < self.connect_retries,
+            };
+
+            if !retry || !is_transient(&err) {
+                return Err(ConnectError::Connect(err));
+            }
+
+            tracing::warn!("Database isn't ready ({}), retrying in {:?}", err, delay);
+            futures_timer::Delay::new(delay).await;
+
+            attempts += 1;
+            delay = (delay * 2).min(MAX_BACKOFF);
+        }
+    }
+}
+
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Whether this looks like the database isn't up yet, as opposed to a problem retrying won't fix
+/// (like a bad password).
+fn is_transient(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut => true,
+
+        // cannot_connect_now: the server is starting up or shutting down.
+        sqlx::Error::Database(db_err) => db_err.code().as_deref() == Some("57P03"),
+
+        _ => false,
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum ConnectError {
     #[error("no database configured")]
     NotConfigured,
 
-    #[error("failed to connect to database: {0}")]
-    Connect(sqlx::Error),
+    #[error("failed to connect to database")]
+    Connect(#[source] sqlx::Error),
+
+    #[error("failed to check which database this is")]
+    Identify(#[source] sqlx::Error),
+
+    #[error("connected to database {actual:?}, but expected_database_name is {expected:?}")]
+    WrongDatabase { expected: String, actual: String },
+
+    #[error("connected to server {actual}, but expected_server_fingerprint is {expected}")]
+    WrongServer { expected: String, actual: String },
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum ProtectedError {
+    #[error("invalid protected_patterns entry: {pattern:?}")]
+    Pattern {
+        pattern: String,
+        #[source]
+        err: regex::Error,
+    },
+
+    #[error("{database} is a protected database: confirm with its name to run down migrations")]
+    NotConfirmed { database: String },
 }
 
 #[cfg(test)]
 mod tests {
+    use sqlx::Executor;
+
     use crate::testing::*;
 
     use super::*;
@@ -47,6 +456,83 @@ mod tests {
         config.connect().await.unwrap();
     }
 
+    #[tokio::test]
+    async fn pool() {
+        let env = TestEnv::new().await.unwrap();
+
+        let config = env.config();
+
+        let pool = config.pool().await.unwrap();
+        pool.acquire().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn connect_retries() {
+        let mut config = Config {
+            // Nothing should be listening on this port.
+            database_connect_options: Some(PgConnectOptions::new().host("127.0.0.1").port(1)),
+            connect_retries: 2,
+            connect_backoff: Duration::from_millis(10),
+            ..Default::default()
+        };
+
+        let started = Instant::now();
+        match config.connect().await {
+            Err(ConnectError::Connect(sqlx::Error::Io(_))) => (),
+
+            Ok(conn) => panic!("Unexpected success: {:?}", conn),
+            Err(err) => panic!("Unexpected error: {:?}", err),
+        };
+        // Two retries: 10ms, then 20ms.
+        assert!(started.elapsed() >= Duration::from_millis(30));
+
+        config.connect_retries = 0;
+        config.connect_wait = Some(Duration::from_millis(50));
+        config.connect().await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn schema_search_path() {
+        let env = TestEnv::new().await.unwrap();
+
+        let mut conn = env.config().connect().await.unwrap();
+        conn.execute(r#"create schema "Tenant"; create function public.answer() returns int as 'select 42' language sql;"#)
+            .await
+            .unwrap();
+
+        let mut config = env.config();
+        config.schema = Some(String::from("Tenant"));
+        let mut conn = config.connect().await.unwrap();
+
+        let search_path: String = sqlx::query_scalar("select current_setting('search_path')")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(r#""Tenant",public"#, search_path);
+
+        let (schema, answer): (String, i32) = sqlx::query_as("select current_schema(), answer()")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!("Tenant", schema);
+        assert_eq!(42, answer);
+    }
+
+    #[tokio::test]
+    async fn pool_not_configured() {
+        let env = TestEnv::new().await.unwrap();
+
+        let mut config = env.config();
+        config.database_connect_options = None;
+
+        match config.pool().await {
+            Err(ConnectError::NotConfigured) => (),
+
+            Ok(pool) => panic!("Unexpected success: {:?}", pool),
+            Err(err) => panic!("Unexpected error: {:?}", err),
+        };
+    }
+
     #[tokio::test]
     async fn not_configured() {
         let env = TestEnv::new().await.unwrap();
@@ -63,6 +549,34 @@ mod tests {
         };
     }
 
+    #[tokio::test]
+    async fn expected_database() {
+        let env = TestEnv::new().await.unwrap();
+
+        let mut config = env.config();
+        let mut conn = config.connect().await.unwrap();
+        let name: String = sqlx::query_scalar("select current_database()")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+
+        config.expected_database_name = Some(String::from("app_production"));
+        match config.connect().await {
+            Err(ConnectError::WrongDatabase { expected, actual }) => {
+                assert_eq!("app_production", expected);
+                assert_eq!(name, actual);
+            }
+
+            Ok(conn) => panic!("Unexpected success: {:?}", conn),
+            Err(err) => panic!("Unexpected error: {:?}", err),
+        };
+        config.pool().await.unwrap_err();
+
+        config.expected_database_name = Some(name);
+        config.connect().await.unwrap();
+        config.pool().await.unwrap();
+    }
+
     #[tokio::test]
     async fn connect_error() {
         let env = TestEnv::new().await.unwrap();
@@ -80,4 +594,39 @@ mod tests {
             err => panic!("Unexpected error: {:?}", err),
         };
     }
+
+    #[test]
+    fn protected() {
+        let mut config = Config {
+            database_connect_options: Some(
+                "postgres://app@db.example.com:5432/app_production"
+                    .parse()
+                    .unwrap(),
+            ),
+            ..Default::default()
+        };
+        assert_eq!(None, config.protected_database().unwrap());
+        config.check_protected(None).unwrap();
+
+        config.protected_patterns = vec![String::from(r"^db\.example\.com:"), String::from("prod")];
+        assert_eq!(
+            Some(String::from("app_production")),
+            config.protected_database().unwrap()
+        );
+        assert!(matches!(
+            config.check_protected(Some("app")),
+            Err(ProtectedError::NotConfirmed { .. })
+        ));
+        config.check_protected(Some("app_production")).unwrap();
+
+        config.protected_patterns = vec![String::from("(")];
+        assert!(matches!(
+            config.protected_database(),
+            Err(ProtectedError::Pattern { .. })
+        ));
+
+        config.protected_patterns.clear();
+        config.protected = true;
+        config.check_protected(Some("app_production")).unwrap();
+    }
 }
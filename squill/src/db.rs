@@ -1,14 +1,30 @@
 use std::collections::BTreeMap;
+use std::time::Duration;
 
+use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgConnection;
+use sqlx::{Acquire, Postgres};
 
 use crate::MigrationId;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MigrationRecord {
     pub id: MigrationId,
     pub name: String,
     pub run_at: time::PrimitiveDateTime,
+
+    /// How long the migration took to apply. This is only known for migrations applied since the
+    /// migration log started recording durations.
+    #[serde(default)]
+    pub duration: Option<Duration>,
+
+    /// The database user that applied the migration, if the migration log records it.
+    #[serde(default)]
+    pub applied_by: Option<String>,
+
+    /// The Squill version and host that applied the migration, if the migration log records it.
+    #[serde(default)]
+    pub applied_with: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -17,8 +33,13 @@ pub struct MigrationLog {
 }
 
 impl MigrationLog {
-    pub async fn new(conn: &mut PgConnection) -> Result<Self, QueryError> {
-        let applied = applied_migrations(conn).await?;
+    #[tracing::instrument(name = "migration_log", skip_all)]
+    pub async fn new<'c, A>(conn: A) -> Result<Self, QueryError>
+    where
+        A: Acquire<'c, Database = Postgres>,
+    {
+        let mut conn = conn.acquire().await.map_err(QueryError)?;
+        let applied = applied_migrations(&mut *conn).await?;
 
         let index = applied
             .into_iter()
@@ -29,18 +50,35 @@ impl MigrationLog {
                         id: MigrationId(row.id),
                         name: row.name,
                         run_at: row.run_at,
+                        duration: row
+                            .duration_ms
+                            .and_then(|ms| u64::try_from(ms).ok())
+                            .map(Duration::from_millis),
+                        applied_by: row.applied_by,
+                        applied_with: row.applied_with,
                     },
                 )
             })
-            .collect();
+            .collect::<BTreeMap<_, _>>();
+
+        tracing::debug!(applied = index.len(), "Loaded migration log");
 
         Ok(Self { log: index })
     }
 
+    pub fn get(&self, id: MigrationId) -> Option<&MigrationRecord> {
+        self.log.get(&id)
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = &MigrationRecord> {
         self.log.values()
     }
 
+    pub(crate) fn from_records(records: impl IntoIterator<Item = MigrationRecord>) -> Self {
+        let log = records.into_iter().map(|r| (r.id, r)).collect();
+        Self { log }
+    }
+
     pub fn last(&self) -> Option<MigrationRecord> {
         self.iter().cloned().max_by_key(|row| (row.run_at, row.id))
     }
@@ -51,6 +89,16 @@ struct MigrationRow {
     pub id: i64,
     pub name: String,
     pub run_at: time::PrimitiveDateTime,
+
+    // Older migration logs don't have this column.
+    #[sqlx(default)]
+    pub duration_ms: Option<i64>,
+
+    #[sqlx(default)]
+    pub applied_by: Option<String>,
+
+    #[sqlx(default)]
+    pub applied_with: Option<String>,
 }
 
 async fn applied_migrations(conn: &mut PgConnection) -> Result<Vec<MigrationRow>, QueryError> {
@@ -73,9 +121,86 @@ async fn applied_migrations(conn: &mut PgConnection) -> Result<Vec<MigrationRow>
     }
 }
 
+/// One application of a migration, including ones that were undone later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub id: MigrationId,
+    pub name: String,
+    pub run_at: time::PrimitiveDateTime,
+    pub applied_by: Option<String>,
+    pub applied_with: Option<String>,
+
+    /// When the migration was undone, if it was.
+    pub undone_at: Option<time::PrimitiveDateTime>,
+    pub undone_by: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct UndoRow {
+    id: i64,
+    name: String,
+    run_at: time::PrimitiveDateTime,
+    applied_by: Option<String>,
+    // Older migration logs don't have this column.
+    #[sqlx(default)]
+    applied_with: Option<String>,
+    undone_at: time::PrimitiveDateTime,
+    undone_by: Option<String>,
+}
+
+/// Load every application of a migration in the order they happened, including the undone ones.
+///
+/// Undone migrations are only listed if the migration log keeps them in schema_migration_undos.
+pub async fn migration_history<'c, A>(conn: A) -> Result<Vec<HistoryEntry>, QueryError>
+where
+    A: Acquire<'c, Database = Postgres>,
+{
+    let mut conn = conn.acquire().await.map_err(QueryError)?;
+    let applied = MigrationLog::new(&mut *conn).await?;
+
+    let mut history: Vec<_> = applied
+        .iter()
+        .map(|r| HistoryEntry {
+            id: r.id,
+            name: r.name.clone(),
+            run_at: r.run_at,
+            applied_by: r.applied_by.clone(),
+            applied_with: r.applied_with.clone(),
+            undone_at: None,
+            undone_by: None,
+        })
+        .collect();
+
+    let has_undos: bool =
+        sqlx::query_scalar("select to_regclass('schema_migration_undos') is not null")
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(QueryError)?;
+
+    if has_undos {
+        let undos: Vec<UndoRow> = sqlx::query_as("select * from schema_migration_undos")
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(QueryError)?;
+
+        history.extend(undos.into_iter().map(|row| HistoryEntry {
+            id: MigrationId(row.id),
+            name: row.name,
+            run_at: row.run_at,
+            applied_by: row.applied_by,
+            applied_with: row.applied_with,
+            undone_at: Some(row.undone_at),
+            undone_by: row.undone_by,
+        }));
+    }
+
+    history.sort_by_key(|h| (h.run_at, h.id));
+    Ok(history)
+}
+
 #[derive(thiserror::Error, Debug)]
-#[error("failed to query applied migrations: {0}")]
-pub struct QueryError(sqlx::Error);
+#[error("failed to query applied migrations")]
+pub struct QueryError(#[source] sqlx::Error);
 
 #[cfg(test)]
 mod tests {
@@ -150,4 +275,75 @@ mod tests {
         assert_eq!(MigrationId(1), last.id);
         assert_eq!("one", &last.name);
     }
+
+    #[tokio::test]
+    async fn durations() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let mut params = fake_migration(1, "one");
+        params.up_sql = String::from("select pg_sleep(0.05)");
+        let one = index.create(params).unwrap();
+        let two = index.create(fake_migration(2, "two")).unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        one.up(&mut conn).await.unwrap();
+
+        let log = MigrationLog::new(&mut conn).await.unwrap();
+        let duration = log.get(one.id).unwrap().duration.unwrap();
+        assert!(duration >= Duration::from_millis(50), "{:?}", duration);
+
+        // Migration logs from before durations were recorded still work.
+        conn.execute("alter table schema_migrations drop column duration_ms")
+            .await
+            .unwrap();
+        conn.execute("drop function _squill_record_duration")
+            .await
+            .unwrap();
+
+        two.up(&mut conn).await.unwrap();
+
+        let log = MigrationLog::new(&mut conn).await.unwrap();
+        assert_eq!(None, log.get(two.id).unwrap().duration);
+    }
+
+    #[tokio::test]
+    async fn history() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        one.up(&mut conn).await.unwrap();
+        one.down(&mut conn, false).await.unwrap();
+        one.up(&mut conn).await.unwrap();
+
+        let user: String = sqlx::query_scalar("select current_user::text")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+
+        let history = migration_history(&mut conn).await.unwrap();
+        let summary: Vec<_> = history
+            .iter()
+            .map(|h| (h.id, h.undone_at.is_some()))
+            .collect();
+        assert_eq!(
+            vec![(MigrationId(0), false), (one.id, true), (one.id, false)],
+            summary
+        );
+        assert!(history
+            .iter()
+            .all(|h| h.applied_by.as_deref() == Some(user.as_str())));
+
+        // No-transaction migrations like init claim themselves, but they get it too.
+        let applied_with = crate::metadata::applied_with();
+        assert!(history
+            .iter()
+            .all(|h| h.applied_with.as_deref() == Some(applied_with.as_str())));
+        assert_eq!(Some(user.as_str()), history[1].undone_by.as_deref());
+    }
 }
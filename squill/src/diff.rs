@@ -0,0 +1,329 @@
+use std::collections::BTreeMap;
+
+use sqlx::{Acquire, Executor, Postgres};
+
+use crate::config::{Config, ConnectError};
+use crate::sandbox::{Sandbox, SandboxError};
+
+// Squill's own tables (the migration log and friends) are left out, so comparing a database with
+// one that hasn't been initialized doesn't suggest dropping them.
+const COLUMNS: &str = r#"
+select format('%I.%I', n.nspname, c.relname), quote_ident(a.attname),
+    format('%s%s%s',
+        format_type(a.atttypid, a.atttypmod),
+        case
+            when a.attidentity = 'a' then ' generated always as identity'
+            when a.attidentity = 'd' then ' generated by default as identity'
+            when a.attgenerated = 's' then ' generated always as (' || pg_get_expr(ad.adbin, ad.adrelid) || ') stored'
+            when ad.adbin is not null then ' default ' || pg_get_expr(ad.adbin, ad.adrelid)
+            else ''
+        end,
+        case when a.attnotnull then ' not null' else '' end
+    )
+from pg_class c
+join pg_namespace n on n.oid = c.relnamespace
+left join pg_attribute a on a.attrelid = c.oid and a.attnum > 0 and not a.attisdropped
+left join pg_attrdef ad on ad.adrelid = a.attrelid and ad.adnum = a.attnum
+where c.relkind in ('r', 'p')
+  and n.nspname not in ('pg_catalog', 'information_schema')
+  and n.nspname not like 'pg_toast%'
+  and n.nspname not like 'pg_temp%'
+  and c.relname not like 'schema\_migration%'
+  and not exists (select 1 from pg_depend d where d.objid = c.oid and d.deptype = 'e')
+order by 1, a.attnum
+"#;
+
+const INDEXES: &str = r#"
+select format('%I.%I', n.nspname, i.relname), pg_get_indexdef(i.oid)
+from pg_index x
+join pg_class i on i.oid = x.indexrelid
+join pg_class c on c.oid = x.indrelid
+join pg_namespace n on n.oid = i.relnamespace
+where n.nspname not in ('pg_catalog', 'information_schema')
+  and n.nspname not like 'pg_toast%'
+  and c.relname not like 'schema\_migration%'
+  -- Constraints (and the indexes that back them) aren't compared yet.
+  and not exists (select 1 from pg_constraint con where con.conindid = i.oid)
+  and not exists (select 1 from pg_depend d where d.objid = c.oid and d.deptype = 'e')
+order by 1
+"#;
+
+/// The tables, columns, and indexes in a database: the parts of the schema that `squill diff`
+/// compares.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SchemaSnapshot {
+    /// The columns of each table, in order. Tables are listed by their quoted, schema-qualified
+    /// name, like `public.users`.
+    pub tables: BTreeMap<String, Vec<Column>>,
+
+    /// Index definitions, by quoted, schema-qualified index name.
+    pub indexes: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    /// The quoted column name.
+    pub name: String,
+
+    /// The type, default, and not-null constraint, like `text default ''::text not null`.
+    pub definition: String,
+}
+
+impl SchemaSnapshot {
+    pub async fn read<'c, A>(conn: A) -> Result<Self, sqlx::Error>
+    where
+        A: Acquire<'c, Database = Postgres>,
+    {
+        let mut conn = conn.acquire().await?;
+        let mut snapshot = Self::default();
+
+        let columns: Vec<(String, Option<String>, Option<String>)> =
+            sqlx::query_as(COLUMNS).fetch_all(&mut *conn).await?;
+        for (table, name, definition) in columns {
+            let table = snapshot.tables.entry(table).or_default();
+
+            // A table without columns still has one row, with nulls for the column.
+            if let (Some(name), Some(definition)) = (name, definition) {
+                table.push(Column { name, definition });
+            }
+        }
+
+        let indexes: Vec<(String, String)> = sqlx::query_as(INDEXES).fetch_all(&mut *conn).await?;
+        snapshot.indexes.extend(indexes);
+
+        Ok(snapshot)
+    }
+}
+
+/// Snapshot the schema in a dump (like the configured schema_file) by loading it into a new,
+/// disposable database.
+///
+/// The dump is run as-is, so it has to create objects in an order that works.
+pub async fn read_schema_dump(config: &Config, sql: &str) -> Result<SchemaSnapshot, DiffError> {
+    let sandbox = Sandbox::create(config, "squill_diff")
+        .await
+        .map_err(DiffError::Sandbox)?;
+
+    // Close the connection before dropping the database, even if loading the dump failed.
+    let snapshot = async {
+        let mut conn = sandbox
+            .config()
+            .connect()
+            .await
+            .map_err(DiffError::Connect)?;
+
+        (&mut conn).execute(sql).await.map_err(DiffError::Load)?;
+
+        SchemaSnapshot::read(&mut conn)
+            .await
+            .map_err(DiffError::Read)
+    }
+    .await;
+
+    sandbox.drop_database().await.map_err(DiffError::Sandbox)?;
+
+    snapshot
+}
+
+/// A draft migration that changes one schema into another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationDraft {
+    pub up_sql: String,
+    pub down_sql: String,
+}
+
+const HEADER: &str =
+    "-- Drafted by `squill diff`, which only compares tables, columns, and indexes.
+-- Review it (especially any TODOs) before applying it.";
+
+impl MigrationDraft {
+    /// Draft the migration from `current` to `target`, or `None` if they're the same.
+    ///
+    /// The down migration is the same comparison in the other direction. Changed columns only get
+    /// a TODO comment, since changing a type or default safely depends on the data.
+    pub fn new(current: &SchemaSnapshot, target: &SchemaSnapshot) -> Option<Self> {
+        let up = statements(current, target);
+        if up.is_empty() {
+            return None;
+        }
+
+        let down = statements(target, current);
+
+        Some(Self {
+            up_sql: format!("{HEADER}\n\n{}\n", up.join("\n\n")),
+            down_sql: format!("{HEADER}\n\n{}\n", down.join("\n\n")),
+        })
+    }
+}
+
+fn statements(from: &SchemaSnapshot, to: &SchemaSnapshot) -> Vec<String> {
+    let mut out = Vec::new();
+
+    // Indexes go first, since they can depend on columns that are about to be dropped.
+    for (name, definition) in &from.indexes {
+        if to.indexes.get(name) != Some(definition) && table_kept(from, to, definition) {
+            out.push(format!("drop index {name};"));
+        }
+    }
+
+    for (table, columns) in &to.tables {
+        let Some(existing) = from.tables.get(table) else {
+            out.push(create_table(table, columns));
+            continue;
+        };
+
+        for column in columns {
+            match existing.iter().find(|c| c.name == column.name) {
+                None => out.push(format!(
+                    "alter table {table} add column {} {};",
+                    column.name, column.definition
+                )),
+                Some(old) if old.definition != column.definition => out.push(format!(
+                    "-- TODO: change {table}.{} from `{}` to `{}`",
+                    column.name, old.definition, column.definition
+                )),
+                Some(_) => {}
+            }
+        }
+
+        for old in existing {
+            if !columns.iter().any(|c| c.name == old.name) {
+                out.push(format!("alter table {table} drop column {};", old.name));
+            }
+        }
+    }
+
+    for table in from.tables.keys() {
+        if !to.tables.contains_key(table) {
+            out.push(format!("drop table {table};"));
+        }
+    }
+
+    for (name, definition) in &to.indexes {
+        if from.indexes.get(name) != Some(definition) {
+            out.push(format!("{definition};"));
+        }
+    }
+
+    out
+}
+
+/// Dropping a table drops its indexes too, so they don't need their own statement.
+fn table_kept(from: &SchemaSnapshot, to: &SchemaSnapshot, definition: &str) -> bool {
+    !from
+        .tables
+        .keys()
+        .any(|table| !to.tables.contains_key(table) && index_on(definition, table))
+}
+
+fn index_on(definition: &str, table: &str) -> bool {
+    definition.contains(&format!(" ON {table} USING "))
+        || definition.contains(&format!(" ON ONLY {table} USING "))
+}
+
+fn create_table(table: &str, columns: &[Column]) -> String {
+    let columns: Vec<_> = columns
+        .iter()
+        .map(|c| format!("\n    {} {}", c.name, c.definition))
+        .collect();
+
+    format!("create table {table} ({}\n);", columns.join(","))
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum DiffError {
+    #[error(transparent)]
+    Sandbox(SandboxError),
+
+    #[error(transparent)]
+    Connect(ConnectError),
+
+    #[error("failed to load the schema dump")]
+    Load(#[source] sqlx::Error),
+
+    #[error("failed to read the schema")]
+    Read(#[source] sqlx::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn draft() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut conn = config.connect().await.unwrap();
+        conn.execute(
+            r#"
+            create table users (id bigint primary key, email text);
+            create table old_things (id int);
+            create index old_things_id on old_things (id);
+            "#,
+        )
+        .await
+        .unwrap();
+        let current = SchemaSnapshot::read(&mut conn).await.unwrap();
+
+        assert_eq!(None, MigrationDraft::new(&current, &current));
+
+        conn.execute(
+            r#"
+            alter table users add column "Display Name" text not null default '';
+            alter table users alter column email set not null;
+            create index users_email on users (email);
+            drop table old_things;
+            create table teams (id int, name text);
+            "#,
+        )
+        .await
+        .unwrap();
+        let target = SchemaSnapshot::read(&mut conn).await.unwrap();
+
+        let draft = MigrationDraft::new(&current, &target).unwrap();
+        let body = |sql: &str| sql.split_once("\n\n").unwrap().1.to_owned();
+
+        assert_eq!(
+            r#"create table public.teams (
+    id integer,
+    name text
+);
+
+alter table public.users add column "Display Name" text default ''::text not null;
+
+-- TODO: change public.users.email from `text` to `text not null`
+
+drop table public.old_things;
+
+CREATE INDEX users_email ON public.users USING btree (email);
+"#,
+            body(&draft.up_sql)
+        );
+
+        assert_eq!(
+            r#"drop index public.users_email;
+
+create table public.old_things (
+    id integer
+);
+
+-- TODO: change public.users.email from `text not null` to `text`
+
+alter table public.users drop column "Display Name";
+
+drop table public.teams;
+
+CREATE INDEX old_things_id ON public.old_things USING btree (id);
+"#,
+            body(&draft.down_sql)
+        );
+
+        let dump = "create table public.teams (id int, name text);";
+        let loaded = read_schema_dump(&config, dump).await.unwrap();
+        assert_eq!(target.tables["public.teams"], loaded.tables["public.teams"]);
+    }
+}
@@ -0,0 +1,408 @@
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::migrate::MigrationId;
+
+/// The `--squill:` directives in a migration file.
+///
+/// A directive is a line like `--squill:no-transaction` or `--squill:statement-timeout=5s`. The
+/// value can also come after a space instead, like `--squill:squashes 1, 2, 3`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationDirectives {
+    /// Run the file outside of a transaction. The file has to record itself in the migration log.
+    pub no_transaction: bool,
+
+    /// Refuse to undo this migration, even where down migrations are allowed.
+    pub only_up: bool,
+
+    pub statement_timeout: Option<Duration>,
+    pub lock_timeout: Option<Duration>,
+
+    /// The isolation level to start the migration's transaction with, instead of the database's
+    /// default.
+    pub isolation: Option<IsolationLevel>,
+
+    /// How many times to run the migration again if it fails with a serialization failure or
+    /// deadlock. This only applies to migrations that run in a transaction.
+    pub retries: u32,
+
+    /// Run the file over and over, committing after each run, until it changes no rows. The
+    /// migration is recorded with the last (empty) batch.
+    pub batched: bool,
+
+    /// The most batches a batched migration can run before it fails, instead of
+    /// [`DEFAULT_MAX_BATCHES`].
+    pub max_batches: Option<u32>,
+
+    /// Save progress after each statement of a migration that runs outside of a transaction, so a
+    /// failed run can be resumed instead of starting over. This runs the file one statement at a
+    /// time instead of all at once.
+    pub checkpoints: bool,
+
+    /// Migrations that have to be applied before this one.
+    pub depends_on: Vec<MigrationId>,
+
+    /// The migrations that this one replaced when they were squashed together.
+    pub squashes: Vec<MigrationId>,
+
+    /// The migration that replaced this one, if it has been retired.
+    pub retired_by: Option<MigrationId>,
+
+    /// The template group the migration was created from.
+    pub template: Option<String>,
+
+    /// Names of the lint rules that are allowed in this file.
+    pub lint_allow: Vec<String>,
+}
+
+/// How many batches a batched migration can run without a max-batches directive. A backfill that
+/// never runs out of rows to change (like one whose `where` clause still matches the rows it
+/// updated) fails here instead of running forever.
+pub const DEFAULT_MAX_BATCHES: u32 = 10_000;
+
+/// A transaction isolation level, like `--squill:isolation=serializable`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsolationLevel {
+    ReadCommitted,
+    RepeatableRead,
+    Serializable,
+}
+
+impl IsolationLevel {
+    /// The level as it's written in `set transaction isolation level`.
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            IsolationLevel::ReadCommitted => "read committed",
+            IsolationLevel::RepeatableRead => "repeatable read",
+            IsolationLevel::Serializable => "serializable",
+        }
+    }
+}
+
+impl std::fmt::Display for IsolationLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_sql())
+    }
+}
+
+impl std::str::FromStr for IsolationLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace([' ', '_'], "-").as_str() {
+            "read-committed" => Ok(IsolationLevel::ReadCommitted),
+            "repeatable-read" => Ok(IsolationLevel::RepeatableRead),
+            "serializable" => Ok(IsolationLevel::Serializable),
+            _ => Err(()),
+        }
+    }
+}
+
+/// One `--squill:` line, before its value is checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Directive<'a> {
+    /// The line number, starting from 1.
+    pub line: usize,
+    pub key: &'a str,
+    pub value: Option<&'a str>,
+}
+
+/// Find the `--squill:` lines in this SQL.
+pub fn directive_lines(sql: &str) -> impl Iterator<Item = Directive<'_>> {
+    lazy_static! {
+        static ref RE_DIRECTIVE: Regex =
+            Regex::new(r"^--squill:(?P<key>[^\s=]*)(?:[\s=](?P<value>.*))?$")
+                .expect("static pattern");
+    }
+
+    sql.lines().enumerate().filter_map(|(i, line)| {
+        let caps = RE_DIRECTIVE.captures(line.trim())?;
+
+        let key = caps.name("key").expect("static capture group").as_str();
+        let value = caps
+            .name("value")
+            .map(|v| v.as_str().trim())
+            .filter(|v| !v.is_empty());
+
+        Some(Directive {
+            line: i + 1,
+            key,
+            value,
+        })
+    })
+}
+
+impl MigrationDirectives {
+    /// Read the directives in this SQL.
+    ///
+    /// Unknown directives are skipped with a warning, so files written for a newer version of
+    /// Squill can still run. A known directive with a missing or invalid value is an error.
+    pub fn parse(sql: &str) -> Result<Self, DirectiveError> {
+        let (directives, problems) = Self::parse_all(sql);
+
+        for problem in problems {
+            match problem {
+                DirectiveError::Unknown { .. } => {
+                    tracing::warn!("skipping {}", problem)
+                }
+                _ => return Err(problem),
+            }
+        }
+
+        Ok(directives)
+    }
+
+    /// Read every valid directive in this SQL, and return the problems with the others (including
+    /// unknown directives) instead of stopping at the first one.
+    pub fn parse_all(sql: &str) -> (Self, Vec<DirectiveError>) {
+        let mut directives = Self::default();
+        let mut problems = Vec::new();
+
+        for directive in directive_lines(sql) {
+            if let Err(err) = directives.apply(&directive) {
+                problems.push(err);
+            }
+        }
+
+        (directives, problems)
+    }
+
+    fn apply(&mut self, directive: &Directive) -> Result<(), DirectiveError> {
+        let Directive { line, key, value } = *directive;
+
+        let flag = || match value {
+            None => Ok(true),
+            Some(_) => Err(DirectiveError::UnexpectedValue {
+                line,
+                key: key.to_owned(),
+            }),
+        };
+
+        let value = || {
+            value.ok_or_else(|| DirectiveError::MissingValue {
+                line,
+                key: key.to_owned(),
+            })
+        };
+
+        let invalid = |value: &str| DirectiveError::InvalidValue {
+            line,
+            key: key.to_owned(),
+            value: value.to_owned(),
+        };
+
+        let ids = |value: &str| {
+            value
+                .split(',')
+                .map(|id| id.trim().parse::<MigrationId>().map_err(|_| invalid(value)))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        match key {
+            "no-transaction" => self.no_transaction = flag()?,
+            "only-up" => self.only_up = flag()?,
+            "batched" => self.batched = flag()?,
+            "checkpoints" => self.checkpoints = flag()?,
+            "statement-timeout" => {
+                let value = value()?;
+                self.statement_timeout = Some(parse_timeout(value).ok_or_else(|| invalid(value))?);
+            }
+            "lock-timeout" => {
+                let value = value()?;
+                self.lock_timeout = Some(parse_timeout(value).ok_or_else(|| invalid(value))?);
+            }
+            "retries" => {
+                let value = value()?;
+                self.retries = value.parse().map_err(|_| invalid(value))?;
+            }
+            "max-batches" => {
+                let value = value()?;
+                match value.parse() {
+                    Ok(0) | Err(_) => return Err(invalid(value)),
+                    Ok(max) => self.max_batches = Some(max),
+                }
+            }
+            "isolation" => {
+                let value = value()?;
+                self.isolation = Some(value.parse().map_err(|_| invalid(value))?);
+            }
+            "depends-on" => self.depends_on.extend(ids(value()?)?),
+            "squashes" => self.squashes.extend(ids(value()?)?),
+            "retired-by" => {
+                let value = value()?;
+                self.retired_by = Some(value.parse().map_err(|_| invalid(value))?);
+            }
+            "template" => self.template = Some(value()?.to_owned()),
+            "lint-allow" => self
+                .lint_allow
+                .extend(value()?.split_whitespace().map(String::from)),
+            _ => {
+                return Err(DirectiveError::Unknown {
+                    line,
+                    key: key.to_owned(),
+                })
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a timeout like `500ms`, `5s`, `2min`, or `1h`. A number without a unit is in
+/// milliseconds, like in Postgres.
+pub fn parse_timeout(s: &str) -> Option<Duration> {
+    lazy_static! {
+        static ref RE_TIMEOUT: Regex =
+            Regex::new(r"^(?P<n>[0-9]+)\s*(?P<unit>ms|s|min|h)?$").expect("static pattern");
+    }
+
+    let caps = RE_TIMEOUT.captures(s.trim())?;
+    let n: u64 = caps["n"].parse().ok()?;
+
+    match caps.name("unit").map(|u| u.as_str()) {
+        None | Some("ms") => Some(Duration::from_millis(n)),
+        Some("s") => Some(Duration::from_secs(n)),
+        Some("min") => n.checked_mul(60).map(Duration::from_secs),
+        Some("h") => n.checked_mul(60 * 60).map(Duration::from_secs),
+        Some(_) => None,
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DirectiveError {
+    #[error("line {line}: unknown directive --squill:{key}")]
+    Unknown { line: usize, key: String },
+
+    #[error("line {line}: --squill:{key} needs a value")]
+    MissingValue { line: usize, key: String },
+
+    #[error("line {line}: --squill:{key} doesn't take a value")]
+    UnexpectedValue { line: usize, key: String },
+
+    #[error("line {line}: invalid value for --squill:{key}: {value:?}")]
+    InvalidValue {
+        line: usize,
+        key: String,
+        value: String,
+    },
+}
+
+impl DirectiveError {
+    /// The line number of the directive, starting from 1.
+    pub fn line(&self) -> usize {
+        match self {
+            DirectiveError::Unknown { line, .. }
+            | DirectiveError::MissingValue { line, .. }
+            | DirectiveError::UnexpectedValue { line, .. }
+            | DirectiveError::InvalidValue { line, .. } => *line,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_directives() {
+        let sql = "-- Add an index without blocking writes.
+--squill:no-transaction
+--squill:only-up
+--squill:batched
+--squill:max-batches=50
+--squill:checkpoints
+--squill:statement-timeout=10min
+--squill:lock-timeout 5s
+--squill:isolation=repeatable-read
+--squill:retries 3
+--squill:depends-on=3, 4
+--squill:squashes 1,2
+--squill:template create_index
+  --squill:lint-allow set-not-null ddl-with-dml
+
+create index concurrently users_email on users (email);
+";
+
+        let directives = MigrationDirectives::parse(sql).unwrap();
+        assert_eq!(
+            MigrationDirectives {
+                no_transaction: true,
+                only_up: true,
+                statement_timeout: Some(Duration::from_secs(600)),
+                lock_timeout: Some(Duration::from_secs(5)),
+                isolation: Some(IsolationLevel::RepeatableRead),
+                retries: 3,
+                batched: true,
+                max_batches: Some(50),
+                checkpoints: true,
+                depends_on: vec![MigrationId(3), MigrationId(4)],
+                squashes: vec![MigrationId(1), MigrationId(2)],
+                retired_by: None,
+                template: Some(String::from("create_index")),
+                lint_allow: vec![String::from("set-not-null"), String::from("ddl-with-dml")],
+            },
+            directives
+        );
+
+        assert_eq!(
+            MigrationDirectives::default(),
+            MigrationDirectives::parse("-- --squill:no-transaction\nselect 1;").unwrap()
+        );
+
+        assert_eq!(Some(Duration::from_millis(250)), parse_timeout("250"));
+        assert_eq!(Some(Duration::from_secs(2 * 60 * 60)), parse_timeout("2h"));
+        assert_eq!(None, parse_timeout("soon"));
+
+        assert_eq!(Ok(IsolationLevel::Serializable), "Serializable".parse());
+        assert_eq!(Ok(IsolationLevel::ReadCommitted), "read committed".parse());
+        assert_eq!(Err(()), "snapshot".parse::<IsolationLevel>());
+    }
+
+    #[test]
+    fn invalid_directives() {
+        let sql = "--squill:no-transation
+--squill:no-transaction=yes
+--squill:depends-on 3, three
+--squill:retired-by
+--squill:only-up
+";
+
+        let (directives, problems) = MigrationDirectives::parse_all(sql);
+        assert!(directives.only_up);
+        assert_eq!(
+            vec![
+                DirectiveError::Unknown {
+                    line: 1,
+                    key: String::from("no-transation"),
+                },
+                DirectiveError::UnexpectedValue {
+                    line: 2,
+                    key: String::from("no-transaction"),
+                },
+                DirectiveError::InvalidValue {
+                    line: 3,
+                    key: String::from("depends-on"),
+                    value: String::from("3, three"),
+                },
+                DirectiveError::MissingValue {
+                    line: 4,
+                    key: String::from("retired-by"),
+                },
+            ],
+            problems
+        );
+
+        // Only unknown directives are skipped.
+        assert!(MigrationDirectives::parse("--squill:later\nselect 1;").is_ok());
+        assert_eq!(
+            Err(DirectiveError::UnexpectedValue {
+                line: 2,
+                key: String::from("no-transaction"),
+            }),
+            MigrationDirectives::parse(sql)
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use sqlx::PgConnection;
+
+use crate::archive::MigrationSource;
+use crate::config::{Config, ConnectError};
+use crate::index::IndexError;
+use crate::template::Templates;
+use crate::{error_chain, slugify};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl std::fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckStatus::Pass => write!(f, "pass"),
+            CheckStatus::Warn => write!(f, "warn"),
+            CheckStatus::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+/// The result of one `squill doctor` check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub check: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+
+    /// What to do about a warning or failure.
+    pub hint: Option<String>,
+}
+
+impl Diagnostic {
+    fn pass(check: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            check,
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(check: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            check,
+            status: CheckStatus::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(check: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            check,
+            status: CheckStatus::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// Check the whole setup: the migrations directories, templates, database connection, and the
+/// migration log that the init migration creates.
+///
+/// Every check runs even if an earlier one fails, except that the database checks need a
+/// connection.
+pub async fn diagnose(config: &Config) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    diagnostics.extend(check_migrations_dirs(config));
+    diagnostics.extend(check_index(config));
+    diagnostics.push(check_templates(config));
+
+    match config.connect().await {
+        Ok(mut conn) => {
+            diagnostics.push(Diagnostic::pass("database", "connected"));
+            diagnostics.extend(check_migration_log(&mut conn).await);
+        }
+        Err(ConnectError::NotConfigured) => diagnostics.push(Diagnostic::fail(
+            "database",
+            "no database is configured",
+            "Set database_url in squill.toml or SQUILL_DATABASE_URL.",
+        )),
+        Err(err @ (ConnectError::WrongDatabase { .. } | ConnectError::WrongServer { .. })) => {
+            diagnostics.push(Diagnostic::fail(
+                "database",
+                error_chain(&err),
+                "Check that database_url points at the right database.",
+            ))
+        }
+        Err(err) => diagnostics.push(Diagnostic::fail(
+            "database",
+            error_chain(&err),
+            "Check database_url and that the database is running.",
+        )),
+    }
+
+    diagnostics
+}
+
+fn check_migrations_dirs(config: &Config) -> Vec<Diagnostic> {
+    let dirs = std::iter::once(&config.migrations_dir).chain(&config.extra_migrations_dirs);
+
+    dirs.map(|dir| {
+        let unpacked = match MigrationSource::new(dir).dir(&config.cache_dir) {
+            Ok(unpacked) => unpacked,
+            Err(err) => {
+                return Diagnostic::fail(
+                    "migrations_dir",
+                    error_chain(&err),
+                    "Check that the archive exists and that tar (or unzip) can read it.",
+                )
+            }
+        };
+
+        match std::fs::read_dir(unpacked) {
+            Ok(_) => Diagnostic::pass("migrations_dir", dir.to_string_lossy()),
+            Err(err) => Diagnostic::fail(
+                "migrations_dir",
+                format!("{}: {err}", dir.to_string_lossy()),
+                "Run `squill init` to create the migrations directory, or fix migrations_dir.",
+            ),
+        }
+    })
+    .collect()
+}
+
+fn check_index(config: &Config) -> Vec<Diagnostic> {
+    let index = match config.migration_index() {
+        Ok(index) => index,
+        Err(IndexError::MultipleMigrationDirectories(multiples)) => {
+            let ids: Vec<_> = multiples.keys().map(|id| id.to_string()).collect();
+            return vec![Diagnostic::fail(
+                "migration IDs",
+                format!("more than one directory for ID {}", ids.join(", ")),
+                "Give each migration its own ID, like with `squill new` for the newer one.",
+            )];
+        }
+        // This is already reported by the migrations_dir check.
+        Err(IndexError::ReadDir { .. } | IndexError::Archive(_)) => return Vec::new(),
+    };
+
+    let mut diagnostics = vec![Diagnostic::pass(
+        "migration IDs",
+        format!("{} migrations", index.len()),
+    )];
+
+    let unslugged: Vec<_> = index
+        .iter()
+        .filter(|m| slugify(&m.name) != m.name)
+        .map(|m| {
+            let expected = config.naming.format(&m.id.to_string(), &slugify(&m.name));
+            format!("{m} (expected {expected})")
+        })
+        .collect();
+
+    let broken: Vec<_> = index
+        .validate()
+        .into_iter()
+        .map(|b| b.migration.to_string())
+        .collect();
+
+    if broken.is_empty() {
+        diagnostics.push(Diagnostic::pass(
+            "migration files",
+            "every migration has up.sql",
+        ));
+    } else {
+        diagnostics.push(Diagnostic::fail(
+            "migration files",
+            format!("missing up.sql: {}", broken.join(", ")),
+            "Add the up file, or remove the directory if it was left behind by mistake.",
+        ));
+    }
+
+    if unslugged.is_empty() {
+        diagnostics.push(Diagnostic::pass("directory names", "all slugified"));
+    } else {
+        diagnostics.push(Diagnostic::warn(
+            "directory names",
+            unslugged.join(", "),
+            "Rename these with `squill rename` so they're easy to type and sort the same everywhere.",
+        ));
+    }
+
+    diagnostics
+}
+
+fn check_templates(config: &Config) -> Diagnostic {
+    match Templates::from_config(config) {
+        Ok(templates) => Diagnostic::pass(
+            "templates",
+            format!("{} template files", templates.files().len()),
+        ),
+        Err(err) => Diagnostic::fail(
+            "templates",
+            error_chain(&err),
+            "Fix the template files, or unset templates_dir to use the built-in templates.",
+        ),
+    }
+}
+
+const FUNCTIONS: [(&str, bool); 3] = [
+    ("_squill_claim_migration(bigint, text)", true),
+    ("_squill_unclaim_migration(bigint)", true),
+    ("_squill_record_duration(bigint, bigint)", false),
+];
+
+async fn check_migration_log(conn: &mut PgConnection) -> Vec<Diagnostic> {
+    let exists: sqlx::Result<bool> =
+        sqlx::query_scalar("select to_regclass('schema_migrations') is not null")
+            .fetch_one(&mut *conn)
+            .await;
+
+    match exists {
+        Ok(true) => (),
+        Ok(false) => {
+            return vec![Diagnostic::fail(
+                "migration log",
+                "the schema_migrations table doesn't exist",
+                "Run `squill init` and then `squill migrate` to apply the init migration.",
+            )]
+        }
+        Err(err) => {
+            return vec![Diagnostic::fail(
+                "migration log",
+                err.to_string(),
+                "Check that the database user can read the schema.",
+            )]
+        }
+    }
+
+    let mut diagnostics = vec![Diagnostic::pass(
+        "migration log",
+        "schema_migrations exists",
+    )];
+
+    let mut missing_required = Vec::new();
+    let mut missing_optional = Vec::new();
+
+    for (signature, required) in FUNCTIONS {
+        let exists: sqlx::Result<bool> =
+            sqlx::query_scalar("select to_regprocedure($1) is not null")
+                .bind(signature)
+                .fetch_one(&mut *conn)
+                .await;
+
+        if !matches!(exists, Ok(true)) {
+            if required {
+                missing_required.push(signature);
+            } else {
+                missing_optional.push(signature);
+            }
+        }
+    }
+
+    diagnostics.push(if !missing_required.is_empty() {
+        Diagnostic::fail(
+            "functions",
+            format!("missing {}", missing_required.join(", ")),
+            "Recreate these from the init migration (0-init/up.sql). Squill needs them to record migrations.",
+        )
+    } else if !missing_optional.is_empty() {
+        Diagnostic::warn(
+            "functions",
+            format!("missing {}", missing_optional.join(", ")),
+            "Add these to record migration durations. See the README for the upgrade migration.",
+        )
+    } else {
+        Diagnostic::pass("functions", "claim functions exist")
+    });
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+
+    use super::*;
+
+    fn statuses(diagnostics: &[Diagnostic]) -> Vec<(&'static str, CheckStatus)> {
+        diagnostics.iter().map(|d| (d.check, d.status)).collect()
+    }
+
+    #[tokio::test]
+    async fn healthy_and_unhealthy() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let diagnostics = diagnose(&config).await;
+        assert!(
+            diagnostics.iter().all(|d| d.status == CheckStatus::Pass),
+            "{diagnostics:#?}"
+        );
+
+        let dir = config.migrations_dir.join("1-Add Users");
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("up.sql"), "").unwrap();
+        std::fs::write(dir.join("down.sql"), "").unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        sqlx::query("drop function _squill_unclaim_migration")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let diagnostics = diagnose(&config).await;
+        assert_eq!(
+            vec![
+                ("migrations_dir", CheckStatus::Pass),
+                ("migration IDs", CheckStatus::Pass),
+                ("migration files", CheckStatus::Pass),
+                ("directory names", CheckStatus::Warn),
+                ("templates", CheckStatus::Pass),
+                ("database", CheckStatus::Pass),
+                ("migration log", CheckStatus::Pass),
+                ("functions", CheckStatus::Fail),
+            ],
+            statuses(&diagnostics)
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::migrate::{Direction, MigrateError, MigrationDirectory};
+
+/// Something that happened to a migration while Squill was applying or undoing it.
+#[derive(Debug, Clone, Copy)]
+pub enum MigrationEvent<'a> {
+    /// The migration is about to run.
+    Started {
+        migration: &'a MigrationDirectory,
+        direction: Direction,
+    },
+
+    /// The migration ran and was recorded in (or removed from) the migration log.
+    Finished {
+        migration: &'a MigrationDirectory,
+        direction: Direction,
+        duration: Duration,
+    },
+
+    /// The migration failed, so nothing after it will run.
+    Failed {
+        migration: &'a MigrationDirectory,
+        direction: Direction,
+        error: &'a MigrateError,
+    },
+
+    /// The migration was pending, but another runner applied it first (see skip_applied).
+    Skipped { migration: &'a MigrationDirectory },
+}
+
+impl MigrationEvent<'_> {
+    pub fn migration(&self) -> &MigrationDirectory {
+        match self {
+            MigrationEvent::Started { migration, .. }
+            | MigrationEvent::Finished { migration, .. }
+            | MigrationEvent::Failed { migration, .. }
+            | MigrationEvent::Skipped { migration } => migration,
+        }
+    }
+}
+
+/// Receives migration events, like for driving a progress display or recording metrics.
+///
+/// Observers are called inline between migrations, so they should return quickly. To do slow work,
+/// send what you need from the event to a channel and handle it elsewhere.
+pub trait MigrationObserver: Send + Sync {
+    fn on_event(&self, event: &MigrationEvent<'_>);
+}
+
+impl<F> MigrationObserver for F
+where
+    F: Fn(&MigrationEvent<'_>) + Send + Sync,
+{
+    fn on_event(&self, event: &MigrationEvent<'_>) {
+        self(event)
+    }
+}
+
+/// The observers to call for each migration event, in the order they were added.
+#[derive(Clone, Default)]
+pub struct Observers(Vec<Arc<dyn MigrationObserver>>);
+
+impl Observers {
+    pub fn add(&mut self, observer: impl MigrationObserver + 'static) {
+        self.0.push(Arc::new(observer));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn emit(&self, event: MigrationEvent<'_>) {
+        for observer in &self.0 {
+            observer.on_event(&event);
+        }
+    }
+}
+
+impl std::fmt::Debug for Observers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Observers({})", self.0.len())
+    }
+}
@@ -0,0 +1,135 @@
+use std::time::Instant;
+
+use sqlx::{Acquire, Executor, Postgres};
+
+use crate::directives::{DirectiveError, MigrationDirectives};
+use crate::migrate::{requires_no_transaction, set_isolation, MigrateOptions, Timeouts};
+
+/// Run one-off SQL (like a data fix) the way a migration file would be run, without recording it
+/// in the migration log.
+///
+/// The `--squill:no-transaction` and timeout directives work the same as in a migration, and so
+/// does auto_no_transaction for SQL that can't run in a transaction. Otherwise, the SQL runs in a
+/// transaction that's rolled back if any statement fails.
+#[tracing::instrument(skip_all)]
+pub async fn exec_sql<'c, A>(conn: A, sql: &str, opts: &MigrateOptions) -> Result<(), ExecError>
+where
+    A: Acquire<'c, Database = Postgres>,
+{
+    let directives = MigrationDirectives::parse(sql).map_err(ExecError::Directive)?;
+    let timeouts = Timeouts {
+        statement: directives.statement_timeout.or(opts.statement_timeout),
+        lock: directives.lock_timeout.or(opts.lock_timeout),
+    };
+
+    let mut conn = conn.acquire().await.map_err(ExecError::Execute)?;
+    let started = Instant::now();
+
+    if directives.no_transaction || requires_no_transaction(sql) {
+        if !directives.no_transaction && !opts.auto_no_transaction {
+            return Err(ExecError::RequiresNoTransaction);
+        }
+        if directives.isolation.is_some() {
+            return Err(ExecError::IsolationWithoutTransaction);
+        }
+
+        timeouts
+            .set(&mut conn, false)
+            .await
+            .map_err(ExecError::Execute)?;
+
+        let result = (&mut *conn).execute(sql).await;
+
+        timeouts.reset(&mut conn).await;
+        result.map_err(ExecError::Execute)?;
+    } else {
+        let mut tx = (&mut *conn).begin().await.map_err(ExecError::Execute)?;
+        if let Some(isolation) = directives.isolation {
+            set_isolation(&mut tx, isolation)
+                .await
+                .map_err(ExecError::Execute)?;
+        }
+        timeouts
+            .set(&mut tx, true)
+            .await
+            .map_err(ExecError::Execute)?;
+
+        (&mut *tx).execute(sql).await.map_err(ExecError::Execute)?;
+
+        tx.commit().await.map_err(ExecError::Execute)?;
+    }
+
+    let elapsed = started.elapsed();
+    tracing::info!(
+        elapsed_ms = elapsed.as_millis() as u64,
+        "Finished running SQL in {:?}",
+        elapsed
+    );
+
+    Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum ExecError {
+    #[error("invalid directive")]
+    Directive(#[source] DirectiveError),
+
+    #[error("this SQL cannot run in a transaction: add --squill:no-transaction or enable auto_no_transaction")]
+    RequiresNoTransaction,
+
+    #[error("--squill:isolation needs a transaction, but this SQL runs outside of one")]
+    IsolationWithoutTransaction,
+
+    #[error("failed to execute SQL")]
+    Execute(#[source] sqlx::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn exec() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+        let opts = config.migrate_options();
+
+        let mut conn = config.connect().await.unwrap();
+        exec_sql(&mut conn, "create table fixes (id int);", &opts)
+            .await
+            .unwrap();
+
+        // A failed statement rolls back the earlier ones.
+        let err = exec_sql(
+            &mut conn,
+            "insert into fixes values (1); insert into missing values (1);",
+            &opts,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, ExecError::Execute(_)), "{err:?}");
+
+        let count: i64 = sqlx::query_scalar("select count(*) from fixes")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(0, count);
+
+        let concurrently = "create index concurrently fixes_id on fixes (id);";
+        let err = exec_sql(&mut conn, concurrently, &opts).await.unwrap_err();
+        assert!(matches!(err, ExecError::RequiresNoTransaction), "{err:?}");
+
+        let sql = format!("--squill:no-transaction\n{concurrently}");
+        exec_sql(&mut conn, &sql, &opts).await.unwrap();
+
+        // Nothing was recorded in the migration log.
+        let applied: i64 = sqlx::query_scalar("select count(*) from schema_migrations")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(1, applied);
+    }
+}
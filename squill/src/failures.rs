@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+
+use sqlx::{Executor, PgConnection};
+
+use crate::migrate::{MigrationDirectory, MigrationId};
+
+/// A failed attempt to apply a migration.
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq)]
+pub struct FailureRecord {
+    pub id: i64,
+    pub name: String,
+    pub error: String,
+    pub failed_at: time::PrimitiveDateTime,
+}
+
+impl FailureRecord {
+    pub fn migration_id(&self) -> MigrationId {
+        MigrationId(self.id)
+    }
+}
+
+const CREATE_FAILURES_TABLE: &str = r#"
+create table if not exists schema_migration_failures (
+    failure_id bigserial primary key,
+    id bigint not null,
+    name text not null,
+    error text not null,
+    failed_at timestamp not null default current_timestamp
+)
+"#;
+
+/// Save a record of this failure. Earlier failures of the same migration are kept.
+///
+/// The schema_migration_failures table is created if it doesn't exist yet.
+pub async fn record_failure(
+    conn: &mut PgConnection,
+    migration: &MigrationDirectory,
+    error: &str,
+) -> sqlx::Result<()> {
+    (&mut *conn).execute(CREATE_FAILURES_TABLE).await?;
+
+    let query = sqlx::query(
+        r#"
+        insert into schema_migration_failures (id, name, error)
+        values ($1, $2, $3)
+        "#,
+    )
+    .bind(migration.id.as_i64())
+    .bind(&migration.name)
+    .bind(error);
+
+    conn.execute(query).await?;
+    Ok(())
+}
+
+/// Load the most recent failure of each migration that has failed.
+pub async fn latest_failures(
+    conn: &mut PgConnection,
+) -> sqlx::Result<BTreeMap<MigrationId, FailureRecord>> {
+    let exists: (bool,) =
+        sqlx::query_as("select to_regclass('schema_migration_failures') is not null")
+            .fetch_one(&mut *conn)
+            .await?;
+
+    if !exists.0 {
+        return Ok(BTreeMap::new());
+    }
+
+    let rows: Vec<FailureRecord> = sqlx::query_as(
+        r#"
+        select distinct on (id) id, name, error, failed_at
+        from schema_migration_failures
+        order by id, failed_at desc, failure_id desc
+        "#,
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(rows.into_iter().map(|r| (r.migration_id(), r)).collect())
+}
+
+/// Remove the allowed migrations from the pending list, returning the ones that were skipped.
+///
+/// Only migrations with a recorded failure can be skipped, so a typo in the allowlist can't
+/// quietly skip a migration that has never been tried.
+pub fn skip_failed(
+    pending: &mut Vec<MigrationDirectory>,
+    failures: &BTreeMap<MigrationId, FailureRecord>,
+    allow: &[MigrationId],
+) -> Result<Vec<MigrationDirectory>, SkipFailedError> {
+    for id in allow {
+        if !pending.iter().any(|m| m.id == *id) {
+            return Err(SkipFailedError::NotPending(*id));
+        }
+        if !failures.contains_key(id) {
+            return Err(SkipFailedError::NoFailure(*id));
+        }
+    }
+
+    let (skipped, kept) = std::mem::take(pending)
+        .into_iter()
+        .partition(|m| allow.contains(&m.id));
+    *pending = kept;
+
+    Ok(skipped)
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum SkipFailedError {
+    #[error("cannot skip migration {0}: it isn't pending")]
+    NotPending(MigrationId),
+
+    #[error("cannot skip migration {0}: it has no recorded failure")]
+    NoFailure(MigrationId),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::MigrationIndex;
+    use crate::testing::*;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn skip_recorded_failures() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+        let two = index.create(fake_migration(2, "two")).unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        assert!(latest_failures(&mut conn).await.unwrap().is_empty());
+
+        record_failure(&mut conn, &one, "first").await.unwrap();
+        record_failure(&mut conn, &one, "second").await.unwrap();
+
+        let failures = latest_failures(&mut conn).await.unwrap();
+        assert_eq!(vec![&one.id], failures.keys().collect::<Vec<_>>());
+        assert_eq!("second", failures[&one.id].error);
+
+        let mut pending = vec![one.clone(), two.clone()];
+        match skip_failed(&mut pending, &failures, &[two.id]) {
+            Err(SkipFailedError::NoFailure(id)) => assert_eq!(two.id, id),
+
+            Err(err) => panic!("Unexpected error: {:?}", err),
+            Ok(skipped) => panic!("Unexpected success: {:?}", skipped),
+        }
+
+        let skipped = skip_failed(&mut pending, &failures, &[one.id]).unwrap();
+        assert_eq!(vec![one], skipped);
+        assert_eq!(vec![two], pending);
+    }
+}
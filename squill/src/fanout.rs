@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+
+use futures::stream::{self, StreamExt};
+
+use crate::config::Config;
+use crate::report::MigrateReport;
+use crate::{error_chain, migrate_all, MigrateAllError};
+
+/// A named database to run migrations against, such as one tenant in a multi-tenant deployment.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub name: String,
+    pub config: Config,
+}
+
+#[derive(Debug)]
+pub struct TargetOutcome {
+    pub name: String,
+    pub result: Result<MigrateReport, MigrateAllError>,
+}
+
+impl TargetOutcome {
+    pub fn is_success(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+/// The combined results of running migrations on every target.
+///
+/// Outcomes are in the same order as the targets were given, regardless of which ones finished
+/// first.
+#[derive(Debug)]
+pub struct TargetReport {
+    pub outcomes: Vec<TargetOutcome>,
+}
+
+impl TargetReport {
+    pub fn succeeded(&self) -> impl Iterator<Item = &TargetOutcome> {
+        self.outcomes.iter().filter(|o| o.is_success())
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &TargetOutcome> {
+        self.outcomes.iter().filter(|o| !o.is_success())
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.outcomes.iter().all(|o| o.is_success())
+    }
+}
+
+/// One target for each of the configured databases, in name order.
+///
+/// Each target uses the rest of the config as-is, except that the state file is turned off. It only
+/// caches one database's migration log, so the targets would overwrite each other's. The
+/// expected database name and server fingerprint are for the main database, so they're cleared
+/// too.
+pub fn database_targets(config: &Config) -> Vec<Target> {
+    config
+        .databases
+        .iter()
+        .map(|(name, opts)| {
+            let mut target = config.clone();
+            target.database_connect_options = Some(opts.clone());
+            target.databases = BTreeMap::new();
+            target.state_file = None;
+            target.expected_database_name = None;
+            target.expected_server_fingerprint = None;
+
+            Target {
+                name: name.clone(),
+                config: target,
+            }
+        })
+        .collect()
+}
+
+/// Apply pending migrations to each target, running at most `concurrency` targets at a time.
+///
+/// A failure on one target does not stop the others. Each target's result is recorded in the
+/// returned report instead.
+pub async fn migrate_targets(
+    targets: impl IntoIterator<Item = Target>,
+    concurrency: usize,
+) -> TargetReport {
+    let mut outcomes: Vec<(usize, TargetOutcome)> = stream::iter(targets.into_iter().enumerate())
+        .map(|(i, target)| async move {
+            tracing::info!("Migrating target: {}", target.name);
+            let result = migrate_all(&target.config).await;
+
+            if let Err(err) = &result {
+                tracing::warn!(
+                    "Failed to migrate target: {}: {}",
+                    target.name,
+                    error_chain(err)
+                );
+            }
+
+            let outcome = TargetOutcome {
+                name: target.name,
+                result,
+            };
+            (i, outcome)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+
+    outcomes.sort_by_key(|(i, _)| *i);
+
+    TargetReport {
+        outcomes: outcomes.into_iter().map(|(_, o)| o).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+    use crate::MigrationIndex;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn isolated_failures() {
+        let good = TestEnv::initialized().await.unwrap();
+        let bad = TestEnv::new().await.unwrap();
+
+        let mut index = MigrationIndex::new(&good.config().migrations_dir).unwrap();
+        let _ = index.create(fake_migration(1, "one")).unwrap();
+
+        let mut bad_config = bad.config();
+        bad_config.database_connect_options = bad_config
+            .database_connect_options
+            .map(|opts| opts.database("__not_a_squill_test"));
+
+        let targets = vec![
+            Target {
+                name: String::from("bad"),
+                config: bad_config,
+            },
+            Target {
+                name: String::from("good"),
+                config: good.config(),
+            },
+        ];
+
+        let report = migrate_targets(targets, 2).await;
+
+        assert!(!report.is_success());
+
+        let names: Vec<_> = report.outcomes.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(vec!["bad", "good"], names);
+
+        let failed: Vec<_> = report.failed().map(|o| o.name.as_str()).collect();
+        assert_eq!(vec!["bad"], failed);
+
+        let good = report.succeeded().next().unwrap();
+        assert_eq!(1, good.result.as_ref().unwrap().applied.len());
+    }
+
+    #[tokio::test]
+    async fn configured_databases() {
+        let env = TestEnv::new().await.unwrap();
+        let mut config = env.config();
+
+        let server = config.database_connect_options.clone().unwrap();
+        config.databases = BTreeMap::from([
+            (
+                String::from("tenant_b"),
+                server.clone().database("tenant_b"),
+            ),
+            (String::from("tenant_a"), server.database("tenant_a")),
+        ]);
+        config.state_file = Some("squill-state.json".into());
+
+        let targets = database_targets(&config);
+
+        let names: Vec<_> = targets.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(vec!["tenant_a", "tenant_b"], names);
+
+        for target in &targets {
+            let opts = target.config.database_connect_options.as_ref().unwrap();
+            assert_eq!(Some(target.name.as_str()), opts.get_database());
+            assert!(target.config.databases.is_empty());
+            assert_eq!(None, target.config.state_file);
+        }
+    }
+
+    #[tokio::test]
+    async fn zero_concurrency() {
+        let env = TestEnv::initialized().await.unwrap();
+
+        let targets = vec![Target {
+            name: String::from("only"),
+            config: env.config(),
+        }];
+
+        let report = migrate_targets(targets, 0).await;
+        assert!(report.is_success());
+    }
+}
@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, PgConnection};
+
+use crate::migrate::MigrationDirectory;
+
+/// A step to run around migrations: either SQL on the migration connection or a shell command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Hook {
+    Sql(String),
+    Command(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Hooks {
+    /// Run once before applying any migrations.
+    pub before_migrate: Vec<Hook>,
+
+    /// Run after each migration is applied.
+    pub after_each: Vec<Hook>,
+
+    /// Run once after all migrations have been applied.
+    pub after_all: Vec<Hook>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum HookEvent<'a> {
+    BeforeMigrate { pending: &'a [MigrationDirectory] },
+    AfterEach { migration: &'a MigrationDirectory },
+    AfterAll { applied: &'a [MigrationDirectory] },
+}
+
+impl HookEvent<'_> {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HookEvent::BeforeMigrate { .. } => "before_migrate",
+            HookEvent::AfterEach { .. } => "after_each",
+            HookEvent::AfterAll { .. } => "after_all",
+        }
+    }
+
+    fn env(&self) -> Vec<(&'static str, String)> {
+        let ids = |migrations: &[MigrationDirectory]| {
+            let ids: Vec<_> = migrations.iter().map(|m| m.id.to_string()).collect();
+            ids.join(",")
+        };
+
+        let mut env = vec![("SQUILL_HOOK", self.name().to_owned())];
+
+        match self {
+            HookEvent::BeforeMigrate { pending } => {
+                env.push(("SQUILL_PENDING_COUNT", pending.len().to_string()));
+                env.push(("SQUILL_PENDING_IDS", ids(pending)));
+            }
+            HookEvent::AfterEach { migration } => {
+                env.push(("SQUILL_MIGRATION_ID", migration.id.to_string()));
+                env.push(("SQUILL_MIGRATION_NAME", migration.name.clone()));
+                env.push((
+                    "SQUILL_MIGRATION_DIR",
+                    migration.dir.to_string_lossy().into_owned(),
+                ));
+            }
+            HookEvent::AfterAll { applied } => {
+                env.push(("SQUILL_APPLIED_COUNT", applied.len().to_string()));
+                env.push(("SQUILL_APPLIED_IDS", ids(applied)));
+            }
+        }
+
+        env
+    }
+}
+
+impl Hooks {
+    pub fn for_event(&self, event: &HookEvent) -> &[Hook] {
+        match event {
+            HookEvent::BeforeMigrate { .. } => &self.before_migrate,
+            HookEvent::AfterEach { .. } => &self.after_each,
+            HookEvent::AfterAll { .. } => &self.after_all,
+        }
+    }
+
+    /// Run every hook configured for this event, stopping at the first failure.
+    ///
+    /// Commands are run synchronously, so this blocks the current thread until each one exits.
+    pub async fn run(
+        &self,
+        conn: &mut PgConnection,
+        event: HookEvent<'_>,
+    ) -> Result<(), HookError> {
+        for hook in self.for_event(&event) {
+            tracing::info!("Running {} hook: {:?}", event.name(), hook);
+
+            match hook {
+                Hook::Sql(sql) => {
+                    (&mut *conn)
+                        .execute(sql.as_str())
+                        .await
+                        .map_err(|err| HookError::Sql {
+                            event: event.name(),
+                            err,
+                        })?;
+                }
+                Hook::Command(command) => run_command(command, &event)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn run_command(command: &str, event: &HookEvent) -> Result<(), HookError> {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = std::process::Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    } else {
+        let mut cmd = std::process::Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    };
+
+    cmd.envs(event.env());
+
+    let status = cmd.status().map_err(|err| HookError::Spawn {
+        event: event.name(),
+        command: command.to_owned(),
+        err,
+    })?;
+
+    if !status.success() {
+        return Err(HookError::Command {
+            event: event.name(),
+            command: command.to_owned(),
+            status,
+        });
+    }
+
+    Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum HookError {
+    #[error("failed to run {event} hook SQL")]
+    Sql {
+        event: &'static str,
+        #[source]
+        err: sqlx::Error,
+    },
+
+    #[error("failed to start {event} hook command: {command:?}")]
+    Spawn {
+        event: &'static str,
+        command: String,
+        #[source]
+        err: std::io::Error,
+    },
+
+    #[error("{event} hook command failed: {command:?}: {status}")]
+    Command {
+        event: &'static str,
+        command: String,
+        status: std::process::ExitStatus,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::MigrationIndex;
+    use crate::migrate_all;
+    use crate::testing::*;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn sql_hooks() {
+        let env = TestEnv::initialized().await.unwrap();
+        let mut config = env.config();
+
+        config.hooks = Hooks {
+            before_migrate: vec![Hook::Sql(String::from(
+                "create table hook_log (event text)",
+            ))],
+            after_each: vec![Hook::Sql(String::from(
+                "insert into hook_log values ('after_each')",
+            ))],
+            after_all: vec![Hook::Sql(String::from(
+                "insert into hook_log values ('after_all')",
+            ))],
+        };
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let _ = index.create(fake_migration(1, "one")).unwrap();
+        let _ = index.create(fake_migration(2, "two")).unwrap();
+
+        migrate_all(&config).await.unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        let events: Vec<(String,)> = sqlx::query_as("select event from hook_log")
+            .fetch_all(&mut conn)
+            .await
+            .unwrap();
+        let events: Vec<_> = events.into_iter().map(|(e,)| e).collect();
+
+        assert_eq!(vec!["after_each", "after_each", "after_all"], events);
+
+        // Nothing is pending, so the hooks don't run again (and recreating the table would fail).
+        migrate_all(&config).await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn command_hook_env() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("hook.txt");
+
+        let hooks = Hooks {
+            after_each: vec![Hook::Command(format!(
+                "echo \"$SQUILL_HOOK $SQUILL_MIGRATION_ID $SQUILL_MIGRATION_NAME\" > {}",
+                out.to_string_lossy()
+            ))],
+            ..Default::default()
+        };
+
+        let mut conn = config.connect().await.unwrap();
+        hooks
+            .run(&mut conn, HookEvent::AfterEach { migration: &one })
+            .await
+            .unwrap();
+
+        let actual = std::fs::read_to_string(out).unwrap();
+        assert_eq!("after_each 1 one\n", actual);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn command_hook_failure() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let hooks = Hooks {
+            after_all: vec![Hook::Command(String::from("exit 3"))],
+            ..Default::default()
+        };
+
+        let mut conn = config.connect().await.unwrap();
+        match hooks
+            .run(&mut conn, HookEvent::AfterAll { applied: &[] })
+            .await
+        {
+            Err(HookError::Command { status, .. }) => assert_eq!(Some(3), status.code()),
+
+            Err(err) => panic!("Unexpected error: {:?}", err),
+            Ok(_) => panic!("Unexpected success"),
+        }
+    }
+}
@@ -0,0 +1,621 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use sqlx::PgConnection;
+
+use crate::index::MigrationParams;
+use crate::migrate::MigrationId;
+use crate::slugify;
+
+/// Another migration tool whose layout can be imported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// `V<version>__<description>.sql`, with optional `U<version>__<description>.sql` undo files.
+    Flyway,
+
+    /// `<version>_<title>.up.sql` and `<version>_<title>.down.sql`.
+    GolangMigrate,
+
+    /// `<version>_<name>.sql`, with `-- migrate:up` and `-- migrate:down` sections.
+    Dbmate,
+}
+
+impl ImportFormat {
+    /// The table the tool records applied migrations in, unless it was configured otherwise.
+    pub fn history_table(&self) -> &'static str {
+        match self {
+            ImportFormat::Flyway => "flyway_schema_history",
+            ImportFormat::GolangMigrate => "schema_migrations",
+            ImportFormat::Dbmate => "schema_migrations",
+        }
+    }
+}
+
+impl std::fmt::Display for ImportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportFormat::Flyway => write!(f, "flyway"),
+            ImportFormat::GolangMigrate => write!(f, "golang-migrate"),
+            ImportFormat::Dbmate => write!(f, "dbmate"),
+        }
+    }
+}
+
+/// A migration read from another tool's files, ready to be created as a Squill migration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedMigration {
+    pub id: MigrationId,
+    pub name: String,
+    pub up_sql: String,
+    pub down_sql: Option<String>,
+
+    /// The files this was read from.
+    pub sources: Vec<PathBuf>,
+}
+
+impl ImportedMigration {
+    pub fn params(&self) -> MigrationParams {
+        MigrationParams {
+            id: self.id,
+            name: self.name.clone(),
+            up_sql: self.up_sql.clone(),
+            down_sql: self.down_sql.clone(),
+        }
+    }
+}
+
+/// Read the migrations in another tool's migrations directory, in ID order.
+///
+/// Files that don't look like migrations for that tool are skipped. Flyway's repeatable
+/// migrations (`R__<description>.sql`) have no version, so they're skipped with a warning.
+pub fn read_migrations(
+    format: ImportFormat,
+    dir: &Path,
+) -> Result<Vec<ImportedMigration>, ImportError> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(|err| ImportError::ReadDir {
+        path: dir.to_path_buf(),
+        err,
+    })? {
+        let entry = entry.map_err(|err| ImportError::ReadDir {
+            path: dir.to_path_buf(),
+            err,
+        })?;
+        let path = entry.path();
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    let mut migrations = match format {
+        ImportFormat::Flyway => read_flyway(&files)?,
+        ImportFormat::GolangMigrate => read_golang_migrate(&files)?,
+        ImportFormat::Dbmate => read_dbmate(&files)?,
+    };
+    migrations.sort_by_key(|m| m.id);
+
+    Ok(migrations)
+}
+
+/// A file that's one half of a migration, before it's paired up with the other half.
+struct Part {
+    id: MigrationId,
+    name: String,
+    path: PathBuf,
+    sql: String,
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default()
+}
+
+fn read_part(path: &Path, version: &str, name: &str) -> Result<Part, ImportError> {
+    let id = version.parse().map_err(|_| ImportError::Version {
+        path: path.to_path_buf(),
+        version: version.to_owned(),
+    })?;
+
+    let sql = std::fs::read_to_string(path).map_err(|err| ImportError::Read {
+        path: path.to_path_buf(),
+        err,
+    })?;
+
+    Ok(Part {
+        id,
+        name: slugify(name),
+        path: path.to_path_buf(),
+        sql,
+    })
+}
+
+fn imported_header(path: &Path) -> String {
+    format!("-- Imported from {}\n", file_name(path))
+}
+
+/// Pair up the up and down files, which share a version.
+fn pair(ups: Vec<Part>, downs: Vec<Part>) -> Result<Vec<ImportedMigration>, ImportError> {
+    let mut migrations: BTreeMap<MigrationId, ImportedMigration> = BTreeMap::new();
+
+    for up in ups {
+        if let Some(existing) = migrations.get(&up.id) {
+            return Err(ImportError::Duplicate {
+                id: up.id,
+                paths: vec![existing.sources[0].clone(), up.path],
+            });
+        }
+
+        migrations.insert(
+            up.id,
+            ImportedMigration {
+                id: up.id,
+                name: up.name,
+                up_sql: imported_header(&up.path) + &up.sql,
+                down_sql: None,
+                sources: vec![up.path],
+            },
+        );
+    }
+
+    for down in downs {
+        let Some(migration) = migrations.get_mut(&down.id) else {
+            return Err(ImportError::MissingUp(down.path));
+        };
+
+        if migration.down_sql.is_some() {
+            return Err(ImportError::Duplicate {
+                id: down.id,
+                paths: vec![migration.sources[1].clone(), down.path],
+            });
+        }
+
+        migration.down_sql = Some(imported_header(&down.path) + &down.sql);
+        migration.sources.push(down.path);
+    }
+
+    Ok(migrations.into_values().collect())
+}
+
+fn read_flyway(files: &[PathBuf]) -> Result<Vec<ImportedMigration>, ImportError> {
+    lazy_static! {
+        static ref RE_FLYWAY: Regex =
+            Regex::new(r"^(?P<kind>[VU])(?P<version>[^_]+)__(?P<name>.+)\.sql$")
+                .expect("static pattern");
+    }
+
+    let mut ups = Vec::new();
+    let mut downs = Vec::new();
+
+    for path in files {
+        let file_name = file_name(path);
+
+        if file_name.starts_with("R__") && file_name.ends_with(".sql") {
+            tracing::warn!(
+                "Skipping repeatable migration, which Squill doesn't support: {}",
+                path.to_string_lossy()
+            );
+            continue;
+        }
+
+        let Some(caps) = RE_FLYWAY.captures(file_name) else {
+            continue;
+        };
+
+        let part = read_part(path, &caps["version"], &caps["name"])?;
+        match &caps["kind"] {
+            "V" => ups.push(part),
+            _ => downs.push(part),
+        }
+    }
+
+    pair(ups, downs)
+}
+
+fn read_golang_migrate(files: &[PathBuf]) -> Result<Vec<ImportedMigration>, ImportError> {
+    lazy_static! {
+        static ref RE_GOLANG_MIGRATE: Regex =
+            Regex::new(r"^(?P<version>[0-9]+)_(?P<name>.+)\.(?P<direction>up|down)\.sql$")
+                .expect("static pattern");
+    }
+
+    let mut ups = Vec::new();
+    let mut downs = Vec::new();
+
+    for path in files {
+        let Some(caps) = RE_GOLANG_MIGRATE.captures(file_name(path)) else {
+            continue;
+        };
+
+        let part = read_part(path, &caps["version"], &caps["name"])?;
+        match &caps["direction"] {
+            "up" => ups.push(part),
+            _ => downs.push(part),
+        }
+    }
+
+    pair(ups, downs)
+}
+
+fn read_dbmate(files: &[PathBuf]) -> Result<Vec<ImportedMigration>, ImportError> {
+    lazy_static! {
+        static ref RE_DBMATE: Regex =
+            Regex::new(r"^(?P<version>[0-9]+)_(?P<name>.+)\.sql$").expect("static pattern");
+    }
+
+    let mut migrations = Vec::new();
+    let mut seen: BTreeMap<MigrationId, PathBuf> = BTreeMap::new();
+
+    for path in files {
+        let Some(caps) = RE_DBMATE.captures(file_name(path)) else {
+            continue;
+        };
+
+        let part = read_part(path, &caps["version"], &caps["name"])?;
+        if let Some(existing) = seen.insert(part.id, part.path.clone()) {
+            return Err(ImportError::Duplicate {
+                id: part.id,
+                paths: vec![existing, part.path],
+            });
+        }
+
+        let sections = dbmate_sections(&part.sql);
+        let Some(up) = sections.up else {
+            return Err(ImportError::MissingUp(part.path));
+        };
+
+        let header = imported_header(&part.path);
+        let up_sql = up.to_sql(&header, part.id, &part.name, Direction::Up);
+        let down_sql = sections
+            .down
+            .filter(|down| !down.sql.trim().is_empty())
+            .map(|down| down.to_sql(&header, part.id, &part.name, Direction::Down));
+
+        migrations.push(ImportedMigration {
+            id: part.id,
+            name: part.name,
+            up_sql,
+            down_sql,
+            sources: vec![part.path],
+        });
+    }
+
+    Ok(migrations)
+}
+
+#[derive(Debug, Default)]
+struct DbmateSections<'a> {
+    up: Option<DbmateSection<'a>>,
+    down: Option<DbmateSection<'a>>,
+}
+
+#[derive(Debug)]
+struct DbmateSection<'a> {
+    sql: &'a str,
+    transaction: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Direction {
+    Up,
+    Down,
+}
+
+impl DbmateSection<'_> {
+    /// The SQL for a Squill migration file.
+    ///
+    /// dbmate's `transaction:false` option becomes the no-transaction directive, which means the
+    /// migration has to record (or unrecord) itself in the migration log.
+    fn to_sql(&self, header: &str, id: MigrationId, name: &str, direction: Direction) -> String {
+        let sql = self.sql.trim();
+        if self.transaction {
+            return format!("{header}\n{sql}\n");
+        }
+
+        let record = match direction {
+            Direction::Up => format!("select _squill_claim_migration({id}, '{name}');"),
+            Direction::Down => format!("select _squill_unclaim_migration({id});"),
+        };
+        format!("{header}--squill:no-transaction\n\n{sql}\n\n{record}\n")
+    }
+}
+
+fn dbmate_sections(sql: &str) -> DbmateSections<'_> {
+    lazy_static! {
+        static ref RE_MARKER: Regex =
+            Regex::new(r"(?m)^--\s*migrate:(?P<direction>up|down)(?P<options>[^\n]*)$")
+                .expect("static pattern");
+    }
+
+    let markers: Vec<_> = RE_MARKER.captures_iter(sql).collect();
+    let mut sections = DbmateSections::default();
+
+    for (i, caps) in markers.iter().enumerate() {
+        let marker = caps.get(0).expect("whole match");
+        let end = markers
+            .get(i + 1)
+            .map(|next| next.get(0).expect("whole match").start())
+            .unwrap_or(sql.len());
+
+        let section = DbmateSection {
+            sql: &sql[marker.end()..end],
+            transaction: !caps["options"]
+                .split_whitespace()
+                .any(|opt| opt == "transaction:false"),
+        };
+
+        match &caps["direction"] {
+            "up" => sections.up = Some(section),
+            _ => sections.down = Some(section),
+        }
+    }
+
+    sections
+}
+
+/// The imported migrations that the other tool recorded as applied in its history table.
+///
+/// Flyway and dbmate list each applied version. golang-migrate only keeps the latest version, so
+/// every migration up to that one counts as applied, unless the table is marked dirty from a
+/// failed run.
+pub async fn applied_in_history(
+    conn: &mut PgConnection,
+    format: ImportFormat,
+    table: &str,
+    migrations: &[ImportedMigration],
+) -> Result<BTreeSet<MigrationId>, ImportError> {
+    lazy_static! {
+        static ref RE_TABLE: Regex =
+            Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*(\.[A-Za-z_][A-Za-z0-9_]*)?$")
+                .expect("static pattern");
+    }
+
+    if !RE_TABLE.is_match(table) {
+        return Err(ImportError::TableName(table.to_owned()));
+    }
+
+    if table == "schema_migrations" || table.ends_with(".schema_migrations") {
+        return Err(ImportError::TableConflict(table.to_owned()));
+    }
+
+    let query_error = |err| ImportError::History {
+        table: table.to_owned(),
+        err,
+    };
+
+    // The table name was checked above, so interpolating it is okay.
+    let versions: Vec<String> = match format {
+        ImportFormat::Flyway => sqlx::query_scalar(&format!(
+            "select version from {table} where success and version is not null"
+        ))
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(query_error)?,
+        ImportFormat::Dbmate => sqlx::query_scalar(&format!("select version::text from {table}"))
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(query_error)?,
+        ImportFormat::GolangMigrate => {
+            let row: Option<(i64, bool)> =
+                sqlx::query_as(&format!("select version, dirty from {table}"))
+                    .fetch_optional(&mut *conn)
+                    .await
+                    .map_err(query_error)?;
+
+            return match row {
+                None => Ok(BTreeSet::new()),
+                Some((version, true)) => Err(ImportError::Dirty(version)),
+                Some((version, false)) => Ok(migrations
+                    .iter()
+                    .map(|m| m.id)
+                    .filter(|id| id.as_i64() <= version)
+                    .collect()),
+            };
+        }
+    };
+
+    let mut applied = BTreeSet::new();
+    for version in versions {
+        let id: MigrationId = version
+            .parse()
+            .map_err(|_| ImportError::HistoryVersion(version.clone()))?;
+        applied.insert(id);
+    }
+
+    Ok(migrations
+        .iter()
+        .map(|m| m.id)
+        .filter(|id| applied.contains(id))
+        .collect())
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum ImportError {
+    #[error("failed to read directory: {}", .path.to_string_lossy())]
+    ReadDir {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+
+    #[error("failed to read file: {}", .path.to_string_lossy())]
+    Read {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+
+    #[error("migration version must be a non-negative integer: {version:?} in {}", .path.to_string_lossy())]
+    Version { path: PathBuf, version: String },
+
+    #[error("more than one file for version {id}: {}", .paths.iter().map(|p| p.to_string_lossy()).collect::<Vec<_>>().join(", "))]
+    Duplicate {
+        id: MigrationId,
+        paths: Vec<PathBuf>,
+    },
+
+    #[error("no up migration for {}", .0.to_string_lossy())]
+    MissingUp(PathBuf),
+
+    #[error("invalid history table name: {0:?}")]
+    TableName(String),
+
+    #[error("the {0} table has the same name as Squill's migration log: rename it first (like `alter table schema_migrations rename to old_schema_migrations`) and pass the new name")]
+    TableConflict(String),
+
+    #[error("failed to read history table {table}")]
+    History {
+        table: String,
+        #[source]
+        err: sqlx::Error,
+    },
+
+    #[error("history table has a version Squill can't use as a migration ID: {0:?}")]
+    HistoryVersion(String),
+
+    #[error("history table is dirty at version {0}: fix the failed migration before importing")]
+    Dirty(i64),
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::Executor;
+
+    use crate::testing::*;
+
+    use super::*;
+
+    fn write_files(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, contents) in files {
+            std::fs::write(dir.path().join(name), contents).unwrap();
+        }
+        dir
+    }
+
+    fn summary(migrations: &[ImportedMigration]) -> Vec<(i64, &str, bool)> {
+        migrations
+            .iter()
+            .map(|m| (m.id.as_i64(), m.name.as_str(), m.down_sql.is_some()))
+            .collect()
+    }
+
+    #[test]
+    fn read_layouts() {
+        let flyway = write_files(&[
+            ("V1__Create_users.sql", "create table users (id int);"),
+            ("V2__Add_email.sql", "alter table users add email text;"),
+            ("U2__Add_email.sql", "alter table users drop email;"),
+            ("R__refresh_views.sql", "select 1;"),
+            ("README.md", "not a migration"),
+        ]);
+        let migrations = read_migrations(ImportFormat::Flyway, flyway.path()).unwrap();
+        assert_eq!(
+            vec![(1, "create_users", false), (2, "add_email", true)],
+            summary(&migrations)
+        );
+        assert!(migrations[0]
+            .up_sql
+            .starts_with("-- Imported from V1__Create_users.sql\n"));
+
+        let golang = write_files(&[
+            ("0001_create_users.up.sql", "create table users (id int);"),
+            ("0001_create_users.down.sql", "drop table users;"),
+            ("0002_add_email.up.sql", "alter table users add email text;"),
+        ]);
+        let migrations = read_migrations(ImportFormat::GolangMigrate, golang.path()).unwrap();
+        assert_eq!(
+            vec![(1, "create_users", true), (2, "add_email", false)],
+            summary(&migrations)
+        );
+
+        let dbmate = write_files(&[
+            (
+                "20240101000000_create_users.sql",
+                "-- migrate:up\ncreate table users (id int);\n\n-- migrate:down\ndrop table users;\n",
+            ),
+            (
+                "20240102000000_index_users.sql",
+                "-- migrate:up transaction:false\ncreate index concurrently users_id on users (id);\n\n-- migrate:down\n",
+            ),
+        ]);
+        let migrations = read_migrations(ImportFormat::Dbmate, dbmate.path()).unwrap();
+        assert_eq!(
+            vec![
+                (20240101000000, "create_users", true),
+                (20240102000000, "index_users", false)
+            ],
+            summary(&migrations)
+        );
+        assert!(migrations[0].up_sql.contains("create table users"));
+        assert!(!migrations[0].up_sql.contains("drop table users"));
+        assert!(migrations[1]
+            .up_sql
+            .contains("--squill:no-transaction\n\ncreate index concurrently"));
+        assert!(migrations[1]
+            .up_sql
+            .contains("select _squill_claim_migration(20240102000000, 'index_users');"));
+
+        let dotted = write_files(&[("V1.1__patch.sql", "select 1;")]);
+        assert!(matches!(
+            read_migrations(ImportFormat::Flyway, dotted.path()),
+            Err(ImportError::Version { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn flyway_history() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+
+        let files = write_files(&[
+            ("V1__one.sql", "select 1;"),
+            ("V2__two.sql", "select 2;"),
+            ("V3__three.sql", "select 3;"),
+        ]);
+        let migrations = read_migrations(ImportFormat::Flyway, files.path()).unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        conn.execute(
+            "create table flyway_schema_history (version text, success boolean);
+            insert into flyway_schema_history values (null, true), ('1', true), ('2', false);",
+        )
+        .await
+        .unwrap();
+
+        let applied = applied_in_history(
+            &mut conn,
+            ImportFormat::Flyway,
+            "flyway_schema_history",
+            &migrations,
+        )
+        .await
+        .unwrap();
+        assert_eq!(BTreeSet::from([MigrationId(1)]), applied);
+
+        assert!(matches!(
+            applied_in_history(
+                &mut conn,
+                ImportFormat::Flyway,
+                "schema_migrations",
+                &migrations
+            )
+            .await,
+            Err(ImportError::TableConflict(_))
+        ));
+        assert!(matches!(
+            applied_in_history(
+                &mut conn,
+                ImportFormat::Flyway,
+                "x; drop table y",
+                &migrations
+            )
+            .await,
+            Err(ImportError::TableName(_))
+        ));
+    }
+}
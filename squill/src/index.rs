@@ -1,19 +1,58 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::io::Write;
+use std::ops::RangeBounds;
 use std::path::{Path, PathBuf};
 
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::archive::ArchiveError;
+use crate::db::MigrationLog;
+use crate::migrate::{retired_by, squashed_ids, with_suffix, SINGLE_FILE_SUFFIXES};
+use crate::naming::MigrationNaming;
+use crate::sql::split_statements;
 use crate::{MigrationDirectory, MigrationId};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MigrationIndex {
     pub(crate) dir: PathBuf,
     pub(crate) index: BTreeMap<MigrationId, MigrationDirectory>,
+    pub(crate) naming: MigrationNaming,
+
+    /// The archive that the primary directory was unpacked from, if it's one.
+    pub(crate) archive: Option<PathBuf>,
 }
 
 impl MigrationIndex {
     pub fn new(migrations_dir: &Path) -> Result<Self, IndexError> {
-        let available = available_migrations(migrations_dir)?;
+        Self::from_dirs(migrations_dir, &[])
+    }
+
+    /// Merge the migrations from several directories into one index.
+    ///
+    /// New migrations are created in the primary directory. An ID that appears in more than one
+    /// directory is an error, just like a duplicate within a single directory.
+    #[tracing::instrument(
+        name = "migration_index",
+        skip_all,
+        fields(dir = %primary.to_string_lossy(), extra_dirs = extra.len()),
+    )]
+    pub fn from_dirs(primary: &Path, extra: &[PathBuf]) -> Result<Self, IndexError> {
+        Self::from_dirs_named(primary, extra, &MigrationNaming::default())
+    }
+
+    /// Like [`from_dirs`](Self::from_dirs), for migrations named with this pattern instead of the
+    /// default `{id}-{name}`. New migrations are created with the same pattern.
+    pub fn from_dirs_named(
+        primary: &Path,
+        extra: &[PathBuf],
+        naming: &MigrationNaming,
+    ) -> Result<Self, IndexError> {
+        let mut available = available_migrations(primary, naming)?;
+        for dir in extra {
+            available.extend(available_migrations(dir, naming)?);
+        }
 
         let mut multi_index: BTreeMap<MigrationId, Vec<MigrationDirectory>> = BTreeMap::new();
         for m in available {
@@ -33,8 +72,10 @@ impl MigrationIndex {
 
         if multiples.is_empty() {
             Ok(Self {
-                dir: migrations_dir.to_path_buf(),
+                dir: primary.to_path_buf(),
                 index,
+                naming: naming.clone(),
+                archive: None,
             })
         } else {
             Err(IndexError::MultipleMigrationDirectories(multiples))
@@ -48,15 +89,69 @@ impl MigrationIndex {
     pub fn iter(&self) -> impl Iterator<Item = &MigrationDirectory> {
         self.index.values()
     }
+
+    /// The migrations with IDs in this range, in order.
+    pub fn range<R>(&self, ids: R) -> impl Iterator<Item = &MigrationDirectory>
+    where
+        R: RangeBounds<MigrationId>,
+    {
+        self.index.range(ids).map(|(_, m)| m)
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// The migration with the highest ID.
+    pub fn latest(&self) -> Option<&MigrationDirectory> {
+        self.index.values().next_back()
+    }
+
+    /// The ID for a new migration created right now with this strategy.
+    pub fn next_id(&self, strategy: IdStrategy) -> MigrationId {
+        strategy.next_id(self, OffsetDateTime::now_utc())
+    }
+
+    /// The migrations that are missing files they need to run.
+    ///
+    /// Only the up file is required, since migrations without a down file are forward-only.
+    pub fn validate(&self) -> Vec<BrokenMigration> {
+        self.iter()
+            .filter(|m| m.is_broken())
+            .map(|m| BrokenMigration {
+                migration: m.clone(),
+                missing: vec![m.up_path.clone()],
+            })
+            .collect()
+    }
+}
+
+/// A migration that can't be applied because some of its files are missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokenMigration {
+    pub migration: MigrationDirectory,
+    pub missing: Vec<PathBuf>,
 }
 
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum IndexError {
-    #[error("failed to read directory: {path}: {err}")]
-    ReadDir { path: PathBuf, err: std::io::Error },
+    #[error("failed to read directory: {path}")]
+    ReadDir {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
 
     #[error("multiple directories found for some migration IDs: (count={})", .0.len())]
     MultipleMigrationDirectories(BTreeMap<MigrationId, Vec<MigrationDirectory>>),
+
+    #[error(transparent)]
+    Archive(ArchiveError),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -64,7 +159,9 @@ pub struct MigrationParams {
     pub id: MigrationId,
     pub name: String,
     pub up_sql: String,
-    pub down_sql: String,
+
+    /// The down migration, or None for a forward-only migration without a down file.
+    pub down_sql: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -78,11 +175,17 @@ impl MigrationIndex {
         &mut self,
         params: MigrationParams,
     ) -> Result<MigrationDirectory, CreateMigrationError> {
+        if let Some(archive) = &self.archive {
+            return Err(CreateMigrationError::Archive(archive.clone()));
+        }
+
         if let Some(migration) = self.index.get(&params.id) {
             return Err(CreateMigrationError::ExistingDirectory(migration.clone()));
         }
 
-        let dir = self.dir.join(format!("{}-{}", params.id, params.name));
+        let dir = self
+            .dir
+            .join(self.naming.format(&params.id.to_string(), &params.name));
 
         let files = create_migration_files(&dir, params.up_sql, params.down_sql)
             .map_err(CreateMigrationError::Io)?;
@@ -102,12 +205,164 @@ impl MigrationIndex {
 }
 
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum CreateMigrationError {
     #[error(transparent)]
     Io(IoError),
 
     #[error("directory already exists for migration ID: {}", .0.dir.to_string_lossy())]
     ExistingDirectory(MigrationDirectory),
+
+    #[error("cannot create migrations in an archive: {}", .0.to_string_lossy())]
+    Archive(PathBuf),
+}
+
+impl MigrationIndex {
+    /// Replace every migration with an ID in this range with a single new one.
+    ///
+    /// The new migration runs all the up files in order (and the down files in reverse order).
+    /// It reuses the last ID in the range, so databases that already applied the whole range see
+    /// it as applied and are left alone. Its up.sql records the IDs it replaced in a
+    /// `--squill:squashes` directive so they aren't reported as missing.
+    pub fn squash(
+        &mut self,
+        first: MigrationId,
+        last: MigrationId,
+        name: &str,
+    ) -> Result<MigrationDirectory, SquashError> {
+        let squashed: Vec<MigrationDirectory> = self
+            .index
+            .range(first..=last)
+            .map(|(_, m)| m.clone())
+            .collect();
+
+        if squashed.len() < 2 {
+            return Err(SquashError::TooFew(squashed.len()));
+        }
+
+        let read = |path: &PathBuf| {
+            fs::read_to_string(path).map_err(|err| SquashError::Read {
+                path: path.clone(),
+                err,
+            })
+        };
+
+        let ids: Vec<_> = squashed.iter().map(|m| m.id.to_string()).collect();
+        let mut up_sql = format!("--squill:squashes {}\n", ids.join(","));
+        for m in &squashed {
+            append_squashed(&mut up_sql, m, &read(&m.up_path)?);
+        }
+
+        // Reversing only some of the squashed migrations would be worse than not reversing any.
+        let down_sql = if squashed.iter().all(|m| m.is_reversible()) {
+            let mut down_sql = String::new();
+            for m in squashed.iter().rev() {
+                append_squashed(&mut down_sql, m, &read(&m.down_path)?);
+            }
+            Some(down_sql)
+        } else {
+            None
+        };
+
+        for m in &squashed {
+            tracing::info!("Removing squashed migration: {}", m.dir.to_string_lossy());
+            remove_migration(m).map_err(|err| SquashError::Remove {
+                path: m.dir.clone(),
+                err,
+            })?;
+            self.index.remove(&m.id);
+        }
+
+        let params = MigrationParams {
+            id: squashed[squashed.len() - 1].id,
+            name: name.to_owned(),
+            up_sql,
+            down_sql,
+        };
+
+        self.create(params).map_err(SquashError::Create)
+    }
+
+    /// The IDs of migrations that were replaced by squashing.
+    ///
+    /// Files that can't be read are skipped, since this is only used to explain missing files.
+    pub fn squashed_ids(&self) -> BTreeSet<MigrationId> {
+        let mut ids = BTreeSet::new();
+
+        for m in self.iter() {
+            match fs::read_to_string(&m.up_path) {
+                Ok(sql) => ids.extend(squashed_ids(&sql)),
+                Err(err) => tracing::debug!("skipping unreadable migration: {}: {}", m, err),
+            }
+        }
+
+        ids
+    }
+
+    /// The retired migrations, mapped to the migrations that replaced them.
+    ///
+    /// Files that can't be read are treated as not retired, so they still fail loudly when
+    /// they're applied.
+    pub fn retired(&self) -> BTreeMap<MigrationId, MigrationId> {
+        let mut retired = BTreeMap::new();
+
+        for m in self.iter() {
+            match fs::read_to_string(&m.up_path) {
+                Ok(sql) => {
+                    if let Some(replacement) = retired_by(&sql) {
+                        retired.insert(m.id, replacement);
+                    }
+                }
+                Err(err) => tracing::debug!("skipping unreadable migration: {}: {}", m, err),
+            }
+        }
+
+        retired
+    }
+}
+
+fn append_squashed(buf: &mut String, migration: &MigrationDirectory, sql: &str) {
+    buf.push_str(&format!(
+        "\n-- Squashed from {}-{}\n",
+        migration.id, migration.name
+    ));
+    buf.push_str(sql);
+
+    if !buf.ends_with('\n') {
+        buf.push('\n');
+    }
+
+    // The last statement in a file doesn't need a semicolon, but it does once more SQL follows.
+    if split_statements(sql)
+        .last()
+        .is_some_and(|s| !s.ends_with(';'))
+    {
+        buf.push_str(";\n");
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum SquashError {
+    #[error("need at least two migrations to squash, but found {0} in that range")]
+    TooFew(usize),
+
+    #[error("failed to read migration file: {path}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+
+    #[error("failed to remove squashed migration: {path}")]
+    Remove {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+
+    #[error(transparent)]
+    Create(CreateMigrationError),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -116,26 +371,215 @@ pub struct Rename {
     pub to: PathBuf,
 }
 
+impl Rename {
+    /// Rename the migration directory, or each file of a single-file migration.
+    pub fn execute(&self) -> std::io::Result<()> {
+        if self.from.is_dir() {
+            return fs::rename(&self.from, &self.to);
+        }
+
+        for suffix in SINGLE_FILE_SUFFIXES {
+            let from = with_suffix(&self.from, suffix);
+            if from.exists() {
+                fs::rename(from, with_suffix(&self.to, suffix))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The ID width used by align-ids when there are no migrations to measure.
+pub const DEFAULT_ID_WIDTH: usize = 10;
+
 impl MigrationIndex {
+    /// The narrowest ID width that fits every migration, or [`DEFAULT_ID_WIDTH`] if there are no
+    /// migrations yet.
+    pub fn id_width(&self) -> usize {
+        self.iter()
+            .map(|m| m.id.width())
+            .max()
+            .unwrap_or(DEFAULT_ID_WIDTH)
+    }
+
+    /// Plan renames that pad every ID to the width of the longest one.
     pub fn align_ids(&self) -> Vec<Rename> {
-        let width = self.iter().map(|m| m.id.width()).max().unwrap_or(10);
+        self.align_ids_to(self.id_width())
+            .expect("the widest ID always fits")
+    }
 
-        let mut renames = Vec::new();
-        for m in self.iter() {
-            let old = m.dir.clone();
+    /// Plan renames that pad every ID to this width.
+    pub fn align_ids_to(&self, width: usize) -> Result<Vec<Rename>, AlignError> {
+        if let Some(m) = self.iter().find(|m| m.id.width() > width) {
+            return Err(AlignError::TooNarrow {
+                width,
+                id: m.id,
+                required: self.id_width(),
+            });
+        }
+
+        let renames = self
+            .iter()
+            .map(|m| Rename {
+                from: m.dir.clone(),
+                to: m.dir.with_file_name(m.aligned_name(width, &self.naming)),
+            })
+            .collect();
+
+        Ok(renames)
+    }
+}
+
+impl MigrationDirectory {
+    /// The directory name for this migration with its ID zero-padded to this width.
+    ///
+    /// IDs that are already wider than this are written in full.
+    pub fn aligned_name(&self, width: usize, naming: &MigrationNaming) -> String {
+        naming.format(&format!("{:0width$}", self.id.0), &self.name)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum AlignError {
+    #[error("ID width {width} is too narrow for migration {id} (need at least {required})")]
+    TooNarrow {
+        width: usize,
+        id: MigrationId,
+        required: usize,
+    },
+}
 
-            let new = m
-                .dir
-                .with_file_name(format!("{:0width$}-{}", m.id.0, m.name));
+impl MigrationIndex {
+    /// Plan renaming a migration's directory, keeping its ID written the same way.
+    pub fn rename(&self, id: MigrationId, new_name: &str) -> Result<Rename, RenameError> {
+        let Some(m) = self.get(id) else {
+            return Err(RenameError::NotFound(id));
+        };
 
-            renames.push(Rename { from: old, to: new });
+        if new_name.is_empty() || new_name.contains(['/', '\\']) {
+            return Err(RenameError::InvalidName(new_name.to_owned()));
         }
 
-        renames
+        // Keep any zero padding the directory already has.
+        let dir_name = m.dir.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        let id = match self.naming.parse(dir_name) {
+            Some(parsed) => parsed.id.to_owned(),
+            None => m.id.to_string(),
+        };
+
+        Ok(Rename {
+            from: m.dir.clone(),
+            to: m.dir.with_file_name(self.naming.format(&id, new_name)),
+        })
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum RenameError {
+    #[error("no migration found with ID {0}")]
+    NotFound(MigrationId),
+
+    #[error("invalid migration name: {0:?}")]
+    InvalidName(String),
+}
+
+/// A migration to delete from disk, planned by [`MigrationIndex::prune`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prune {
+    pub migration: MigrationDirectory,
+}
+
+impl Prune {
+    /// Delete the migration's directory (or its files, for a single-file migration).
+    pub fn execute(&self) -> std::io::Result<()> {
+        remove_migration(&self.migration)
+    }
+}
+
+impl MigrationIndex {
+    /// Plan deleting the migrations before this ID that every one of these migration logs has
+    /// already applied, so that old migrations don't pile up forever.
+    ///
+    /// The init migration is always kept, since new databases still need it. With
+    /// `retired_only`, only migrations that were replaced by a newer one (with the retired-by
+    /// directive) are deleted.
+    pub fn prune(
+        &self,
+        before: MigrationId,
+        logs: &[MigrationLog],
+        retired_only: bool,
+    ) -> Vec<Prune> {
+        let retired = self.retired();
+
+        self.range(MigrationId(1)..before)
+            .filter(|m| logs.iter().all(|log| log.get(m.id).is_some()))
+            .filter(|m| !retired_only || retired.contains_key(&m.id))
+            .map(|m| Prune {
+                migration: m.clone(),
+            })
+            .collect()
+    }
+}
+
+/// How `squill new` picks an ID when one isn't given.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdStrategy {
+    /// The current Unix timestamp in seconds.
+    #[default]
+    UnixSeconds,
+
+    /// The current Unix timestamp in milliseconds.
+    UnixMillis,
+
+    /// One more than the highest existing ID.
+    Sequential,
+
+    /// The current UTC date and time as digits, like 20240131123000.
+    Datetime,
+}
+
+impl IdStrategy {
+    pub fn next_id(&self, index: &MigrationIndex, now: OffsetDateTime) -> MigrationId {
+        let now = now.to_offset(time::UtcOffset::UTC);
+
+        let id = match self {
+            IdStrategy::UnixSeconds => return MigrationId::from_timestamp(now.into()),
+            IdStrategy::UnixMillis => (now.unix_timestamp_nanos() / 1_000_000)
+                .try_into()
+                .expect("system clock is not in the far future"),
+            IdStrategy::Sequential => return index.next_sequential_id(),
+            IdStrategy::Datetime => {
+                let date = i64::from(now.year()) * 10_000
+                    + i64::from(u8::from(now.month())) * 100
+                    + i64::from(now.day());
+                let time = i64::from(now.hour()) * 10_000
+                    + i64::from(now.minute()) * 100
+                    + i64::from(now.second());
+                date * 1_000_000 + time
+            }
+        };
+
+        MigrationId(id)
+    }
+}
+
+impl MigrationIndex {
+    /// One more than the highest ID in the index, or 1 if there are no migrations yet (since 0 is
+    /// the init migration).
+    pub fn next_sequential_id(&self) -> MigrationId {
+        match self.latest() {
+            Some(m) => MigrationId(m.id.0 + 1),
+            None => MigrationId(1),
+        }
     }
 }
 
-fn available_migrations(dir: &Path) -> Result<Vec<MigrationDirectory>, IndexError> {
+fn available_migrations(
+    dir: &Path,
+    naming: &MigrationNaming,
+) -> Result<Vec<MigrationDirectory>, IndexError> {
     let entries = match fs::read_dir(dir) {
         Ok(entries) => entries,
 
@@ -159,7 +603,11 @@ fn available_migrations(dir: &Path) -> Result<Vec<MigrationDirectory>, IndexErro
                 return None;
             };
 
-            match path.clone().try_into() {
+            if path.is_file() {
+                return read_single_file(path, naming);
+            }
+
+            match MigrationDirectory::from_dir(path.clone(), naming) {
                 Ok(dir) => Some(dir),
                 Err(err) => {
                     tracing::warn!("skipping non-migration directory: {:?}: {:?}", path, err);
@@ -172,22 +620,58 @@ fn available_migrations(dir: &Path) -> Result<Vec<MigrationDirectory>, IndexErro
     Ok(paths)
 }
 
+fn read_single_file(path: PathBuf, naming: &MigrationNaming) -> Option<MigrationDirectory> {
+    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+
+    // The other files are read along with the up file.
+    if SINGLE_FILE_SUFFIXES[1..]
+        .iter()
+        .any(|suffix| file_name.ends_with(suffix))
+    {
+        return None;
+    }
+
+    match MigrationDirectory::from_up_file(path.clone(), naming) {
+        Ok(migration) => Some(migration),
+        Err(err) => {
+            tracing::warn!("skipping non-migration file: {:?}: {:?}", path, err);
+            None
+        }
+    }
+}
+
+/// Remove a migration's directory, or its files if it's a single-file migration.
+fn remove_migration(migration: &MigrationDirectory) -> std::io::Result<()> {
+    if !migration.is_single_file() {
+        return fs::remove_dir_all(&migration.dir);
+    }
+
+    for suffix in SINGLE_FILE_SUFFIXES {
+        match fs::remove_file(with_suffix(&migration.dir, suffix)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum IoError {
-    #[error("failed to create directory: {0}: {1}")]
-    CreateDir(PathBuf, std::io::Error),
+    #[error("failed to create directory: {0}")]
+    CreateDir(PathBuf, #[source] std::io::Error),
 
-    #[error("failed to create file: {0}: {1}")]
-    CreateFile(PathBuf, std::io::Error),
+    #[error("failed to create file: {0}")]
+    CreateFile(PathBuf, #[source] std::io::Error),
 
-    #[error("failed to write file: {0}: {1}")]
-    WriteFile(PathBuf, std::io::Error),
+    #[error("failed to write file: {0}")]
+    WriteFile(PathBuf, #[source] std::io::Error),
 }
 
 fn create_migration_files(
     dir: &Path,
     up_sql: String,
-    down_sql: String,
+    down_sql: Option<String>,
 ) -> Result<MigrationFiles, IoError> {
     let up_path = dir.join("up.sql");
     let down_path = dir.join("down.sql");
@@ -198,11 +682,13 @@ fn create_migration_files(
     tracing::info!("Creating up migration file: {}", up_path.to_string_lossy());
     create_file(&up_path, &up_sql)?;
 
-    tracing::info!(
-        "Creating down migration file: {}",
-        down_path.to_string_lossy()
-    );
-    create_file(&down_path, &down_sql)?;
+    if let Some(down_sql) = down_sql {
+        tracing::info!(
+            "Creating down migration file: {}",
+            down_path.to_string_lossy()
+        );
+        create_file(&down_path, &down_sql)?;
+    }
 
     Ok(MigrationFiles {
         up: up_path,
@@ -312,7 +798,7 @@ mod tests {
             id: MigrationId(123),
             name: String::from("second"),
             up_sql: String::from("-- 123-second: up"),
-            down_sql: String::from("-- 123-second: down"),
+            down_sql: Some(String::from("-- 123-second: down")),
         };
 
         match index.create(params) {
@@ -336,7 +822,7 @@ mod tests {
             id: MigrationId(123),
             name: String::from("first"),
             up_sql: String::from("-- 123-first: up"),
-            down_sql: String::from("-- 123-first: down"),
+            down_sql: Some(String::from("-- 123-first: down")),
         };
 
         let files = index.create(params.clone()).unwrap();
@@ -345,7 +831,7 @@ mod tests {
         let actual_down_sql = std::fs::read_to_string(files.down_path).unwrap();
 
         assert_eq!(&params.up_sql, &actual_up_sql);
-        assert_eq!(&params.down_sql, &actual_down_sql);
+        assert_eq!(params.down_sql.as_ref(), Some(&actual_down_sql));
 
         let migration = index.get(MigrationId(123)).unwrap();
 
@@ -353,6 +839,47 @@ mod tests {
         assert_eq!(config.migrations_dir.join("123-first"), migration.dir);
     }
 
+    #[tokio::test]
+    async fn squash_range() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+        let two = index.create(fake_migration(2, "two")).unwrap();
+        let _ = index.create(fake_migration(3, "three")).unwrap();
+
+        let squashed = index
+            .squash(MigrationId(1), MigrationId(2), "one_and_two")
+            .unwrap();
+
+        assert_eq!(MigrationId(2), squashed.id);
+        assert!(!one.dir.exists());
+        assert!(!two.dir.exists());
+
+        let ids: Vec<_> = index.iter().map(|m| m.id.as_i64()).collect();
+        assert_eq!(vec![2, 3], ids);
+
+        let up = std::fs::read_to_string(&squashed.up_path).unwrap();
+        assert!(up.contains("create table tbl_one"), "{up:?}");
+        assert!(up.contains("create table tbl_two"), "{up:?}");
+
+        let down = std::fs::read_to_string(&squashed.down_path).unwrap();
+        let drop_one = down.find("drop table tbl_one").unwrap();
+        let drop_two = down.find("drop table tbl_two").unwrap();
+        assert!(drop_two < drop_one, "{down:?}");
+
+        let expected: BTreeSet<_> = [MigrationId(1), MigrationId(2)].into();
+        assert_eq!(expected, index.squashed_ids());
+
+        match index.squash(MigrationId(3), MigrationId(10), "just_three") {
+            Err(SquashError::TooFew(1)) => (),
+
+            Ok(m) => panic!("Unexpected success: {:?}", m),
+            Err(err) => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
     #[tokio::test]
     async fn align_id_add_padding() {
         let env = TestEnv::new().await.unwrap();
@@ -395,4 +922,281 @@ mod tests {
 
         assert_eq!(expected, actual);
     }
+
+    #[tokio::test]
+    async fn align_id_width() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+
+        mkdir(&config.migrations_dir.join("1-create_users")).unwrap();
+        mkdir(&config.migrations_dir.join("123-add_email")).unwrap();
+
+        let index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        assert_eq!(3, index.id_width());
+
+        let renames = index.align_ids_to(5).unwrap();
+        let names: Vec<_> = renames
+            .iter()
+            .map(|r| r.to.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(vec!["00001-create_users", "00123-add_email"], names);
+
+        match index.align_ids_to(2) {
+            Err(AlignError::TooNarrow { id, required, .. }) => {
+                assert_eq!(MigrationId(123), id);
+                assert_eq!(3, required);
+            }
+
+            Ok(renames) => panic!("Unexpected success: {:?}", renames),
+        }
+
+        let empty = tempfile::tempdir().unwrap();
+        let index = MigrationIndex::new(empty.path()).unwrap();
+        assert_eq!(DEFAULT_ID_WIDTH, index.id_width());
+    }
+
+    #[tokio::test]
+    async fn rename() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+
+        mkdir(&config.migrations_dir.join("0042-old_name")).unwrap();
+        let index = MigrationIndex::new(&config.migrations_dir).unwrap();
+
+        let expected = Rename {
+            from: config.migrations_dir.join("0042-old_name"),
+            to: config.migrations_dir.join("0042-new_name"),
+        };
+        assert_eq!(expected, index.rename(MigrationId(42), "new_name").unwrap());
+
+        assert!(matches!(
+            index.rename(MigrationId(7), "new_name"),
+            Err(RenameError::NotFound(MigrationId(7)))
+        ));
+        assert!(matches!(
+            index.rename(MigrationId(42), "a/b"),
+            Err(RenameError::InvalidName(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn multiple_dirs() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+
+        let other = tempfile::tempdir().unwrap();
+        let other_dir = other.path().to_path_buf();
+
+        let mut primary = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = primary.create(fake_migration(1, "one")).unwrap();
+
+        let mut secondary = MigrationIndex::new(&other_dir).unwrap();
+        let two = secondary.create(fake_migration(2, "two")).unwrap();
+
+        let merged =
+            MigrationIndex::from_dirs(&config.migrations_dir, &[other_dir.clone()]).unwrap();
+        let ids: Vec<_> = merged.iter().map(|m| m.id.as_i64()).collect();
+        assert_eq!(vec![1, 2], ids);
+
+        assert_eq!(config.migrations_dir, merged.get(one.id).unwrap().root());
+        assert_eq!(other_dir, merged.get(two.id).unwrap().root());
+
+        // New migrations go in the primary directory.
+        assert_eq!(config.migrations_dir, merged.dir);
+
+        // The same ID in two directories is a collision.
+        let _ = secondary.create(fake_migration(1, "also_one")).unwrap();
+        match MigrationIndex::from_dirs(&config.migrations_dir, &[other_dir]) {
+            Err(IndexError::MultipleMigrationDirectories(multiples)) => {
+                assert_eq!(2, multiples[&one.id].len());
+            }
+
+            Ok(index) => panic!("Unexpected success: {:?}", index),
+            Err(err) => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[tokio::test]
+    async fn id_strategies() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let now = time::macros::datetime!(2024-01-31 12:30:00 UTC);
+
+        assert_eq!(MigrationId(1), IdStrategy::Sequential.next_id(&index, now));
+
+        let _ = index.create(fake_migration(0, "init")).unwrap();
+        let _ = index.create(fake_migration(7, "seven")).unwrap();
+
+        let id = |strategy: IdStrategy| strategy.next_id(&index, now).as_i64();
+        assert_eq!(8, id(IdStrategy::Sequential));
+        assert_eq!(1706704200, id(IdStrategy::UnixSeconds));
+        assert_eq!(1706704200000, id(IdStrategy::UnixMillis));
+        assert_eq!(20240131123000, id(IdStrategy::Datetime));
+
+        // Other offsets are converted to UTC first.
+        let local = time::macros::datetime!(2024-01-31 14:30:00 +02:00);
+        assert_eq!(
+            20240131123000,
+            IdStrategy::Datetime.next_id(&index, local).as_i64()
+        );
+
+        assert_eq!(MigrationId(8), index.next_id(IdStrategy::Sequential));
+    }
+
+    #[tokio::test]
+    async fn prune() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let mut params = fake_migration(1, "one");
+        params.up_sql = format!("--squill:retired-by 3\n{}", params.up_sql);
+        let one = index.create(params).unwrap();
+        let two = index.create(fake_migration(2, "two")).unwrap();
+        let _ = index.create(fake_migration(3, "three")).unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        one.up(&mut conn).await.unwrap();
+        two.up(&mut conn).await.unwrap();
+        let logs = [MigrationLog::new(&mut conn).await.unwrap()];
+
+        let ids = |plan: Vec<Prune>| -> Vec<i64> {
+            plan.iter().map(|p| p.migration.id.as_i64()).collect()
+        };
+
+        // The init migration stays, and so does the pending one.
+        assert_eq!(vec![1, 2], ids(index.prune(MigrationId(10), &logs, false)));
+        assert_eq!(vec![1], ids(index.prune(MigrationId(2), &logs, false)));
+        assert_eq!(vec![1], ids(index.prune(MigrationId(10), &logs, true)));
+
+        for plan in index.prune(MigrationId(3), &logs, false) {
+            plan.execute().unwrap();
+        }
+
+        let index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let ids: Vec<_> = index.iter().map(|m| m.id.as_i64()).collect();
+        assert_eq!(vec![0, 3], ids);
+    }
+
+    #[tokio::test]
+    async fn ranges() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        assert!(index.is_empty());
+        assert_eq!(None, index.latest());
+
+        for id in [1, 3, 5, 7] {
+            let _ = index.create(fake_migration(id, "migration")).unwrap();
+        }
+
+        assert_eq!(4, index.len());
+        assert_eq!(MigrationId(7), index.latest().unwrap().id);
+
+        let ids = |migrations: Vec<&MigrationDirectory>| -> Vec<i64> {
+            migrations.iter().map(|m| m.id.as_i64()).collect()
+        };
+        assert_eq!(
+            vec![3, 5],
+            ids(index.range(MigrationId(2)..=MigrationId(5)).collect())
+        );
+        assert_eq!(vec![5, 7], ids(index.range(MigrationId(5)..).collect()));
+        assert_eq!(vec![1], ids(index.range(..MigrationId(3)).collect()));
+    }
+
+    #[tokio::test]
+    async fn single_file_migrations() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+        let dir = &config.migrations_dir;
+
+        std::fs::write(dir.join("5-flat.up.sql"), "create table flat ()").unwrap();
+        std::fs::write(dir.join("5-flat.down.sql"), "drop table flat").unwrap();
+        std::fs::write(dir.join("6-up_only.up.sql"), "select 1").unwrap();
+        std::fs::write(dir.join("notes.sql"), "-- not a migration").unwrap();
+
+        let mut index = MigrationIndex::new(dir).unwrap();
+        let _ = index.create(fake_migration(7, "directory")).unwrap();
+
+        let ids: Vec<_> = index.iter().map(|m| m.id.as_i64()).collect();
+        assert_eq!(vec![5, 6, 7], ids);
+
+        let flat = index.get(MigrationId(5)).unwrap();
+        assert!(flat.is_single_file());
+        assert_eq!("flat", flat.name);
+        assert_eq!(dir.join("5-flat.up.sql"), flat.up_path);
+        assert_eq!(dir.join("5-flat.down.sql"), flat.down_path);
+        assert!(flat.contains(&dir.join("5-flat.down.sql")));
+        assert!(!flat.contains(&dir.join("6-up_only.up.sql")));
+
+        assert!(!index.get(MigrationId(6)).unwrap().down_path.exists());
+        assert!(!index.get(MigrationId(7)).unwrap().is_single_file());
+
+        index
+            .rename(MigrationId(5), "renamed")
+            .unwrap()
+            .execute()
+            .unwrap();
+        assert!(dir.join("5-renamed.up.sql").exists());
+        assert!(dir.join("5-renamed.down.sql").exists());
+        assert!(!dir.join("5-flat.up.sql").exists());
+
+        let index = MigrationIndex::new(dir).unwrap();
+        assert_eq!("renamed", index.get(MigrationId(5)).unwrap().name);
+    }
+
+    #[tokio::test]
+    async fn validate_missing_files() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+        let two = index.create(fake_migration(2, "two")).unwrap();
+        let three = index.create(fake_migration(3, "three")).unwrap();
+
+        std::fs::remove_file(&two.up_path).unwrap();
+        std::fs::remove_file(&three.down_path).unwrap();
+
+        let index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let expected = vec![BrokenMigration {
+            migration: two.clone(),
+            missing: vec![two.up_path.clone()],
+        }];
+        assert_eq!(expected, index.validate());
+        assert!(!one.is_broken());
+        assert!(!three.is_broken());
+    }
+
+    #[tokio::test]
+    async fn custom_naming() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+        let dir = &config.migrations_dir;
+        let naming = MigrationNaming::new("V{id}__{name}").unwrap();
+
+        mkdir(&dir.join("V1__existing")).unwrap();
+        std::fs::write(dir.join("V2__flat.up.sql"), "select 1").unwrap();
+        mkdir(&dir.join("3-default_style")).unwrap();
+
+        let mut index = MigrationIndex::from_dirs_named(dir, &[], &naming).unwrap();
+        let created = index.create(fake_migration(4, "created")).unwrap();
+        assert_eq!(dir.join("V4__created"), created.dir);
+
+        let ids: Vec<_> = index.iter().map(|m| m.id.as_i64()).collect();
+        assert_eq!(vec![1, 2, 4], ids);
+        assert_eq!("existing", index.get(MigrationId(1)).unwrap().name);
+
+        let expected = Rename {
+            from: dir.join("V1__existing"),
+            to: dir.join("V1__renamed"),
+        };
+        assert_eq!(expected, index.rename(MigrationId(1), "renamed").unwrap());
+
+        let aligned = index.align_ids_to(2).unwrap();
+        assert_eq!(dir.join("V01__existing"), aligned[0].to);
+    }
 }
@@ -0,0 +1,177 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::sql::split_statements;
+
+// An identifier, optionally schema-qualified, optionally quoted.
+const NAME: &str = r#"(?:"[^"]+"|[\w$]+)(?:\.(?:"[^"]+"|[\w$]+))?"#;
+
+// Comments before a statement (like the template header) aren't part of it.
+const LEADING_COMMENTS: &str = r"\A(?:\s+|--[^\n]*(?:\n|\z)|/\*(?s:.*?)\*/)*";
+
+lazy_static! {
+    static ref RE_LEADING_COMMENTS: Regex =
+        Regex::new(LEADING_COMMENTS).expect("static pattern");
+
+    static ref RE_CREATE: Regex = Regex::new(&format!(
+        r"(?is)\Acreate\s+(?:unlogged\s+)?(table|type|view|materialized\s+view|sequence|schema|extension)\s+(if\s+not\s+exists\s+)?({NAME})"
+    ))
+    .expect("static pattern");
+
+    static ref RE_CREATE_INDEX: Regex = Regex::new(&format!(
+        r"(?is)\Acreate\s+(?:unique\s+)?index\s+(?:concurrently\s+)?(if\s+not\s+exists\s+)?({NAME})\s+on\s"
+    ))
+    .expect("static pattern");
+
+    static ref RE_ADD_COLUMN: Regex = Regex::new(&format!(
+        r"(?is)\Aalter\s+table\s+(?:if\s+exists\s+)?(?:only\s+)?({NAME})\s+add\s+(?:column\s+)?(if\s+not\s+exists\s+)?({NAME})\s"
+    ))
+    .expect("static pattern");
+
+    // Statements that don't change the schema, so there's nothing to reverse.
+    static ref RE_IGNORED: Regex =
+        Regex::new(r"(?is)\A(?:set|reset|begin|commit|select\s+_squill_claim_migration)\b")
+            .expect("static pattern");
+}
+
+/// Write the statements that reverse this up migration, if it only contains changes that are
+/// simple to reverse: creating tables, indexes, types, views, sequences, schemas, and extensions,
+/// and adding columns.
+///
+/// This returns None if any statement can't be reversed (or there are no statements at all), since
+/// a down migration that only reverses some of the changes is worse than an obvious TODO.
+pub fn invert(up_sql: &str) -> Option<String> {
+    let mut drops = Vec::new();
+
+    for statement in split_statements(up_sql) {
+        let statement = RE_LEADING_COMMENTS.replace(statement, "");
+
+        if RE_IGNORED.is_match(&statement) {
+            continue;
+        }
+
+        drops.push(invert_statement(&statement)?);
+    }
+
+    if drops.is_empty() {
+        return None;
+    }
+
+    // Undo the changes in the opposite order they were made.
+    drops.reverse();
+    Some(drops.join("\n"))
+}
+
+fn invert_statement(statement: &str) -> Option<String> {
+    let if_exists = |m: Option<regex::Match>| match m {
+        Some(_) => " if exists",
+        None => "",
+    };
+
+    if let Some(caps) = RE_CREATE.captures(statement) {
+        let kind = caps[1].split_whitespace().collect::<Vec<_>>().join(" ");
+        return Some(format!(
+            "drop {}{} {};",
+            kind.to_lowercase(),
+            if_exists(caps.get(2)),
+            &caps[3]
+        ));
+    }
+
+    if let Some(caps) = RE_CREATE_INDEX.captures(statement) {
+        // An unnamed concurrent index looks like one named "concurrently".
+        if caps[2].eq_ignore_ascii_case("concurrently") {
+            return None;
+        }
+
+        return Some(format!(
+            "drop index{} {};",
+            if_exists(caps.get(1)),
+            &caps[2]
+        ));
+    }
+
+    if let Some(caps) = RE_ADD_COLUMN.captures(statement) {
+        // Only one column at a time, so the others aren't left behind.
+        if statement.contains(',') {
+            return None;
+        }
+
+        // Without the optional "column", adding a constraint looks the same.
+        let keyword = [
+            "constraint",
+            "primary",
+            "unique",
+            "check",
+            "foreign",
+            "exclude",
+        ];
+        if keyword.iter().any(|k| caps[3].eq_ignore_ascii_case(k)) {
+            return None;
+        }
+
+        return Some(format!(
+            "alter table {} drop column{} {};",
+            &caps[1],
+            if_exists(caps.get(2)),
+            &caps[3]
+        ));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inverses() {
+        let up = r#"
+            -- ID:   1
+            -- Name: everything
+            --squill:no-transaction
+            set lock_timeout = '5s';
+            create type mood as enum ('happy', 'sad');
+            create table if not exists app.users (id bigint primary key, feeling mood);
+            CREATE UNIQUE INDEX CONCURRENTLY "users_Feeling" ON app.users (feeling);
+            alter table app.users add column email text not null default '';
+            create materialized view happy_users as select * from app.users;
+            select _squill_claim_migration(1, 'everything');
+        "#;
+
+        assert_eq!(
+            Some(
+                [
+                    "drop materialized view happy_users;",
+                    "alter table app.users drop column email;",
+                    r#"drop index "users_Feeling";"#,
+                    "drop table if exists app.users;",
+                    "drop type mood;",
+                ]
+                .join("\n")
+            ),
+            invert(up)
+        );
+    }
+
+    #[test]
+    fn cannot_invert() {
+        // Nothing to reverse.
+        assert_eq!(None, invert("-- TODO: Write your migration here!"));
+
+        // One statement that can't be reversed spoils the rest.
+        assert_eq!(
+            None,
+            invert("create table t (id int); insert into t values (1);")
+        );
+        assert_eq!(None, invert("create or replace view v as select 1;"));
+        assert_eq!(None, invert("create index on t (id);"));
+        assert_eq!(None, invert("create index concurrently on t (id);"));
+        assert_eq!(
+            None,
+            invert("alter table t add column a int, add column b int;")
+        );
+        assert_eq!(None, invert("alter table t add check (id > 0);"));
+    }
+}
@@ -1,43 +1,375 @@
 #![warn(clippy::unwrap_used)]
 
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use futures::future::BoxFuture;
 use lazy_static::lazy_static;
 use regex::Regex;
-
+use sqlx::PgConnection;
+
+pub mod archive;
+pub mod attempts;
+pub mod audit;
+pub mod baseline;
+pub mod checkpoint;
+pub mod client;
 pub mod config;
 pub mod db;
+pub mod diff;
+pub mod directives;
+pub mod doctor;
+pub mod events;
+pub mod exec;
+pub mod failures;
+pub mod fanout;
+pub mod hooks;
+pub mod import;
 pub mod index;
+pub mod invert;
+pub mod lint;
+pub mod manifest;
+pub mod metadata;
 pub mod migrate;
+pub mod naming;
+pub mod notify;
+pub mod owners;
+pub mod readiness;
+pub mod redact;
+pub mod remote;
+pub mod render;
+pub mod report;
+pub mod sandbox;
+pub mod scaffold;
+pub mod schema;
+pub mod smoke;
+pub mod sql;
+pub mod startup;
+pub mod state;
+pub mod stats;
 pub mod status;
 pub mod template;
+pub mod verify_down;
+pub mod window;
 
+use crate::attempts::AttemptLog;
 use crate::config::{Config, ConnectError};
-use crate::index::{CreateMigrationError, IndexError, IoError, MigrationIndex, MigrationParams};
-use crate::migrate::{MigrateError, MigrationDirectory, MigrationId};
-use crate::status::{Status, StatusError};
-use crate::template::{TemplateContext, TemplateError, TemplateGroup, TemplateId, Templates};
-
-#[cfg(test)]
-mod testing;
+use crate::events::MigrationEvent;
+use crate::failures::record_failure;
+use crate::hooks::{HookError, HookEvent};
+use crate::index::{CreateMigrationError, IndexError, IoError, MigrationParams};
+use crate::invert::invert;
+use crate::lint::{check_lint, LintError};
+use crate::migrate::{Direction, MigrateError, MigrateOptions, MigrationDirectory, MigrationId};
+use crate::notify::{notify_all, Notification};
+use crate::report::{AppliedMigration, FailedMigration, MigrateReport};
+use crate::status::{OutOfOrderError, Status, StatusError};
+use crate::template::{
+    template_author, TemplateContext, TemplateError, TemplateGroup, TemplateId, Templates,
+};
+
+pub use crate::client::{Squill, SquillBuilder};
+pub use crate::startup::{run_pending_migrations, run_pending_migrations_with};
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+pub async fn migrate_all(config: &Config) -> Result<MigrateReport, MigrateAllError> {
+    migrate_next(config, usize::MAX).await
+}
 
-pub async fn migrate_all(config: &Config) -> Result<Vec<MigrationDirectory>, MigrateAllError> {
+/// Apply only the next `count` pending migrations, like `squill migrate --count`.
+///
+/// The rest are left for a later run, and the report lists them in `remaining`. The out-of-order
+/// and lint checks only look at the migrations that will be applied.
+pub async fn migrate_next(config: &Config, count: usize) -> Result<MigrateReport, MigrateAllError> {
     let status = Status::new(config).await.map_err(MigrateAllError::Status)?;
 
+    let mut pending = status.pending();
+    let later = pending.split_off(count.min(pending.len()));
+    status
+        .check_order(&pending, config.out_of_order)
+        .map_err(MigrateAllError::OutOfOrder)?;
+
+    check_lint(&pending, config.lint).map_err(MigrateAllError::Lint)?;
+
     let mut conn = config.connect().await.map_err(MigrateAllError::Connect)?;
 
+    let result = apply_pending(config, &mut conn, pending, &ApplyOptions::default()).await;
+    with_remaining(result, &later)
+}
+
+/// Add the migrations that were held back from a run to its report, even if the run failed.
+pub fn with_remaining(
+    result: Result<MigrateReport, MigrateAllError>,
+    later: &[MigrationDirectory],
+) -> Result<MigrateReport, MigrateAllError> {
+    let later = later.iter().map(Into::into);
+
+    match result {
+        Ok(mut report) => {
+            report.remaining.extend(later);
+            Ok(report)
+        }
+        Err(MigrateAllError::Migrate { err, mut report }) => {
+            report.remaining.extend(later);
+            Err(MigrateAllError::Migrate { err, report })
+        }
+        Err(MigrateAllError::Hook { err, mut report }) => {
+            report.remaining.extend(later);
+            Err(MigrateAllError::Hook { err, report })
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Awaited before each migration in [`apply_pending`] starts. Returning false stops the run.
+#[derive(Clone)]
+pub struct BeforeEach(Arc<dyn Fn() -> BoxFuture<'static, bool> + Send + Sync>);
+
+impl BeforeEach {
+    pub fn new<F, Fut>(f: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        Self(Arc::new(move || -> BoxFuture<'static, bool> {
+            Box::pin(f())
+        }))
+    }
+}
+
+/// Called with each problem that doesn't stop the run, after it's logged.
+pub type WarningCallback = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// What a caller that drives the run itself (like the CLI) can change on top of the config.
+#[derive(Clone, Default)]
+pub struct ApplyOptions {
+    /// Run each migration with these instead of `config.migrate_options()`.
+    pub migrate: Option<MigrateOptions>,
+
+    /// Checked before each migration, like for pausing or a maintenance window. When it returns
+    /// false, the run stops without an error and the report lists the rest in `remaining`.
+    pub before_each: Option<BeforeEach>,
+
+    /// Told about failed notifications and failure records, which don't fail the run.
+    pub on_warning: Option<WarningCallback>,
+}
+
+impl ApplyOptions {
+    fn warn(&self, message: &str) {
+        if let Some(on_warning) = &self.on_warning {
+            on_warning(message);
+        }
+    }
+}
+
+impl std::fmt::Debug for ApplyOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApplyOptions")
+            .field("migrate", &self.migrate)
+            .field("before_each", &self.before_each.is_some())
+            .field("on_warning", &self.on_warning.is_some())
+            .finish()
+    }
+}
+
+/// Apply these migrations in order, running the configured hooks around them and telling the
+/// configured observers about each one.
+///
+/// Notification failures are logged but don't affect the result. The span and events use the
+/// `squill::migrate` target instead of the crate root, so they're filtered with the migrations'.
+#[tracing::instrument(
+    name = "migrate_all",
+    target = "squill::migrate",
+    skip_all,
+    fields(pending = pending.len()),
+)]
+pub async fn apply_pending(
+    config: &Config,
+    conn: &mut PgConnection,
+    pending: Vec<MigrationDirectory>,
+    apply: &ApplyOptions,
+) -> Result<MigrateReport, MigrateAllError> {
+    let opts = apply
+        .migrate
+        .clone()
+        .unwrap_or_else(|| config.migrate_options());
     let mut applied = Vec::new();
+    let mut report = MigrateReport::default();
+
+    // Skip all the hooks when there's nothing to do.
+    if pending.is_empty() {
+        return Ok(report);
+    }
+
+    if let Err(err) = config
+        .hooks
+        .run(conn, HookEvent::BeforeMigrate { pending: &pending })
+        .await
+    {
+        report.remaining = pending.iter().map(Into::into).collect();
+        return Err(hook_failed(config, apply, &applied, &pending[0], err, report).await);
+    }
 
-    for migration in status.pending() {
-        migration
-            .up(&mut conn)
+    let mut attempts = AttemptLog::open(config).await;
+
+    for (i, migration) in pending.iter().enumerate() {
+        if let Some(before_each) = &apply.before_each {
+            if !(before_each.0)().await {
+                report.remaining = pending[i..].iter().map(Into::into).collect();
+                break;
+            }
+        }
+
+        tracing::info!(
+            target: "squill::migrate",
+            id = %migration.id,
+            "Applying migration: {}",
+            migration
+        );
+
+        config.observers.emit(MigrationEvent::Started {
+            migration,
+            direction: Direction::Up,
+        });
+
+        let started = Instant::now();
+        let attempt = match &mut attempts {
+            Some(attempts) => attempts.start(migration).await,
+            None => None,
+        };
+        let result = migration.up_with(&mut *conn, &opts).await;
+        if let Some(attempts) = &mut attempts {
+            let error = result.as_ref().err().map(error_chain);
+            attempts.finish(attempt, error.as_deref()).await;
+        }
+
+        if let Err(err) = result {
+            if matches!(err, MigrateError::AlreadyApplied(_)) && config.skip_applied {
+                tracing::warn!(
+                    target: "squill::migrate",
+                    id = %migration.id,
+                    "Skipping migration that another runner already applied: {}",
+                    migration
+                );
+                config.observers.emit(MigrationEvent::Skipped { migration });
+                continue;
+            }
+
+            config.observers.emit(MigrationEvent::Failed {
+                migration,
+                direction: Direction::Up,
+                error: &err,
+            });
+
+            let error = error_chain(&err);
+            if config.record_failures {
+                if let Err(err) = record_failure(conn, migration, &error).await {
+                    let message = format!(
+                        "failed to record failure of migration {}: {}",
+                        migration,
+                        error_chain(&err)
+                    );
+                    tracing::warn!(target: "squill::migrate", "{}", message);
+                    apply.warn(&message);
+                }
+            }
+            notify(
+                config,
+                apply,
+                &Notification::Failed {
+                    applied: &applied,
+                    migration,
+                    error: &error,
+                },
+            )
+            .await;
+            report.failed = Some(FailedMigration::new(migration, started.elapsed(), &err));
+            report.remaining = pending[i + 1..].iter().map(Into::into).collect();
+            return Err(MigrateAllError::Migrate { err, report });
+        }
+        let duration = started.elapsed();
+
+        config.observers.emit(MigrationEvent::Finished {
+            migration,
+            direction: Direction::Up,
+            duration,
+        });
+
+        // The migration is committed by now, so it's in the report even if its hook fails.
+        report
+            .applied
+            .push(AppliedMigration::new(migration, duration));
+        applied.push(migration.clone());
+
+        if let Err(err) = config
+            .hooks
+            .run(conn, HookEvent::AfterEach { migration })
             .await
-            .map_err(MigrateAllError::Migrate)?;
-        applied.push(migration);
+        {
+            report.remaining = pending[i + 1..].iter().map(Into::into).collect();
+            return Err(hook_failed(config, apply, &applied, migration, err, report).await);
+        }
     }
 
-    Ok(applied)
+    if let Err(err) = config
+        .hooks
+        .run(conn, HookEvent::AfterAll { applied: &applied })
+        .await
+    {
+        let last = applied.last().unwrap_or(&pending[pending.len() - 1]);
+        return Err(hook_failed(config, apply, &applied, last, err, report).await);
+    }
+
+    notify(config, apply, &Notification::Migrated { applied: &applied }).await;
+
+    Ok(report)
+}
+
+/// Send the failure notification for a hook that stopped the run, and wrap its error with how far
+/// the run got. `migration` is the one the hook ran for (or the closest one, for the hooks around
+/// the whole run).
+async fn hook_failed(
+    config: &Config,
+    apply: &ApplyOptions,
+    applied: &[MigrationDirectory],
+    migration: &MigrationDirectory,
+    err: HookError,
+    report: MigrateReport,
+) -> MigrateAllError {
+    let error = error_chain(&err);
+    notify(
+        config,
+        apply,
+        &Notification::Failed {
+            applied,
+            migration,
+            error: &error,
+        },
+    )
+    .await;
+
+    MigrateAllError::Hook { err, report }
+}
+
+async fn notify(config: &Config, apply: &ApplyOptions, notification: &Notification<'_>) {
+    // The errors are already logged, and a failed notification shouldn't fail the migration.
+    let errors = notify_all(
+        &config.notifications,
+        config.profile.as_deref(),
+        notification,
+    )
+    .await;
+
+    for err in errors {
+        apply.warn(&error_chain(&err));
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum MigrateAllError {
     #[error(transparent)]
     Status(StatusError),
@@ -45,22 +377,63 @@ pub enum MigrateAllError {
     #[error(transparent)]
     Connect(ConnectError),
 
+    /// A migration failed. The report says which ones were applied before it and which ones
+    /// weren't started.
+    #[error("failed to apply migrations")]
+    Migrate {
+        #[source]
+        err: MigrateError,
+        report: MigrateReport,
+    },
+
+    /// A hook failed. Migrations that finished before it (including the one an after_each hook
+    /// ran for) stay applied, and the report says which ones those are.
+    #[error("failed to run migration hooks")]
+    Hook {
+        #[source]
+        err: HookError,
+        report: MigrateReport,
+    },
+
     #[error(transparent)]
-    Migrate(MigrateError),
+    OutOfOrder(OutOfOrderError),
+
+    #[error(transparent)]
+    Lint(LintError),
+
+    #[error("failed to lock the migration log")]
+    Lock(#[source] sqlx::Error),
+}
+
+impl MigrateAllError {
+    /// How far the run got before it stopped, if any migrations were started.
+    pub fn report(&self) -> Option<&MigrateReport> {
+        match self {
+            MigrateAllError::Migrate { report, .. } | MigrateAllError::Hook { report, .. } => {
+                Some(report)
+            }
+            _ => None,
+        }
+    }
 }
 
 pub fn create_init_migration(config: &Config) -> Result<MigrationDirectory, NewMigrationError> {
     let templates = Templates::default();
 
-    let mut index =
-        MigrationIndex::new(&config.migrations_dir).map_err(NewMigrationError::Index)?;
+    let mut index = config.migration_index().map_err(NewMigrationError::Index)?;
 
     let id = MigrationId(0);
     let name = "init".to_owned();
 
+    // The init migration always comes from the built-in template, which doesn't need the rest.
     let ctx = TemplateContext {
         id,
         name: name.clone(),
+        vars: config.template_vars.clone(),
+        schema: config.schema.clone(),
+        created_at: None,
+        author: None,
+        profile: None,
     };
 
     let up_sql = templates
@@ -75,60 +448,133 @@ pub fn create_init_migration(config: &Config) -> Result<MigrationDirectory, NewM
         id,
         name,
         up_sql,
-        down_sql,
+        down_sql: Some(down_sql),
     };
 
     index.create(params).map_err(NewMigrationError::Create)
 }
 
+/// The init migration from [`initialize`], and whether it was applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitMigration {
+    pub migration: MigrationDirectory,
+    pub applied: bool,
+}
+
+/// Create the init migration, and apply it right away if `apply` is set.
+///
+/// If applying it fails, the files are left in place to fix and apply later.
+pub async fn initialize(config: &Config, apply: bool) -> Result<InitMigration, InitError> {
+    let migration = create_init_migration(config).map_err(InitError::Create)?;
+
+    if !apply {
+        return Ok(InitMigration {
+            migration,
+            applied: false,
+        });
+    }
+
+    let mut conn = config.connect().await.map_err(InitError::Connect)?;
+    migration
+        .up_with(&mut conn, &config.migrate_options())
+        .await
+        .map_err(InitError::Apply)?;
+
+    Ok(InitMigration {
+        migration,
+        applied: true,
+    })
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum InitError {
+    #[error(transparent)]
+    Create(NewMigrationError),
+
+    #[error(transparent)]
+    Connect(ConnectError),
+
+    #[error("failed to apply the init migration")]
+    Apply(#[source] MigrateError),
+}
+
 pub fn create_new_migration(
     config: &Config,
     template: Option<impl Into<String>>,
     id: MigrationId,
     name: impl AsRef<str>,
 ) -> Result<MigrationDirectory, NewMigrationError> {
+    let mut index = config.migration_index().map_err(NewMigrationError::Index)?;
+
+    let params = new_migration_params(config, template, id, name)?;
+
+    index.create(params).map_err(NewMigrationError::Create)
+}
+
+pub(crate) fn new_migration_params(
+    config: &Config,
+    template: Option<impl Into<String>>,
+    id: MigrationId,
+    name: impl AsRef<str>,
+) -> Result<MigrationParams, NewMigrationError> {
     let name = name.as_ref();
 
-    let templates = if let Some(dir) = &config.templates_dir {
-        Templates::new(dir).map_err(NewMigrationError::Template)?
-    } else {
-        Templates::default()
-    };
+    let templates = Templates::from_config(config).map_err(NewMigrationError::Template)?;
 
     let group = match template {
         Some(s) => TemplateGroup::Named(s.into()),
         None => TemplateGroup::Default,
     };
 
-    let mut index =
-        MigrationIndex::new(&config.migrations_dir).map_err(NewMigrationError::Index)?;
-
     let name = slugify(name);
 
     let ctx = TemplateContext {
         id,
         name: name.clone(),
+        vars: config.template_vars.clone(),
+        schema: config.schema.clone(),
+        created_at: Some(time::OffsetDateTime::now_utc()),
+        author: template_author(config),
+        profile: config.profile.clone(),
     };
 
     let up_sql = templates
         .render(&group, TemplateId::NewUp, &ctx)
         .map_err(NewMigrationError::Template)?;
 
-    let down_sql = templates
-        .render(&group, TemplateId::NewDown, &ctx)
-        .map_err(NewMigrationError::Template)?;
+    // Record where this came from so migrations can be audited by template later.
+    let up_sql = format!("--squill:template {}\n{}", group, up_sql);
 
-    let params = MigrationParams {
+    let generated = match config.auto_down {
+        true => invert(&up_sql),
+        false => None,
+    };
+
+    let down_sql = match generated {
+        _ if config.no_down => None,
+        Some(sql) => Some(format!(
+            "-- ID:   {id}\n-- Name: {name}\n--\n\
+            -- Generated from up.sql by auto_down. Check that it reverses every change!\n\n\
+            {sql}\n"
+        )),
+        None => Some(
+            templates
+                .render(&group, TemplateId::NewDown, &ctx)
+                .map_err(NewMigrationError::Template)?,
+        ),
+    };
+
+    Ok(MigrationParams {
         id,
         name,
         up_sql,
         down_sql,
-    };
-
-    index.create(params).map_err(NewMigrationError::Create)
+    })
 }
 
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum NewMigrationError {
     #[error(transparent)]
     Index(IndexError),
@@ -160,6 +606,28 @@ pub fn slugify(s: impl AsRef<str>) -> String {
     s.to_string()
 }
 
+/// Format an error with each of its sources, like `failed to read file: up.sql: permission
+/// denied`.
+///
+/// Squill's errors leave their source out of the message, so use this (or walk
+/// [`std::error::Error::source`]) to show the whole story. A source that's already at the end of
+/// the message isn't repeated.
+pub fn error_chain(err: &(dyn std::error::Error + 'static)) -> String {
+    let mut message = err.to_string();
+
+    let mut source = err.source();
+    while let Some(err) = source {
+        let part = err.to_string();
+        if !message.ends_with(&part) {
+            message.push_str(": ");
+            message.push_str(&part);
+        }
+        source = err.source();
+    }
+
+    message
+}
+
 #[cfg(test)]
 mod tests {
     use sqlx::Executor;
@@ -193,6 +661,19 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn initialize_and_apply() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+
+        let init = initialize(&config, true).await.unwrap();
+        assert!(init.applied);
+        assert_eq!(MigrationId(0), init.migration.id);
+
+        let status = Status::new(&config).await.unwrap();
+        assert!(status.pending().is_empty(), "{status:?}");
+    }
+
     #[tokio::test]
     async fn nonexistent_migration_directory() {
         let env = TestEnv::new().await.unwrap();
@@ -234,6 +715,146 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn partial_progress() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = config.migration_index().unwrap();
+        index.create(fake_migration(1, "one")).unwrap();
+        index
+            .create(MigrationParams {
+                id: MigrationId(2),
+                name: String::from("broken"),
+                up_sql: String::from("select * from tbl_missing"),
+                down_sql: Some(String::new()),
+            })
+            .unwrap();
+        index.create(fake_migration(3, "three")).unwrap();
+
+        let err = migrate_all(&config).await.unwrap_err();
+        assert!(matches!(err, MigrateAllError::Migrate { .. }), "{err:?}");
+        assert_eq!(
+            r#"failed to apply migrations: failed to execute migration 2-broken: error returned from database: relation "tbl_missing" does not exist"#,
+            error_chain(&err)
+        );
+
+        let report = err.report().unwrap();
+        assert_eq!(vec![MigrationId(1)], report.applied_ids());
+        assert_eq!(MigrationId(2), report.failed.as_ref().unwrap().migration.id);
+        let remaining: Vec<_> = report.remaining.iter().map(|m| m.id).collect();
+        assert_eq!(vec![MigrationId(3)], remaining);
+    }
+
+    #[tokio::test]
+    async fn failed_after_each_hook() {
+        let env = TestEnv::initialized().await.unwrap();
+        let mut config = env.config();
+        config.hooks.after_each = vec![crate::hooks::Hook::Sql(String::from(
+            "select * from tbl_hook_missing",
+        ))];
+
+        let mut index = config.migration_index().unwrap();
+        index.create(fake_migration(1, "one")).unwrap();
+        index.create(fake_migration(2, "two")).unwrap();
+
+        let err = migrate_all(&config).await.unwrap_err();
+        assert!(matches!(err, MigrateAllError::Hook { .. }), "{err:?}");
+
+        // The migration was committed before its hook ran, so the report still lists it.
+        let report = err.report().unwrap();
+        assert_eq!(vec![MigrationId(1)], report.applied_ids());
+        assert!(report.failed.is_none());
+        let remaining: Vec<_> = report.remaining.iter().map(|m| m.id).collect();
+        assert_eq!(vec![MigrationId(2)], remaining);
+
+        let status = Status::new(&config).await.unwrap();
+        let pending: Vec<_> = status.pending().into_iter().map(|m| m.id).collect();
+        assert_eq!(vec![MigrationId(2)], pending);
+    }
+
+    #[tokio::test]
+    async fn migrate_next_count() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = config.migration_index().unwrap();
+        for (id, name) in [(1, "one"), (2, "two"), (3, "three")] {
+            index.create(fake_migration(id, name)).unwrap();
+        }
+
+        let report = migrate_next(&config, 1).await.unwrap();
+        assert_eq!(vec![MigrationId(1)], report.applied_ids());
+        let remaining: Vec<_> = report.remaining.iter().map(|m| m.id).collect();
+        assert_eq!(vec![MigrationId(2), MigrationId(3)], remaining);
+
+        let report = migrate_next(&config, 5).await.unwrap();
+        assert_eq!(vec![MigrationId(2), MigrationId(3)], report.applied_ids());
+        assert!(report.remaining.is_empty());
+    }
+
+    #[tokio::test]
+    async fn apply_pending_stops_before_each() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = config.migration_index().unwrap();
+        for (id, name) in [(1, "one"), (2, "two"), (3, "three")] {
+            index.create(fake_migration(id, name)).unwrap();
+        }
+
+        let started = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let apply = ApplyOptions {
+            before_each: Some(BeforeEach::new({
+                let started = started.clone();
+                move || {
+                    let n = started.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    async move { n < 2 }
+                }
+            })),
+            ..Default::default()
+        };
+
+        let pending = Status::new(&config).await.unwrap().pending();
+        let mut conn = config.connect().await.unwrap();
+        let report = apply_pending(&config, &mut conn, pending, &apply)
+            .await
+            .unwrap();
+
+        assert_eq!(vec![MigrationId(1), MigrationId(2)], report.applied_ids());
+        let remaining: Vec<_> = report.remaining.iter().map(|m| m.id).collect();
+        assert_eq!(vec![MigrationId(3)], remaining);
+    }
+
+    #[tokio::test]
+    async fn target_schema() {
+        let env = TestEnv::new().await.unwrap();
+        let mut config = env.config();
+        config.schema = Some(String::from("app"));
+
+        create_init_migration(&config).unwrap();
+        let mut index = config.migration_index().unwrap();
+        index.create(fake_migration(1, "one")).unwrap();
+
+        let report = migrate_all(&config).await.unwrap();
+        assert_eq!(2, report.applied.len());
+
+        let mut conn = env.config().connect().await.unwrap();
+        for (table, expected) in [
+            ("app.schema_migrations", true),
+            ("app.tbl_one", true),
+            ("public.schema_migrations", false),
+            ("public.tbl_one", false),
+        ] {
+            let exists: bool = sqlx::query_scalar("select to_regclass($1) is not null")
+                .bind(table)
+                .fetch_one(&mut conn)
+                .await
+                .unwrap();
+            assert_eq!(expected, exists, "{table}");
+        }
+    }
+
     #[tokio::test]
     async fn new_migration_embedded_template() {
         let env = TestEnv::new().await.unwrap();
@@ -244,6 +865,7 @@ mod tests {
         let up =
             std::fs::read_to_string(config.migrations_dir.join("123-create_users/up.sql")).unwrap();
         assert!(up.contains("-- TODO: Write your migration here!"), "{up:?}");
+        assert_eq!(Some("(default)"), migrate::template_group(&up).as_deref());
 
         let down = std::fs::read_to_string(config.migrations_dir.join("123-create_users/down.sql"))
             .unwrap();
@@ -253,6 +875,34 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn new_migration_auto_down() {
+        let env = TestEnv::new().await.unwrap();
+        let mut config = env.config();
+        config.auto_down = true;
+        config
+            .template_vars
+            .insert("table".to_owned(), "users".to_owned());
+
+        let users = create_new_migration(
+            &config,
+            Some("create_table"),
+            MigrationId(1),
+            "create_users",
+        )
+        .unwrap();
+        let down = std::fs::read_to_string(users.down_path).unwrap();
+        assert!(down.ends_with("\n\ndrop table users;\n"), "{down:?}");
+
+        // The default template has nothing to reverse yet.
+        let todo = create_new_migration(&config, NO_STR, MigrationId(2), "todo").unwrap();
+        let down = std::fs::read_to_string(todo.down_path).unwrap();
+        assert!(
+            down.contains("-- TODO: Reverse the up migration's steps here."),
+            "{down:?}"
+        );
+    }
+
     #[tokio::test]
     async fn new_migration_named_template() {
         let env = TestEnv::new().await.unwrap();
@@ -277,6 +927,10 @@ mod tests {
             up.contains("-- Up\n-- 123 --\n-- create_users --\n"),
             "{up:?}"
         );
+        assert_eq!(
+            Some("create_table"),
+            migrate::template_group(&up).as_deref()
+        );
 
         let down = std::fs::read_to_string(config.migrations_dir.join("123-create_users/down.sql"))
             .unwrap();
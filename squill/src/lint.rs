@@ -0,0 +1,369 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::directives::MigrationDirectives;
+use crate::migrate::{requires_no_transaction, MigrationDirectory};
+use crate::sql::split_statements;
+
+/// What to do when pending migrations have lint findings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LintPolicy {
+    /// Don't lint before migrating.
+    #[default]
+    Off,
+    Warn,
+    Error,
+}
+
+/// A risky pattern in a migration file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LintRule {
+    /// Building an index without `concurrently` blocks writes to the table until it finishes.
+    IndexNotConcurrent,
+
+    /// `set not null` scans the whole table while holding an exclusive lock.
+    SetNotNull,
+
+    /// The file can't run in a transaction, but doesn't have the no-transaction directive.
+    RequiresNoTransaction,
+
+    /// Updating or deleting rows in the same transaction as an `alter table` holds the table lock
+    /// until the data change finishes.
+    DdlWithDml,
+
+    /// A `--squill:` directive is unknown or has an invalid value.
+    InvalidDirective,
+}
+
+impl LintRule {
+    pub const ALL: [LintRule; 5] = [
+        LintRule::IndexNotConcurrent,
+        LintRule::SetNotNull,
+        LintRule::RequiresNoTransaction,
+        LintRule::DdlWithDml,
+        LintRule::InvalidDirective,
+    ];
+
+    /// The name used in `--squill:lint-allow` directives.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LintRule::IndexNotConcurrent => "index-not-concurrent",
+            LintRule::SetNotNull => "set-not-null",
+            LintRule::RequiresNoTransaction => "requires-no-transaction",
+            LintRule::DdlWithDml => "ddl-with-dml",
+            LintRule::InvalidDirective => "invalid-directive",
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            LintRule::IndexNotConcurrent => {
+                "creating an index blocks writes to the table; use `create index concurrently` in a no-transaction migration"
+            }
+            LintRule::SetNotNull => {
+                "`set not null` scans the table under an exclusive lock; add a `check (col is not null) not valid` constraint and validate it first"
+            }
+            LintRule::RequiresNoTransaction => {
+                "this can't run in a transaction; add the --squill:no-transaction directive and claim the migration yourself"
+            }
+            LintRule::DdlWithDml => {
+                "changing rows in the same transaction as `alter table` holds the table lock until the data change finishes; move the data change to its own migration"
+            }
+            LintRule::InvalidDirective => {
+                "this --squill: directive is unknown or has a missing or invalid value, so Squill won't run the file or will ignore the line"
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for LintRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// One lint finding in a migration file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintFinding {
+    pub rule: LintRule,
+
+    /// The position of the statement in the file, starting from 1. For a directive, this is the
+    /// statement after it.
+    pub statement: usize,
+}
+
+impl LintFinding {
+    pub fn message(&self) -> &'static str {
+        self.rule.message()
+    }
+}
+
+/// The lint findings for one migration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationLint {
+    pub migration: MigrationDirectory,
+    pub findings: Vec<LintFinding>,
+}
+
+// An identifier, optionally schema-qualified, optionally quoted.
+const NAME: &str = r#"(?:"[^"]+"|[\w$]+)(?:\.(?:"[^"]+"|[\w$]+))?"#;
+
+lazy_static! {
+    static ref RE_LINE_COMMENT: Regex = Regex::new(r"--[^\n]*").expect("static pattern");
+    static ref RE_CREATE_TABLE: Regex = Regex::new(&format!(
+        r"(?is)\bcreate\s+(?:unlogged\s+)?table\s+(?:if\s+not\s+exists\s+)?({NAME})"
+    ))
+    .expect("static pattern");
+    static ref RE_CREATE_INDEX: Regex = Regex::new(&format!(
+        r"(?is)\bcreate\s+(?:unique\s+)?index\s+(concurrently\s+)?[^;]*?\bon\s+(?:only\s+)?({NAME})"
+    ))
+    .expect("static pattern");
+    static ref RE_SET_NOT_NULL: Regex = Regex::new(&format!(
+        r"(?is)\balter\s+table\s+(?:if\s+exists\s+)?(?:only\s+)?({NAME})\s.*\bset\s+not\s+null\b"
+    ))
+    .expect("static pattern");
+    static ref RE_ALTER_TABLE: Regex =
+        Regex::new(r"(?is)\A\s*alter\s+table\b").expect("static pattern");
+    static ref RE_UPDATE_DELETE: Regex =
+        Regex::new(r"(?is)\A\s*(?:with\b.*\b)?(?:update|delete)\b").expect("static pattern");
+}
+
+/// Check the SQL of an up migration for risky patterns.
+///
+/// Indexes and `set not null` on tables created earlier in the same file are fine, since nothing
+/// else can be using those tables yet. A `--squill:lint-allow <rule> ...` line turns off rules for
+/// the whole file.
+pub fn lint_sql(sql: &str) -> Vec<LintFinding> {
+    let (directives, problems) = MigrationDirectives::parse_all(sql);
+    let allowed: BTreeSet<_> = LintRule::ALL
+        .into_iter()
+        .filter(|r| directives.lint_allow.iter().any(|name| name == r.name()))
+        .collect();
+    let in_transaction = !directives.no_transaction;
+
+    let statements = split_statements(sql);
+
+    let mut findings = Vec::new();
+    if !allowed.contains(&LintRule::InvalidDirective) {
+        findings.extend(problems.iter().map(|problem| LintFinding {
+            rule: LintRule::InvalidDirective,
+            statement: statement_after_line(sql, &statements, problem.line()),
+        }));
+    }
+
+    let mut created = BTreeSet::new();
+    let mut altered = false;
+
+    for (i, statement) in statements.into_iter().enumerate() {
+        let statement = RE_LINE_COMMENT.replace_all(statement, "");
+        let mut found = |rule: LintRule| {
+            if !allowed.contains(&rule) {
+                findings.push(LintFinding {
+                    rule,
+                    statement: i + 1,
+                });
+            }
+        };
+
+        if let Some(caps) = RE_CREATE_TABLE.captures(&statement) {
+            created.insert(normalize(&caps[1]));
+        }
+
+        if let Some(caps) = RE_CREATE_INDEX.captures(&statement) {
+            if caps.get(1).is_none() && !created.contains(&normalize(&caps[2])) {
+                found(LintRule::IndexNotConcurrent);
+            }
+        }
+
+        if let Some(caps) = RE_SET_NOT_NULL.captures(&statement) {
+            if !created.contains(&normalize(&caps[1])) {
+                found(LintRule::SetNotNull);
+            }
+        }
+
+        if in_transaction && requires_no_transaction(&statement) {
+            found(LintRule::RequiresNoTransaction);
+        }
+
+        if RE_ALTER_TABLE.is_match(&statement) {
+            altered = true;
+        }
+
+        if in_transaction && altered && RE_UPDATE_DELETE.is_match(&statement) {
+            found(LintRule::DdlWithDml);
+        }
+    }
+
+    findings
+}
+
+/// Lint the up file of each migration, returning the ones that have findings.
+pub fn lint_migrations(migrations: &[MigrationDirectory]) -> Result<Vec<MigrationLint>, LintError> {
+    let mut linted = Vec::new();
+
+    for migration in migrations {
+        let sql = std::fs::read_to_string(&migration.up_path).map_err(|err| LintError::Read {
+            path: migration.up_path.clone(),
+            err,
+        })?;
+
+        let findings = lint_sql(&sql);
+        if !findings.is_empty() {
+            linted.push(MigrationLint {
+                migration: migration.clone(),
+                findings,
+            });
+        }
+    }
+
+    Ok(linted)
+}
+
+/// Apply the lint policy to these pending migrations.
+///
+/// This returns the migrations with findings unless the policy makes them an error. With the warn
+/// policy, each finding is also logged as a warning.
+pub fn check_lint(
+    pending: &[MigrationDirectory],
+    policy: LintPolicy,
+) -> Result<Vec<MigrationLint>, LintError> {
+    if policy == LintPolicy::Off {
+        return Ok(Vec::new());
+    }
+
+    let linted = lint_migrations(pending)?;
+
+    if policy == LintPolicy::Error && !linted.is_empty() {
+        return Err(LintError::Failed(linted));
+    }
+
+    for lint in &linted {
+        for finding in &lint.findings {
+            tracing::warn!(
+                "{} (statement {}): {}: {}",
+                lint.migration,
+                finding.statement,
+                finding.rule,
+                finding.message()
+            );
+        }
+    }
+
+    Ok(linted)
+}
+
+/// The position (starting from 1) of the first statement that ends after this line starts.
+fn statement_after_line(sql: &str, statements: &[&str], line: usize) -> usize {
+    let line_start: usize = sql.split_inclusive('\n').take(line - 1).map(str::len).sum();
+
+    // The statements are slices of the SQL, so their positions can be compared directly.
+    let before = statements
+        .iter()
+        .filter(|s| s.as_ptr() as usize + s.len() - sql.as_ptr() as usize <= line_start)
+        .count();
+
+    (before + 1).min(statements.len().max(1))
+}
+
+/// Unquoted names are case-insensitive.
+fn normalize(name: &str) -> String {
+    if name.contains('"') {
+        name.to_owned()
+    } else {
+        name.to_lowercase()
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum LintError {
+    #[error("failed to read migration file: {path}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+
+    #[error("{} pending migration(s) failed the lint check (run `squill lint` for details)", .0.len())]
+    Failed(Vec<MigrationLint>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(sql: &str) -> Vec<(LintRule, usize)> {
+        lint_sql(sql)
+            .into_iter()
+            .map(|f| (f.rule, f.statement))
+            .collect()
+    }
+
+    #[test]
+    fn findings() {
+        let sql = r#"
+            create index on users (email);
+            create index concurrently users_name on users (name);
+            alter table users alter column email set not null;
+            update users set name = lower(name);
+        "#;
+
+        assert_eq!(
+            vec![
+                (LintRule::IndexNotConcurrent, 1),
+                (LintRule::RequiresNoTransaction, 2),
+                (LintRule::SetNotNull, 3),
+                (LintRule::DdlWithDml, 4),
+            ],
+            rules(sql)
+        );
+    }
+
+    #[test]
+    fn new_tables_are_fine() {
+        let sql = r#"
+            create table Accounts (id bigint, email text);
+            create unique index accounts_email on accounts (email);
+            alter table accounts alter column email set not null;
+            insert into accounts values (1, 'a@example.com');
+        "#;
+
+        assert!(rules(sql).is_empty(), "{:?}", rules(sql));
+    }
+
+    #[test]
+    fn allowed() {
+        let sql = r#"
+            --squill:no-transaction
+            --squill:lint-allow set-not-null index-not-concurrent
+            create index users_email on users (email);
+            alter table users alter column email set not null;
+            update users set name = lower(name);
+            select _squill_claim_migration(1, 'allowed');
+        "#;
+
+        assert!(rules(sql).is_empty(), "{:?}", rules(sql));
+    }
+
+    #[test]
+    fn invalid_directives() {
+        let sql = "--squill:no-transation
+create table a (id int);
+--squill:statement-timeout=forever
+create table b (id int);
+";
+
+        assert_eq!(
+            vec![
+                (LintRule::InvalidDirective, 1),
+                (LintRule::InvalidDirective, 2)
+            ],
+            rules(sql)
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::index::MigrationIndex;
+use crate::migrate::MigrationId;
+
+/// A snapshot of the migrations directory that can be saved and compared against later.
+///
+/// Export one from another branch or release to preview how merging it would change the
+/// migration history.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub migrations: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub id: MigrationId,
+    pub name: String,
+
+    /// SHA-256 of the up.sql file contents, in hex.
+    pub up: String,
+
+    /// SHA-256 of the down.sql file contents, in hex, or None for a forward-only migration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub down: Option<String>,
+}
+
+impl Manifest {
+    pub fn from_index(index: &MigrationIndex) -> Result<Self, ManifestError> {
+        let mut migrations = Vec::new();
+
+        for migration in index.iter() {
+            migrations.push(ManifestEntry {
+                id: migration.id,
+                name: migration.name.clone(),
+                up: file_digest(&migration.up_path)?,
+                down: match migration.is_reversible() {
+                    true => Some(file_digest(&migration.down_path)?),
+                    false => None,
+                },
+            });
+        }
+
+        Ok(Self { migrations })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ManifestError> {
+        let content = std::fs::read_to_string(path).map_err(|err| ManifestError::Read {
+            path: path.to_path_buf(),
+            err,
+        })?;
+
+        serde_json::from_str(&content).map_err(|err| ManifestError::Parse {
+            path: path.to_path_buf(),
+            err,
+        })
+    }
+
+    pub fn to_json(&self) -> Result<String, ManifestError> {
+        serde_json::to_string_pretty(self).map_err(ManifestError::Serialize)
+    }
+
+    /// Compare this manifest to another one.
+    ///
+    /// The result describes what would change if this history became the other one: "added"
+    /// migrations are only in `other`, and "removed" migrations are only in `self`.
+    pub fn diff(&self, other: &Manifest) -> ManifestDiff {
+        let ours: BTreeMap<_, _> = self.migrations.iter().map(|m| (m.id, m)).collect();
+        let theirs: BTreeMap<_, _> = other.migrations.iter().map(|m| (m.id, m)).collect();
+
+        let mut diff = ManifestDiff::default();
+
+        for (id, entry) in &theirs {
+            match ours.get(id) {
+                None => diff.added.push((*entry).clone()),
+                Some(ours) if ours != entry => diff.changed.push(ManifestChange {
+                    before: (*ours).clone(),
+                    after: (*entry).clone(),
+                }),
+                Some(_) => (),
+            }
+        }
+
+        for (id, entry) in &ours {
+            if !theirs.contains_key(id) {
+                diff.removed.push((*entry).clone());
+            }
+        }
+
+        diff
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManifestDiff {
+    pub added: Vec<ManifestEntry>,
+    pub removed: Vec<ManifestEntry>,
+    pub changed: Vec<ManifestChange>,
+}
+
+impl ManifestDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// A migration ID that exists on both sides, but with a different name or file contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestChange {
+    pub before: ManifestEntry,
+    pub after: ManifestEntry,
+}
+
+pub(crate) fn file_digest(path: &Path) -> Result<String, ManifestError> {
+    let content = std::fs::read(path).map_err(|err| ManifestError::Read {
+        path: path.to_path_buf(),
+        err,
+    })?;
+
+    let digest = Sha256::digest(content);
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum ManifestError {
+    #[error("failed to read file: {path}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+
+    #[error("failed to parse manifest: {path}")]
+    Parse {
+        path: PathBuf,
+        #[source]
+        err: serde_json::Error,
+    },
+
+    #[error("failed to serialize manifest")]
+    Serialize(#[source] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+
+    use super::*;
+
+    #[test]
+    fn diff_branches() {
+        let migrations_dir = tempfile::tempdir().unwrap();
+        let migrations_dir = migrations_dir.path();
+
+        let mut index = MigrationIndex::new(migrations_dir).unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+        let two = index.create(fake_migration(2, "two")).unwrap();
+
+        let before = Manifest::from_index(&index).unwrap();
+
+        // Pretend the other branch edited one, deleted two, and added three.
+        std::fs::write(&one.up_path, "select 1;").unwrap();
+        std::fs::remove_dir_all(&two.dir).unwrap();
+        let mut index = MigrationIndex::new(migrations_dir).unwrap();
+        let _ = index.create(fake_migration(3, "three")).unwrap();
+
+        let after = Manifest::from_index(&index).unwrap();
+
+        let lock_dir = tempfile::tempdir().unwrap();
+        let path = lock_dir.path().join("other.lock");
+        std::fs::write(&path, after.to_json().unwrap()).unwrap();
+        let after = Manifest::load(&path).unwrap();
+
+        let diff = before.diff(&after);
+
+        let ids = |entries: &[ManifestEntry]| -> Vec<i64> {
+            entries.iter().map(|e| e.id.as_i64()).collect()
+        };
+        assert_eq!(vec![3], ids(&diff.added));
+        assert_eq!(vec![2], ids(&diff.removed));
+
+        assert_eq!(1, diff.changed.len());
+        assert_eq!(MigrationId(1), diff.changed[0].after.id);
+        assert_ne!(diff.changed[0].before.up, diff.changed[0].after.up);
+        assert_eq!(diff.changed[0].before.down, diff.changed[0].after.down);
+
+        assert!(before.diff(&before).is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+
+use sqlx::{Executor, PgConnection};
+
+use crate::migrate::MigrationId;
+
+/// Where a migration was applied from.
+///
+/// The client address comes from the server's view of the connection, so it's only known after
+/// the metadata has been recorded.
+#[derive(sqlx::FromRow, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApplyMetadata {
+    pub hostname: Option<String>,
+    pub username: Option<String>,
+    pub ci_job_url: Option<String>,
+    pub client_addr: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct MetadataRow {
+    id: i64,
+    #[sqlx(flatten)]
+    metadata: ApplyMetadata,
+}
+
+const CREATE_METADATA_TABLE: &str = r#"
+create table if not exists schema_migration_metadata (
+    id bigint primary key,
+    hostname text,
+    username text,
+    ci_job_url text,
+    client_addr text,
+    recorded_at timestamp not null default current_timestamp
+)
+"#;
+
+/// The Squill version and host applying migrations, like `squill 0.9.0 on build-42`.
+///
+/// This is what the init migration's applied_with column records.
+pub fn applied_with() -> String {
+    let version = concat!("squill ", env!("CARGO_PKG_VERSION"));
+
+    match ApplyMetadata::from_env().hostname {
+        Some(host) => format!("{version} on {host}"),
+        None => version.to_owned(),
+    }
+}
+
+impl ApplyMetadata {
+    /// Collect the metadata for this process from its environment.
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let first = |names: &[&str]| {
+            names
+                .iter()
+                .filter_map(|name| var(name))
+                .find(|v| !v.trim().is_empty())
+        };
+
+        let hostname = first(&["HOSTNAME", "COMPUTERNAME"]).or_else(|| {
+            std::fs::read_to_string("/etc/hostname")
+                .ok()
+                .map(|h| h.trim().to_owned())
+                .filter(|h| !h.is_empty())
+        });
+
+        Self {
+            hostname,
+            username: first(&["USER", "USERNAME", "LOGNAME"]),
+            ci_job_url: ci_job_url(&var),
+            client_addr: None,
+        }
+    }
+
+    /// Save this metadata for the migration, replacing any from an earlier run.
+    ///
+    /// The schema_migration_metadata table is created if it doesn't exist yet.
+    pub async fn record(&self, conn: &mut PgConnection, id: MigrationId) -> sqlx::Result<()> {
+        (&mut *conn).execute(CREATE_METADATA_TABLE).await?;
+
+        let query = sqlx::query(
+            r#"
+            insert into schema_migration_metadata
+                (id, hostname, username, ci_job_url, client_addr)
+            values ($1, $2, $3, $4, inet_client_addr()::text)
+            on conflict (id) do update set
+                hostname = excluded.hostname,
+                username = excluded.username,
+                ci_job_url = excluded.ci_job_url,
+                client_addr = excluded.client_addr,
+                recorded_at = current_timestamp
+            "#,
+        )
+        .bind(id.as_i64())
+        .bind(&self.hostname)
+        .bind(&self.username)
+        .bind(&self.ci_job_url);
+
+        conn.execute(query).await?;
+        Ok(())
+    }
+
+    /// Load the recorded metadata for every migration that has some.
+    pub async fn load_all(
+        conn: &mut PgConnection,
+    ) -> sqlx::Result<BTreeMap<MigrationId, ApplyMetadata>> {
+        let exists: (bool,) =
+            sqlx::query_as("select to_regclass('schema_migration_metadata') is not null")
+                .fetch_one(&mut *conn)
+                .await?;
+
+        if !exists.0 {
+            return Ok(BTreeMap::new());
+        }
+
+        let rows: Vec<MetadataRow> = sqlx::query_as(
+            r#"
+            select id, hostname, username, ci_job_url, client_addr
+            from schema_migration_metadata
+            "#,
+        )
+        .fetch_all(conn)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (MigrationId(row.id), row.metadata))
+            .collect())
+    }
+}
+
+/// A link to the CI job running this process, using the variables of common CI providers.
+fn ci_job_url(var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    // GitHub Actions doesn't set the URL directly.
+    if let (Some(server), Some(repo), Some(run)) = (
+        var("GITHUB_SERVER_URL"),
+        var("GITHUB_REPOSITORY"),
+        var("GITHUB_RUN_ID"),
+    ) {
+        return Some(format!("{server}/{repo}/actions/runs/{run}"));
+    }
+
+    [
+        "CI_JOB_URL",          // GitLab
+        "BUILDKITE_BUILD_URL", // Buildkite
+        "CIRCLE_BUILD_URL",    // CircleCI
+        "BUILD_URL",           // Jenkins
+    ]
+    .iter()
+    .find_map(|name| var(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::MigrationIndex;
+    use crate::migrate::MigrateOptions;
+    use crate::testing::*;
+
+    use super::*;
+
+    #[test]
+    fn from_vars() {
+        let vars = |pairs: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                pairs
+                    .iter()
+                    .find(|(k, _)| *k == name)
+                    .map(|(_, v)| v.to_string())
+            }
+        };
+
+        let github = ApplyMetadata::from_vars(vars(&[
+            ("HOSTNAME", "runner-1"),
+            ("USER", "ci"),
+            ("GITHUB_SERVER_URL", "https://github.com"),
+            ("GITHUB_REPOSITORY", "jdkaplan/squill"),
+            ("GITHUB_RUN_ID", "42"),
+        ]));
+        assert_eq!(Some("runner-1"), github.hostname.as_deref());
+        assert_eq!(Some("ci"), github.username.as_deref());
+        assert_eq!(
+            Some("https://github.com/jdkaplan/squill/actions/runs/42"),
+            github.ci_job_url.as_deref()
+        );
+
+        let gitlab = ApplyMetadata::from_vars(vars(&[
+            ("USER", ""),
+            ("USERNAME", "deploy"),
+            ("CI_JOB_URL", "https://gitlab.example.com/-/jobs/7"),
+        ]));
+        assert_eq!(Some("deploy"), gitlab.username.as_deref());
+        assert_eq!(
+            Some("https://gitlab.example.com/-/jobs/7"),
+            gitlab.ci_job_url.as_deref()
+        );
+
+        let local = ApplyMetadata::from_vars(vars(&[]));
+        assert_eq!(None, local.ci_job_url);
+    }
+
+    #[tokio::test]
+    async fn record_metadata() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+        let two = index.create(fake_migration(2, "two")).unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        assert!(ApplyMetadata::load_all(&mut conn).await.unwrap().is_empty());
+
+        one.up(&mut conn).await.unwrap();
+
+        let opts = MigrateOptions {
+            record_metadata: true,
+            ..Default::default()
+        };
+        two.up_with(&mut conn, &opts).await.unwrap();
+
+        let all = ApplyMetadata::load_all(&mut conn).await.unwrap();
+        assert_eq!(vec![&two.id], all.keys().collect::<Vec<_>>());
+        assert_eq!(ApplyMetadata::from_env().username, all[&two.id].username);
+    }
+}
@@ -1,11 +1,22 @@
 use lazy_static::lazy_static;
 use regex::Regex;
-use sqlx::postgres::PgConnection;
-use sqlx::{Connection, Executor, PgExecutor};
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use sqlx::{Acquire, Executor, PgConnection, PgExecutor, Postgres};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::Instrument;
+
+use crate::checkpoint::{clear_checkpoints, load_checkpoints, resume_point, save_checkpoint};
+use crate::directives::{DirectiveError, IsolationLevel, MigrationDirectives, DEFAULT_MAX_BATCHES};
+use crate::metadata::{applied_with, ApplyMetadata};
+use crate::naming::MigrationNaming;
+use crate::sql::split_statements;
+use crate::stats::StatsSnapshot;
 
 // Migration ID has to fit in an i64 for Postgres purposes, but it should always be non-negative.
-#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "i64", into = "i64")]
 pub struct MigrationId(pub(crate) i64);
 
 impl std::fmt::Display for MigrationId {
@@ -18,6 +29,38 @@ impl MigrationId {
     pub fn as_i64(&self) -> i64 {
         self.0
     }
+
+    /// The ID for a migration created at this time, in Unix seconds like the default id_strategy.
+    ///
+    /// Times before 1970 get ID 0.
+    pub fn from_timestamp(time: SystemTime) -> Self {
+        let seconds = time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Self(i64::try_from(seconds).unwrap_or(i64::MAX))
+    }
+
+    /// The ID for a migration created right now, in Unix seconds.
+    pub fn now() -> Self {
+        Self::from_timestamp(SystemTime::now())
+    }
+
+    /// When the migration was created, if the ID looks like a Unix timestamp in seconds.
+    ///
+    /// Only IDs from 2000 through 2099 count, so sequential IDs and other timestamp formats (like
+    /// 20240131123000 or milliseconds) don't get a nonsense date.
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        const PLAUSIBLE: std::ops::Range<i64> = 946_684_800..4_102_444_800;
+
+        if !PLAUSIBLE.contains(&self.0) {
+            return None;
+        }
+
+        let seconds = u64::try_from(self.0).ok()?;
+        Some(UNIX_EPOCH + Duration::from_secs(seconds))
+    }
 }
 
 impl MigrationId {
@@ -47,6 +90,7 @@ impl TryFrom<i64> for MigrationId {
 }
 
 #[derive(thiserror::Error, Debug, Clone)]
+#[non_exhaustive]
 pub enum ParseMigrationIdError {
     #[error(transparent)]
     ParseInt(#[from] std::num::ParseIntError),
@@ -74,6 +118,113 @@ pub struct MigrationDirectory {
     pub down_path: PathBuf,
 }
 
+/// The file name endings of a single-file migration, like `123-create_users.up.sql`.
+pub(crate) const SINGLE_FILE_SUFFIXES: [&str; 3] = [".up.sql", ".down.sql", ".verify.sql"];
+
+/// Add a file name ending to the stem of a single-file migration.
+pub(crate) fn with_suffix(stem: &Path, suffix: &str) -> PathBuf {
+    let mut path = stem.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+impl MigrationDirectory {
+    /// Read a single-file migration from its up file, like `123-create_users.up.sql`.
+    ///
+    /// The down file next to it (`123-create_users.down.sql`) is optional. For these migrations,
+    /// `dir` is the shared stem of the file names (`123-create_users`) rather than a directory.
+    pub fn from_up_file(
+        path: PathBuf,
+        naming: &MigrationNaming,
+    ) -> Result<Self, MigrationDirectoryError> {
+        if !path.is_file() {
+            return Err(MigrationDirectoryError::NotFile(path));
+        }
+
+        let Some(stem) = path
+            .to_str()
+            .and_then(|p| p.strip_suffix(SINGLE_FILE_SUFFIXES[0]))
+        else {
+            return Err(MigrationDirectoryError::InvalidDirectoryName(path));
+        };
+        let stem = PathBuf::from(stem);
+
+        let (id, name) = parse_migration_name(&stem, naming)
+            .ok_or_else(|| MigrationDirectoryError::InvalidDirectoryName(path.clone()))?;
+
+        Ok(MigrationDirectory {
+            id: id?,
+            name,
+            up_path: path,
+            down_path: with_suffix(&stem, SINGLE_FILE_SUFFIXES[1]),
+            dir: stem,
+        })
+    }
+
+    /// Whether this migration is missing its up file, so it can't be applied.
+    pub fn is_broken(&self) -> bool {
+        !self.up_path.is_file()
+    }
+
+    /// Whether this migration has a down file. Migrations without one are forward-only.
+    pub fn is_reversible(&self) -> bool {
+        self.down_path.exists()
+    }
+
+    /// Whether this migration is a pair of `.sql` files instead of a directory.
+    pub fn is_single_file(&self) -> bool {
+        self.up_path.parent() != Some(self.dir.as_path())
+    }
+
+    /// Whether this path is one of the migration's files.
+    pub fn contains(&self, path: &Path) -> bool {
+        if self.is_single_file() {
+            path == self.up_path || path == self.down_path || path == self.verify_path()
+        } else {
+            path.starts_with(&self.dir)
+        }
+    }
+
+    /// The optional acceptance check for the up migration.
+    ///
+    /// If this file exists, its query must return true after the up migration runs.
+    pub fn verify_path(&self) -> PathBuf {
+        if self.is_single_file() {
+            with_suffix(&self.dir, SINGLE_FILE_SUFFIXES[2])
+        } else {
+            self.dir.join("verify.sql")
+        }
+    }
+
+    /// The file to run in this direction for the active profile.
+    ///
+    /// A migration directory can override `up.sql` or `down.sql` for a profile with a file like
+    /// `up.production.sql`. Without one (or without a profile), this is the usual file.
+    pub fn sql_path(&self, direction: Direction, profile: Option<&str>) -> PathBuf {
+        let path = match direction {
+            Direction::Up => &self.up_path,
+            Direction::Down => &self.down_path,
+        };
+
+        let overridden = profile
+            .filter(|_| !self.is_single_file())
+            .and_then(|profile| {
+                let stem = path.file_stem()?.to_str()?;
+                Some(self.dir.join(format!("{stem}.{profile}.sql")))
+            });
+
+        match overridden {
+            Some(overridden) if overridden.is_file() => overridden,
+            _ => path.clone(),
+        }
+    }
+
+    /// The migrations directory that contains this migration.
+    pub fn root(&self) -> &Path {
+        self.dir.parent().unwrap_or(Path::new(""))
+    }
+}
+
 impl std::fmt::Display for MigrationDirectory {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.dir.to_string_lossy())
@@ -81,14 +232,18 @@ impl std::fmt::Display for MigrationDirectory {
 }
 
 #[derive(Clone, Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum MigrationDirectoryError {
     #[error("path is not a directory: {0:?}")]
     NotDirectory(PathBuf),
 
+    #[error("path is not a file: {0:?}")]
+    NotFile(PathBuf),
+
     #[error("invalid directory name: {0:?}")]
     InvalidDirectoryName(PathBuf),
 
-    #[error("invalid migration id: {0:?}")]
+    #[error("invalid migration id")]
     InvalidMigrationId(#[from] ParseMigrationIdError),
 }
 
@@ -96,31 +251,26 @@ impl TryFrom<PathBuf> for MigrationDirectory {
     type Error = MigrationDirectoryError;
 
     fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        Self::from_dir(path, &MigrationNaming::default())
+    }
+}
+
+impl MigrationDirectory {
+    /// Read a migration directory whose name follows this naming pattern.
+    pub fn from_dir(
+        path: PathBuf,
+        naming: &MigrationNaming,
+    ) -> Result<Self, MigrationDirectoryError> {
         if !path.is_dir() {
             return Err(MigrationDirectoryError::NotDirectory(path));
         }
 
-        lazy_static! {
-            static ref RE_MIGRATION: Regex =
-                Regex::new(r"^(?P<id>\d+)-(?P<name>.*)$").expect("static pattern");
-        }
-
-        let Some(m) = path
-            .file_name()
-            .and_then(|n| n.to_str())
-            .and_then(|n| RE_MIGRATION.captures(n))
-        else {
+        let Some((id, name)) = parse_migration_name(&path, naming) else {
             return Err(MigrationDirectoryError::InvalidDirectoryName(path));
         };
 
-        let id = m.name("id").expect("static capture group");
-        let id = id.as_str().parse()?;
-
-        let name = m.name("name").expect("static capture group");
-        let name = name.as_str().to_string();
-
         Ok(MigrationDirectory {
-            id,
+            id: id?,
             name,
             up_path: path.join("up.sql"),
             down_path: path.join("down.sql"),
@@ -129,13 +279,39 @@ impl TryFrom<PathBuf> for MigrationDirectory {
     }
 }
 
+/// Split a migration's file name into its parts.
+fn parse_migration_name(
+    path: &Path,
+    naming: &MigrationNaming,
+) -> Option<(Result<MigrationId, ParseMigrationIdError>, String)> {
+    let parsed = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .and_then(|n| naming.parse(n))?;
+
+    Some((parsed.id.parse(), parsed.name.to_string()))
+}
+
 pub fn skip_transaction(sql: &str) -> bool {
-    lazy_static! {
-        static ref RE_NO_TX: Regex =
-            Regex::new("(?m)^--squill:no-transaction").expect("static pattern");
-    }
+    MigrationDirectives::parse_all(sql).0.no_transaction
+}
+
+/// Find the IDs of the migrations that this one replaced when they were squashed together.
+pub fn squashed_ids(sql: &str) -> Vec<MigrationId> {
+    MigrationDirectives::parse_all(sql).0.squashes
+}
+
+/// Find the migration that replaced this one, if it has been retired.
+///
+/// A retired migration is kept for databases that already applied it, but new databases get its
+/// effects from the replacement instead.
+pub fn retired_by(sql: &str) -> Option<MigrationId> {
+    MigrationDirectives::parse_all(sql).0.retired_by
+}
 
-    RE_NO_TX.is_match(sql)
+/// Find the template group that was recorded in this SQL when the migration was created.
+pub fn template_group(sql: &str) -> Option<String> {
+    MigrationDirectives::parse_all(sql).0.template
 }
 
 pub async fn claim(
@@ -150,6 +326,18 @@ pub async fn claim(
     conn.execute(query).await
 }
 
+/// Set the squill.applied_with setting for the current transaction (if `local`) or the whole
+/// session, so the migration log can record what applied each migration.
+pub async fn set_applied_with(conn: &mut PgConnection, local: bool) -> sqlx::Result<()> {
+    sqlx::query("select set_config('squill.applied_with', $1, $2)")
+        .bind(applied_with())
+        .bind(local)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
 pub async fn unclaim(
     conn: impl PgExecutor<'_>,
     id: MigrationId,
@@ -159,75 +347,929 @@ pub async fn unclaim(
     conn.execute(query).await
 }
 
+/// Record how long an applied migration took.
+///
+/// Migration logs created before durations were tracked don't have `_squill_record_duration`, so
+/// this does nothing for them.
+pub async fn record_duration(
+    conn: &mut PgConnection,
+    id: MigrationId,
+    duration: Duration,
+) -> sqlx::Result<()> {
+    let supported: bool = sqlx::query_scalar(
+        "select to_regprocedure('_squill_record_duration(bigint, bigint)') is not null",
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if !supported {
+        return Ok(());
+    }
+
+    let query = sqlx::query("select _squill_record_duration($1, $2)")
+        .bind(id.as_i64())
+        .bind(duration.as_millis() as i64);
+
+    conn.execute(query).await?;
+    Ok(())
+}
+
+/// Change the name recorded for an applied migration.
+///
+/// This returns false if the migration isn't in the migration log.
+pub async fn rename_claimed(
+    conn: impl PgExecutor<'_>,
+    id: MigrationId,
+    name: &str,
+) -> sqlx::Result<bool> {
+    let query = sqlx::query("update schema_migrations set name = $2 where id = $1")
+        .bind(id.as_i64())
+        .bind(name);
+
+    let result = conn.execute(query).await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Find statements that Postgres refuses to run inside a transaction block.
+pub fn requires_no_transaction(sql: &str) -> bool {
+    lazy_static! {
+        static ref RE_LINE_COMMENT: Regex = Regex::new(r"--[^\n]*").expect("static pattern");
+        static ref RE_CONCURRENTLY: Regex = Regex::new(
+            r"(?ix)
+            \b (?: create \s+ (?: unique \s+ )? index | drop \s+ index | reindex \s+ (?: \( [^)]* \) \s+ )? \w+ )
+            \s+ concurrently \b
+            "
+        )
+        .expect("static pattern");
+    }
+
+    let sql = RE_LINE_COMMENT.replace_all(sql, "");
+    RE_CONCURRENTLY.is_match(&sql)
+}
+
+/// Settings that change how a migration file is run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrateOptions {
+    /// Refuse to run down migrations.
+    pub only_up: bool,
+
+    /// Run files that can't be run in a transaction (like `create index concurrently`) as if
+    /// they had the no-transaction directive, then record them in the migration log.
+    pub auto_no_transaction: bool,
+
+    /// Run each statement separately and report progress after each one finishes.
+    ///
+    /// Without this, the whole file is sent to the database at once.
+    pub progress: Option<ProgressCallback>,
+
+    /// Record a summary of server statistics (rows and buffers) for each up migration in the
+    /// schema_migration_stats table.
+    pub collect_stats: bool,
+
+    /// Record where each up migration was applied from (host, user, and CI job) in the
+    /// schema_migration_metadata table.
+    pub record_metadata: bool,
+
+    /// For migrations that run outside of a transaction, skip the statements that finished in an
+    /// earlier failed run instead of starting over.
+    ///
+    /// Progress is only saved (in the schema_migration_checkpoints table) for migrations with a
+    /// `--squill:checkpoints` directive, so this only has to be set when retrying one of those.
+    pub resume: bool,
+
+    /// Cancel any statement that runs longer than this. A `--squill:statement-timeout=5s`
+    /// directive in the file overrides it.
+    pub statement_timeout: Option<Duration>,
+
+    /// Stop waiting for a lock after this long. A `--squill:lock-timeout=5s` directive in the
+    /// file overrides it.
+    pub lock_timeout: Option<Duration>,
+
+    /// The active profile. Migration directories can have files like `up.production.sql` to run
+    /// instead of `up.sql` for a certain profile.
+    pub profile: Option<String>,
+}
+
+/// Progress through a migration file, reported after each statement finishes.
+#[derive(Debug, Clone, Copy)]
+pub struct StatementProgress<'a> {
+    pub migration: &'a MigrationDirectory,
+    pub sql: &'a str,
+
+    /// How many statements have finished so far, including this one.
+    pub completed: usize,
+    pub total: usize,
+
+    /// How long this statement took to run.
+    pub elapsed: Duration,
+}
+
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(&StatementProgress) + Send + Sync>);
+
+impl ProgressCallback {
+    pub fn new(f: impl Fn(&StatementProgress) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+}
+
+impl std::fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+impl PartialEq for ProgressCallback {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ProgressCallback {}
+
+/// The session timeouts a migration runs with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Timeouts {
+    pub(crate) statement: Option<Duration>,
+    pub(crate) lock: Option<Duration>,
+}
+
+impl Timeouts {
+    fn settings(&self) -> impl Iterator<Item = (&'static str, Duration)> {
+        [
+            ("statement_timeout", self.statement),
+            ("lock_timeout", self.lock),
+        ]
+        .into_iter()
+        .filter_map(|(setting, timeout)| Some((setting, timeout?)))
+    }
+
+    /// Set the timeouts for the current transaction (if `local`) or the whole session.
+    pub(crate) async fn set(&self, conn: &mut PgConnection, local: bool) -> sqlx::Result<()> {
+        for (setting, timeout) in self.settings() {
+            sqlx::query("select set_config($1, $2, $3)")
+                .bind(setting)
+                .bind(format!("{}ms", timeout.as_millis()))
+                .bind(local)
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Put session timeouts back to the connection's defaults, so they don't apply to whatever
+    /// uses the connection next.
+    pub(crate) async fn reset(&self, conn: &mut PgConnection) {
+        for (setting, _) in self.settings() {
+            // The setting names are static, so interpolating them is okay.
+            if let Err(err) = conn.execute(&*format!("reset {setting}")).await {
+                tracing::warn!("failed to reset {}: {}", setting, err);
+            }
+        }
+    }
+}
+
+/// Set the isolation level of the current transaction. This has to come before any other query
+/// in the transaction.
+pub(crate) async fn set_isolation(
+    conn: &mut PgConnection,
+    isolation: IsolationLevel,
+) -> sqlx::Result<()> {
+    // The levels are static, so interpolating them is okay.
+    let sql = format!("set transaction isolation level {}", isolation.as_sql());
+    conn.execute(&*sql).await?;
+    Ok(())
+}
+
+/// How long to wait before the first retry of a serialization failure or deadlock. Each retry
+/// waits about twice as long as the one before.
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Whether this failure was a serialization failure or deadlock, which can succeed if the
+/// migration is run again.
+fn is_retryable(err: &MigrateError) -> bool {
+    let (MigrateError::Execute { err, .. } | MigrateError::Verify { err, .. }) = err else {
+        return false;
+    };
+
+    match err {
+        // serialization_failure, deadlock_detected
+        sqlx::Error::Database(db_err) => {
+            matches!(db_err.code().as_deref(), Some("40001" | "40P01"))
+        }
+        _ => false,
+    }
+}
+
+/// Pick a delay between half of this one and all of it, so runs that conflicted with each other
+/// don't retry at the same time.
+fn jitter(delay: Duration) -> Duration {
+    use std::hash::{BuildHasher, Hasher};
+
+    // The standard library doesn't have a random number generator, but its hasher keys are random.
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    let fraction = (random % 1000) as f64 / 1000.0;
+
+    delay.mul_f64(0.5 + fraction / 2.0)
+}
+
+/// Whether a migration is being applied or undone.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
 impl MigrationDirectory {
-    pub async fn up(&self, conn: &mut PgConnection) -> Result<(), MigrateError> {
+    /// Read the template group this migration was created from.
+    ///
+    /// This is `None` for migrations that were written by hand or created before template groups
+    /// were recorded.
+    pub fn template(&self) -> Result<Option<String>, MigrateError> {
         let sql = std::fs::read_to_string(&self.up_path).map_err(|err| MigrateError::Read {
-            path: self.up_path.to_path_buf(),
+            path: self.up_path.clone(),
             err,
         })?;
 
-        if skip_transaction(&sql) {
-            conn.execute(&*sql).await.map_err(MigrateError::Execute)?;
-        } else {
-            let id = self.id;
-            let name = self.name.clone();
-
-            conn.transaction(|conn| {
-                Box::pin(async move {
-                    claim(&mut **conn, id, &name).await?;
-                    conn.execute(&*sql).await
-                })
-            })
+        Ok(template_group(&sql))
+    }
+
+    /// Read the directives in the up file.
+    pub fn directives(&self) -> Result<MigrationDirectives, MigrateError> {
+        let sql = std::fs::read_to_string(&self.up_path).map_err(|err| MigrateError::Read {
+            path: self.up_path.clone(),
+            err,
+        })?;
+
+        MigrationDirectives::parse(&sql).map_err(|err| MigrateError::Directive {
+            path: self.up_path.clone(),
+            err,
+        })
+    }
+
+    pub async fn up<'c, A>(&self, conn: A) -> Result<(), MigrateError>
+    where
+        A: Acquire<'c, Database = Postgres>,
+    {
+        self.up_with(conn, &MigrateOptions::default()).await
+    }
+
+    pub async fn up_with<'c, A>(&self, conn: A, opts: &MigrateOptions) -> Result<(), MigrateError>
+    where
+        A: Acquire<'c, Database = Postgres>,
+    {
+        if !opts.collect_stats && !opts.record_metadata {
+            return self.run(conn, Direction::Up, opts).await;
+        }
+
+        let mut conn = conn
+            .acquire()
             .await
-            .map_err(MigrateError::Execute)?;
+            .map_err(|err| self.execute_error(err))?;
+
+        let before = if opts.collect_stats {
+            Some(StatsSnapshot::take(&mut conn).await)
+        } else {
+            None
+        };
+
+        self.run(&mut *conn, Direction::Up, opts).await?;
+
+        // The migration has already been committed, so failing to record stats or metadata
+        // shouldn't be reported as a migration failure.
+        if let Some(before) = before {
+            let recorded = match before {
+                Ok(before) => match StatsSnapshot::take(&mut conn).await {
+                    Ok(after) => after.since(&before).record(&mut conn, self.id).await,
+                    Err(err) => Err(err),
+                },
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = recorded {
+                tracing::warn!(
+                    id = %self.id,
+                    "failed to record stats for migration {}: {}",
+                    self,
+                    err
+                );
+            }
+        }
+
+        if opts.record_metadata {
+            let metadata = ApplyMetadata::from_env();
+            if let Err(err) = metadata.record(&mut conn, self.id).await {
+                tracing::warn!(
+                    id = %self.id,
+                    "failed to record metadata for migration {}: {}",
+                    self,
+                    err
+                );
+            }
         }
 
         Ok(())
     }
 
-    pub async fn down(&self, conn: &mut PgConnection, only_up: bool) -> Result<(), MigrateError> {
-        if only_up {
+    pub async fn down<'c, A>(&self, conn: A, only_up: bool) -> Result<(), MigrateError>
+    where
+        A: Acquire<'c, Database = Postgres>,
+    {
+        let opts = MigrateOptions {
+            only_up,
+            ..Default::default()
+        };
+        self.down_with(conn, &opts).await
+    }
+
+    pub async fn down_with<'c, A>(&self, conn: A, opts: &MigrateOptions) -> Result<(), MigrateError>
+    where
+        A: Acquire<'c, Database = Postgres>,
+    {
+        if opts.only_up {
             return Err(MigrateError::OnlyUp);
         }
 
-        let sql = std::fs::read_to_string(&self.down_path).map_err(|err| MigrateError::Read {
-            path: self.down_path.to_path_buf(),
+        if !self.is_reversible() {
+            return Err(MigrateError::NoDownMigration(self.id));
+        }
+
+        if self.directives()?.only_up {
+            return Err(MigrateError::OnlyUpMigration(self.id));
+        }
+
+        self.run(conn, Direction::Down, opts).await
+    }
+
+    /// Every migration run gets a span with the same fields, so they can be compared across runs
+    /// (and exported to an observability stack).
+    #[tracing::instrument(
+        name = "migration",
+        skip_all,
+        fields(
+            id = %self.id,
+            name = %self.name,
+            direction = ?direction,
+            statements = tracing::field::Empty,
+            duration_ms = tracing::field::Empty,
+            success = tracing::field::Empty,
+        ),
+    )]
+    async fn run<'c, A>(
+        &self,
+        conn: A,
+        direction: Direction,
+        opts: &MigrateOptions,
+    ) -> Result<(), MigrateError>
+    where
+        A: Acquire<'c, Database = Postgres>,
+    {
+        let started = Instant::now();
+        let result = self.run_in_span(conn, direction, opts).await;
+        let duration = started.elapsed();
+
+        let span = tracing::Span::current();
+        span.record("duration_ms", duration.as_millis() as u64);
+        span.record("success", result.is_ok());
+
+        match &result {
+            Ok(()) => tracing::info!(
+                duration_ms = duration.as_millis() as u64,
+                "Finished migration: {}",
+                self
+            ),
+            Err(err) => tracing::error!(
+                duration_ms = duration.as_millis() as u64,
+                error = %crate::error_chain(err),
+                "Migration failed: {}",
+                self
+            ),
+        }
+
+        result
+    }
+
+    async fn run_in_span<'c, A>(
+        &self,
+        conn: A,
+        direction: Direction,
+        opts: &MigrateOptions,
+    ) -> Result<(), MigrateError>
+    where
+        A: Acquire<'c, Database = Postgres>,
+    {
+        let path = &self.sql_path(direction, opts.profile.as_deref());
+        if path != &self.up_path && path != &self.down_path {
+            tracing::info!("Using {} for {}", path.to_string_lossy(), self);
+        }
+
+        let sql = std::fs::read_to_string(path).map_err(|err| MigrateError::Read {
+            path: path.to_path_buf(),
             err,
         })?;
 
-        if skip_transaction(&sql) {
-            conn.execute(&*sql).await.map_err(MigrateError::Execute)?;
+        let verify = match direction {
+            Direction::Up => self.read_verify()?,
+            Direction::Down => None,
+        };
+
+        let directives =
+            MigrationDirectives::parse(&sql).map_err(|err| MigrateError::Directive {
+                path: path.to_path_buf(),
+                err,
+            })?;
+        tracing::Span::current().record("statements", split_statements(&sql).len());
+
+        let timeouts = Timeouts {
+            statement: directives.statement_timeout.or(opts.statement_timeout),
+            lock: directives.lock_timeout.or(opts.lock_timeout),
+        };
+
+        if directives.isolation.is_some()
+            && (directives.no_transaction || requires_no_transaction(&sql))
+        {
+            return Err(MigrateError::IsolationWithoutTransaction(
+                path.to_path_buf(),
+            ));
+        }
+
+        if directives.batched && (directives.no_transaction || requires_no_transaction(&sql)) {
+            return Err(MigrateError::BatchedWithoutTransaction(path.to_path_buf()));
+        }
+
+        let mut conn = conn
+            .acquire()
+            .await
+            .map_err(|err| self.execute_error(err))?;
+
+        if direction == Direction::Up {
+            self.check_dependencies(&mut conn, &directives.depends_on)
+                .await?;
+        }
+
+        // Only up migrations outside of a transaction can be resumed, and only if they ask for
+        // it. Otherwise, the file runs all at once like any other.
+        let checkpoint = direction == Direction::Up && directives.checkpoints;
+        let started = Instant::now();
+
+        if directives.no_transaction {
+            // The file records itself, so the check can only report a failure afterward.
+            timeouts
+                .set(&mut conn, false)
+                .await
+                .map_err(|err| self.execute_error(err))?;
+            set_applied_with(&mut conn, false)
+                .await
+                .map_err(|err| self.execute_error(err))?;
+
+            let result: Result<(), MigrateError> = async {
+                self.execute(&mut conn, &sql, opts, checkpoint).await?;
+                self.verify(&mut conn, verify.as_deref()).await?;
+                self.record_duration(&mut conn, direction, started.elapsed())
+                    .await
+            }
+            .await;
+
+            timeouts.reset(&mut conn).await;
+            result?;
+        } else if requires_no_transaction(&sql) {
+            if !opts.auto_no_transaction {
+                return Err(MigrateError::RequiresNoTransaction(path.to_path_buf()));
+            }
+
+            tracing::warn!(
+                "running migration outside of a transaction: {}",
+                path.to_string_lossy()
+            );
+
+            timeouts
+                .set(&mut conn, false)
+                .await
+                .map_err(|err| self.execute_error(err))?;
+            set_applied_with(&mut conn, false)
+                .await
+                .map_err(|err| self.execute_error(err))?;
+
+            let result: Result<(), MigrateError> = async {
+                self.execute(&mut conn, &sql, opts, checkpoint).await?;
+                self.verify(&mut conn, verify.as_deref()).await?;
+                self.record(&mut *conn, direction)
+                    .await
+                    .map_err(|err| self.claim_error(err))?;
+                self.record_duration(&mut conn, direction, started.elapsed())
+                    .await
+            }
+            .await;
+
+            timeouts.reset(&mut conn).await;
+            result?;
+        } else if directives.batched {
+            let max_batches = directives.max_batches.unwrap_or(DEFAULT_MAX_BATCHES);
+            let mut batches = 0;
+
+            loop {
+                if batches == max_batches {
+                    return Err(MigrateError::TooManyBatches {
+                        path: path.to_path_buf(),
+                        max: max_batches,
+                    });
+                }
+
+                let mut tx = (&mut *conn)
+                    .begin()
+                    .await
+                    .map_err(|err| self.execute_error(err))?;
+                if let Some(isolation) = directives.isolation {
+                    set_isolation(&mut tx, isolation)
+                        .await
+                        .map_err(|err| self.execute_error(err))?;
+                }
+                timeouts
+                    .set(&mut tx, true)
+                    .await
+                    .map_err(|err| self.execute_error(err))?;
+                set_applied_with(&mut tx, true)
+                    .await
+                    .map_err(|err| self.execute_error(err))?;
+
+                let rows = (&mut *tx)
+                    .execute(&*sql)
+                    .await
+                    .map_err(|err| self.execute_error(err))?
+                    .rows_affected();
+                batches += 1;
+
+                if rows > 0 {
+                    tx.commit().await.map_err(|err| self.execute_error(err))?;
+                    tracing::info!(
+                        id = %self.id,
+                        batch = batches,
+                        rows,
+                        "Finished batch {} of {} ({} rows)",
+                        batches,
+                        self,
+                        rows
+                    );
+                    continue;
+                }
+
+                // Nothing was left to change, so this batch records the migration.
+                self.record(&mut *tx, direction)
+                    .await
+                    .map_err(|err| self.claim_error(err))?;
+                self.verify(&mut tx, verify.as_deref()).await?;
+                self.record_duration(&mut tx, direction, started.elapsed())
+                    .await?;
+
+                tx.commit().await.map_err(|err| self.execute_error(err))?;
+                break;
+            }
         } else {
-            let id = self.id;
+            let mut attempts = 0;
+            let mut delay = RETRY_BACKOFF;
 
-            conn.transaction(|conn| {
-                Box::pin(async move {
-                    unclaim(&mut **conn, id).await?;
-                    conn.execute(&*sql).await
-                })
-            })
+            loop {
+                let result: Result<(), MigrateError> = async {
+                    let mut tx = (&mut *conn)
+                        .begin()
+                        .await
+                        .map_err(|err| self.execute_error(err))?;
+                    if let Some(isolation) = directives.isolation {
+                        set_isolation(&mut tx, isolation)
+                            .await
+                            .map_err(|err| self.execute_error(err))?;
+                    }
+                    timeouts
+                        .set(&mut tx, true)
+                        .await
+                        .map_err(|err| self.execute_error(err))?;
+                    set_applied_with(&mut tx, true)
+                        .await
+                        .map_err(|err| self.execute_error(err))?;
+
+                    self.record(&mut *tx, direction)
+                        .await
+                        .map_err(|err| self.claim_error(err))?;
+                    self.execute(&mut tx, &sql, opts, false).await?;
+                    self.verify(&mut tx, verify.as_deref()).await?;
+                    self.record_duration(&mut tx, direction, started.elapsed())
+                        .await?;
+
+                    tx.commit().await.map_err(|err| self.execute_error(err))
+                }
+                .await;
+
+                match result {
+                    Err(err) if attempts < directives.retries && is_retryable(&err) => {
+                        let wait = jitter(delay);
+                        tracing::warn!(
+                            id = %self.id,
+                            "{}, retrying in {:?}",
+                            crate::error_chain(&err),
+                            wait
+                        );
+                        futures_timer::Delay::new(wait).await;
+
+                        attempts += 1;
+                        delay *= 2;
+                    }
+                    result => break result?,
+                }
+            }
+        }
+
+        // Undoing the migration also throws away what a failed attempt to apply it left behind.
+        if checkpoint || direction == Direction::Down {
+            self.finish_checkpoints(&mut conn).await;
+        }
+
+        Ok(())
+    }
+
+    /// Make sure the migrations from depends-on directives have been applied.
+    async fn check_dependencies(
+        &self,
+        conn: &mut PgConnection,
+        depends_on: &[MigrationId],
+    ) -> Result<(), MigrateError> {
+        if depends_on.is_empty() {
+            return Ok(());
+        }
+
+        let ids: Vec<i64> = depends_on.iter().map(|id| id.as_i64()).collect();
+        let applied: Vec<i64> =
+            sqlx::query_scalar("select id from schema_migrations where id = any($1)")
+                .bind(&ids)
+                .fetch_all(&mut *conn)
+                .await
+                .map_err(|err| self.execute_error(err))?;
+
+        match depends_on.iter().find(|id| !applied.contains(&id.as_i64())) {
+            Some(dependency) => Err(MigrateError::MissingDependency {
+                id: self.id,
+                dependency: *dependency,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Run the file, saving a checkpoint after each statement if `checkpoint` is set.
+    async fn execute(
+        &self,
+        conn: &mut PgConnection,
+        sql: &str,
+        opts: &MigrateOptions,
+        checkpoint: bool,
+    ) -> Result<(), MigrateError> {
+        if !checkpoint && opts.progress.is_none() {
+            conn.execute(sql)
+                .await
+                .map_err(|err| self.execute_error(err))?;
+            return Ok(());
+        }
+
+        let statements = split_statements(sql);
+        let total = statements.len();
+
+        let done = if checkpoint {
+            self.start_checkpoints(conn, &statements, opts.resume)
+                .await?
+        } else {
+            0
+        };
+
+        if done > 0 {
+            tracing::info!("Resuming {} after statement {}/{}", self, done, total);
+        }
+
+        for (i, statement) in statements.into_iter().enumerate().skip(done) {
+            let span = tracing::debug_span!("statement", number = i + 1, total,);
+
+            let started = Instant::now();
+            (&mut *conn)
+                .execute(statement)
+                .instrument(span)
+                .await
+                .map_err(|err| self.execute_error(err))?;
+            let elapsed = started.elapsed();
+
+            tracing::debug!(
+                elapsed_ms = elapsed.as_millis() as u64,
+                "Finished statement {}/{} of {} in {:?}",
+                i + 1,
+                total,
+                self,
+                elapsed
+            );
+
+            if checkpoint {
+                save_checkpoint(conn, self.id, i, statement)
+                    .await
+                    .map_err(|err| self.execute_error(err))?;
+            }
+
+            if let Some(progress) = &opts.progress {
+                (progress.0)(&StatementProgress {
+                    migration: self,
+                    sql: statement,
+                    completed: i + 1,
+                    total,
+                    elapsed,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Find where to start running the file, clearing any earlier progress unless resuming.
+    async fn start_checkpoints(
+        &self,
+        conn: &mut PgConnection,
+        statements: &[&str],
+        resume: bool,
+    ) -> Result<usize, MigrateError> {
+        if !resume {
+            clear_checkpoints(conn, self.id)
+                .await
+                .map_err(|err| self.execute_error(err))?;
+            return Ok(0);
+        }
+
+        let checkpoints = load_checkpoints(conn, self.id)
             .await
-            .map_err(MigrateError::Execute)?;
+            .map_err(|err| self.execute_error(err))?;
+
+        resume_point(statements, &checkpoints).map_err(|i| MigrateError::CheckpointChanged {
+            path: self.up_path.clone(),
+            statement: i + 1,
+        })
+    }
+
+    /// The migration finished, so its checkpoints aren't needed anymore.
+    async fn finish_checkpoints(&self, conn: &mut PgConnection) {
+        if let Err(err) = clear_checkpoints(conn, self.id).await {
+            tracing::warn!(
+                id = %self.id,
+                "failed to clear checkpoints for migration {}: {}",
+                self,
+                err
+            );
+        }
+    }
+
+    fn read_verify(&self) -> Result<Option<String>, MigrateError> {
+        let path = self.verify_path();
+
+        match std::fs::read_to_string(&path) {
+            Ok(sql) => Ok(Some(sql)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(MigrateError::Read { path, err }),
+        }
+    }
+
+    async fn verify(&self, conn: &mut PgConnection, sql: Option<&str>) -> Result<(), MigrateError> {
+        let Some(sql) = sql else {
+            return Ok(());
+        };
+
+        let passed: Option<bool> =
+            sqlx::query_scalar(sql)
+                .fetch_one(conn)
+                .await
+                .map_err(|err| MigrateError::Verify {
+                    path: self.verify_path(),
+                    err,
+                })?;
+
+        if passed != Some(true) {
+            return Err(MigrateError::VerifyFailed(self.verify_path()));
         }
 
         Ok(())
     }
+
+    async fn record_duration(
+        &self,
+        conn: &mut PgConnection,
+        direction: Direction,
+        duration: Duration,
+    ) -> Result<(), MigrateError> {
+        match direction {
+            Direction::Up => record_duration(conn, self.id, duration)
+                .await
+                .map_err(|err| self.execute_error(err)),
+            Direction::Down => Ok(()),
+        }
+    }
+
+    /// A duplicate claim means another runner applied this migration first.
+    fn claim_error(&self, err: sqlx::Error) -> MigrateError {
+        if let sqlx::Error::Database(ref db_err) = err {
+            // unique_violation
+            if db_err.code().as_deref() == Some("23505") {
+                return MigrateError::AlreadyApplied(self.id);
+            }
+        }
+        self.execute_error(err)
+    }
+
+    fn execute_error(&self, err: sqlx::Error) -> MigrateError {
+        MigrateError::Execute {
+            id: self.id,
+            name: self.name.clone(),
+            err,
+        }
+    }
+
+    async fn record(
+        &self,
+        conn: impl PgExecutor<'_>,
+        direction: Direction,
+    ) -> sqlx::Result<<sqlx::Postgres as sqlx::Database>::QueryResult> {
+        match direction {
+            Direction::Up => claim(conn, self.id, &self.name).await,
+            Direction::Down => unclaim(conn, self.id).await,
+        }
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum MigrateError {
-    #[error("failed to read migration file: {path}: {err}")]
-    Read { path: PathBuf, err: std::io::Error },
+    #[error("failed to read migration file: {path}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
 
-    #[error("failed to execute migration: {0}")]
-    Execute(sqlx::Error),
+    #[error("failed to execute migration {id}-{name}")]
+    Execute {
+        id: MigrationId,
+        name: String,
+        #[source]
+        err: sqlx::Error,
+    },
 
-    #[error("cannot execute down migration: not allowed with only_up")]
+    #[error("cannot execute down migration: not allowed with only_up (disable only_up to allow down migrations in development)")]
     OnlyUp,
+
+    #[error("migration {0} has no down migration, so it can't be undone")]
+    NoDownMigration(MigrationId),
+
+    #[error("migration cannot run in a transaction: {}: add --squill:no-transaction or enable auto_no_transaction", .0.to_string_lossy())]
+    RequiresNoTransaction(PathBuf),
+
+    #[error("--squill:isolation needs a transaction, but this migration runs outside of one: {}", .0.to_string_lossy())]
+    IsolationWithoutTransaction(PathBuf),
+
+    #[error("--squill:batched commits after each batch, so it can't be combined with no-transaction: {}", .0.to_string_lossy())]
+    BatchedWithoutTransaction(PathBuf),
+
+    #[error("batched migration still changed rows after {max} batches: {}: check that each batch only changes rows that haven't been changed yet, or raise --squill:max-batches", .path.to_string_lossy())]
+    TooManyBatches { path: PathBuf, max: u32 },
+
+    #[error("failed to run verification query: {path}")]
+    Verify {
+        path: PathBuf,
+        #[source]
+        err: sqlx::Error,
+    },
+
+    #[error("verification query did not return true: {}", .0.to_string_lossy())]
+    VerifyFailed(PathBuf),
+
+    #[error("migration {0} was already applied (probably by another runner at the same time)")]
+    AlreadyApplied(MigrationId),
+
+    #[error("cannot resume migration: statement {statement} of {} changed since the failed run", .path.to_string_lossy())]
+    CheckpointChanged { path: PathBuf, statement: usize },
+
+    #[error("invalid directive in {}", .path.to_string_lossy())]
+    Directive {
+        path: PathBuf,
+        #[source]
+        err: DirectiveError,
+    },
+
+    #[error("migration {0} has the only-up directive, so it can't be undone")]
+    OnlyUpMigration(MigrationId),
+
+    #[error("migration {id} depends on migration {dependency}, which hasn't been applied")]
+    MissingDependency {
+        id: MigrationId,
+        dependency: MigrationId,
+    },
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::db::MigrationLog;
+    use crate::index::{MigrationIndex, MigrationParams};
     use crate::testing::*;
 
     use super::*;
@@ -242,6 +1284,565 @@ mod tests {
         assert!(!skip_transaction(NO_OP_YES_TX));
     }
 
+    #[test]
+    fn concurrent_index() {
+        assert!(requires_no_transaction(
+            "create index concurrently users_email on users (email);"
+        ));
+        assert!(requires_no_transaction(
+            "CREATE UNIQUE INDEX\n  CONCURRENTLY users_email on users (email);"
+        ));
+        assert!(requires_no_transaction(
+            "drop index concurrently users_email;"
+        ));
+        assert!(requires_no_transaction(
+            "reindex (verbose) index concurrently users_email;"
+        ));
+
+        assert!(!requires_no_transaction(
+            "create index users_email on users (email);"
+        ));
+        assert!(!requires_no_transaction(
+            "-- TODO: create index concurrently later\ncreate table users (id int);"
+        ));
+    }
+
+    #[tokio::test]
+    async fn concurrent_index_in_transaction() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+        let two = index
+            .create(MigrationParams {
+                id: MigrationId(2),
+                name: String::from("index_one"),
+                up_sql: String::from("create index concurrently idx_one on tbl_one (id_1);"),
+                down_sql: Some(String::from("drop index concurrently idx_one;")),
+            })
+            .unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        one.up(&mut conn).await.unwrap();
+
+        match two.up(&mut conn).await {
+            Err(MigrateError::RequiresNoTransaction(path)) => assert_eq!(two.up_path, path),
+
+            Err(err) => panic!("Unexpected error: {:?}", err),
+            Ok(_) => panic!("Unexpected success"),
+        }
+
+        let opts = MigrateOptions {
+            auto_no_transaction: true,
+            ..Default::default()
+        };
+        two.up_with(&mut conn, &opts).await.unwrap();
+
+        let log = MigrationLog::new(&mut conn).await.unwrap();
+        assert!(log.get(MigrationId(2)).is_some());
+
+        two.down_with(&mut conn, &opts).await.unwrap();
+
+        let log = MigrationLog::new(&mut conn).await.unwrap();
+        assert!(log.get(MigrationId(2)).is_none());
+    }
+
+    #[tokio::test]
+    async fn verify_query() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+        let two = index.create(fake_migration(2, "two")).unwrap();
+
+        std::fs::write(
+            one.verify_path(),
+            "select count(*) = 0 from tbl_one where id_1 is not null",
+        )
+        .unwrap();
+        std::fs::write(two.verify_path(), "select false").unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        one.up(&mut conn).await.unwrap();
+
+        match two.up(&mut conn).await {
+            Err(MigrateError::VerifyFailed(path)) => assert_eq!(two.verify_path(), path),
+
+            Err(err) => panic!("Unexpected error: {:?}", err),
+            Ok(_) => panic!("Unexpected success"),
+        }
+
+        // The failed check rolls back the whole migration.
+        let log = MigrationLog::new(&mut conn).await.unwrap();
+        assert!(log.get(MigrationId(1)).is_some());
+        assert!(log.get(MigrationId(2)).is_none());
+        conn.execute("select * from tbl_two").await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn already_applied() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let mut params = fake_migration(1, "one");
+        params.up_sql = String::from("create table if not exists tbl_one (id int)");
+        let one = index.create(params).unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        one.up(&mut conn).await.unwrap();
+
+        match one.up(&mut conn).await {
+            Err(MigrateError::AlreadyApplied(id)) => assert_eq!(one.id, id),
+
+            Err(err) => panic!("Unexpected error: {:?}", err),
+            Ok(_) => panic!("Unexpected success"),
+        }
+    }
+
+    #[tokio::test]
+    async fn resume_no_tx() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index
+            .create(MigrationParams {
+                id: MigrationId(1),
+                name: String::from("resume"),
+                up_sql: String::from(
+                    "--squill:no-transaction\n\
+                    --squill:checkpoints\n\
+                    create table resume_a (id int);\n\
+                    insert into resume_b values (1);\n\
+                    select _squill_claim_migration(1, 'resume');",
+                ),
+                down_sql: Some(String::from("drop table resume_a;")),
+            })
+            .unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        one.up(&mut conn).await.unwrap_err();
+
+        let checkpoints = load_checkpoints(&mut conn, one.id).await.unwrap();
+        assert_eq!(1, checkpoints.len(), "{:?}", checkpoints);
+        assert_eq!(
+            vec![one.id],
+            crate::checkpoint::checkpointed_migrations(&mut conn)
+                .await
+                .unwrap()
+        );
+
+        // Rerunning the first statement would fail now that the table exists.
+        conn.execute("create table resume_b (id int)")
+            .await
+            .unwrap();
+        let opts = MigrateOptions {
+            resume: true,
+            ..Default::default()
+        };
+        one.up_with(&mut conn, &opts).await.unwrap();
+
+        let log = MigrationLog::new(&mut conn).await.unwrap();
+        assert!(log.get(one.id).is_some());
+        assert!(load_checkpoints(&mut conn, one.id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn no_checkpoints_without_directive() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index
+            .create(MigrationParams {
+                id: MigrationId(1),
+                name: String::from("no_resume"),
+                up_sql: String::from(
+                    "--squill:no-transaction\n\
+                    create table no_resume_a (id int);\n\
+                    insert into no_resume_b values (1);\n\
+                    select _squill_claim_migration(1, 'no_resume');",
+                ),
+                down_sql: None,
+            })
+            .unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        one.up(&mut conn).await.unwrap_err();
+
+        assert!(load_checkpoints(&mut conn, one.id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn statement_progress() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index
+            .create(MigrationParams {
+                id: MigrationId(1),
+                name: String::from("three_statements"),
+                up_sql: String::from(
+                    "create table a (id int);\ncreate table b (id int);\ncreate table c (id int);",
+                ),
+                down_sql: Some(String::from("drop table a, b, c;")),
+            })
+            .unwrap();
+
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let opts = MigrateOptions {
+            progress: Some(ProgressCallback::new({
+                let seen = seen.clone();
+                move |p: &StatementProgress| seen.lock().unwrap().push((p.completed, p.total))
+            })),
+            ..Default::default()
+        };
+
+        let mut conn = config.connect().await.unwrap();
+        one.up_with(&mut conn, &opts).await.unwrap();
+
+        assert_eq!(vec![(1, 3), (2, 3), (3, 3)], *seen.lock().unwrap());
+
+        let log = MigrationLog::new(&mut conn).await.unwrap();
+        assert!(log.get(MigrationId(1)).is_some());
+    }
+
+    #[tokio::test]
+    async fn timeouts() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let sleepy = |id: i64, header: &str| MigrationParams {
+            id: MigrationId(id),
+            name: format!("sleepy_{id}"),
+            up_sql: format!("{header}\nselect pg_sleep(0.2);"),
+            down_sql: Some(String::from("select 1;")),
+        };
+        let one = index.create(sleepy(1, "")).unwrap();
+        let two = index
+            .create(sleepy(2, "--squill:statement-timeout=5s"))
+            .unwrap();
+        let three = index
+            .create(sleepy(
+                3,
+                "--squill:no-transaction\n--squill:statement-timeout=5s",
+            ))
+            .unwrap();
+        let four = index
+            .create(sleepy(4, "--squill:lock-timeout=later"))
+            .unwrap();
+
+        let opts = MigrateOptions {
+            statement_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+
+        let mut conn = config.connect().await.unwrap();
+        match one.up_with(&mut conn, &opts).await {
+            Err(MigrateError::Execute { .. }) => (),
+            res => panic!("Unexpected result: {res:?}"),
+        }
+
+        two.up_with(&mut conn, &opts).await.unwrap();
+
+        // Directives on no-transaction migrations change the session, so they're reset afterward.
+        three.up_with(&mut conn, &opts).await.unwrap();
+        let timeout: String = sqlx::query_scalar("show statement_timeout")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!("0", timeout);
+
+        match four.up_with(&mut conn, &opts).await {
+            Err(MigrateError::Directive {
+                err: DirectiveError::InvalidValue { key, .. },
+                ..
+            }) => assert_eq!("lock-timeout", key),
+            res => panic!("Unexpected result: {res:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn isolation() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index
+            .create(MigrationParams {
+                id: MigrationId(1),
+                name: String::from("backfill"),
+                up_sql: String::from(
+                    "--squill:isolation=serializable
+create table levels as select current_setting('transaction_isolation') as level;",
+                ),
+                down_sql: Some(String::from("drop table levels;")),
+            })
+            .unwrap();
+        let two = index
+            .create(MigrationParams {
+                id: MigrationId(2),
+                name: String::from("concurrently"),
+                up_sql: String::from(
+                    "--squill:no-transaction\n--squill:isolation=serializable\nselect 1;",
+                ),
+                down_sql: None,
+            })
+            .unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        one.up(&mut conn).await.unwrap();
+
+        let level: String = sqlx::query_scalar("select level from levels")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!("serializable", level);
+
+        match two.up(&mut conn).await {
+            Err(MigrateError::IsolationWithoutTransaction(path)) => {
+                assert_eq!(two.up_path, path)
+            }
+            res => panic!("Unexpected result: {res:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn retries() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut conn = config.connect().await.unwrap();
+        conn.execute("create sequence attempts_1; create sequence attempts_2;")
+            .await
+            .unwrap();
+
+        // Sequences aren't rolled back, so they count the attempts. Each migration fails with a
+        // serialization failure until its third attempt.
+        let flaky = |id: i64, retries: u32| MigrationParams {
+            id: MigrationId(id),
+            name: format!("flaky_{id}"),
+            up_sql: format!(
+                "--squill:retries={retries}
+do $$
+begin
+    if nextval('attempts_{id}') < 3 then
+        raise exception 'conflict' using errcode = 'serialization_failure';
+    end if;
+end
+$$;"
+            ),
+            down_sql: None,
+        };
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index.create(flaky(1, 1)).unwrap();
+        let two = index.create(flaky(2, 2)).unwrap();
+
+        match one.up(&mut conn).await {
+            Err(err) => assert!(is_retryable(&err), "{err:?}"),
+            res => panic!("Unexpected result: {res:?}"),
+        }
+
+        two.up(&mut conn).await.unwrap();
+
+        let attempts: i64 = sqlx::query_scalar("select last_value from attempts_2")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(3, attempts);
+    }
+
+    #[tokio::test]
+    async fn batched() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut conn = config.connect().await.unwrap();
+        conn.execute(
+            "create table items as select id, null::text as label from generate_series(1, 10) id;",
+        )
+        .await
+        .unwrap();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index
+            .create(MigrationParams {
+                id: MigrationId(1),
+                name: String::from("backfill_labels"),
+                up_sql: String::from(
+                    "--squill:batched
+update items set label = 'item ' || id
+where id in (select id from items where label is null order by id limit 3);",
+                ),
+                down_sql: None,
+            })
+            .unwrap();
+
+        one.up(&mut conn).await.unwrap();
+
+        let missing: i64 = sqlx::query_scalar("select count(*) from items where label is null")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(0, missing);
+
+        let log = MigrationLog::new(&mut conn).await.unwrap();
+        assert!(log.get(MigrationId(1)).is_some());
+    }
+
+    #[tokio::test]
+    async fn batched_max_batches() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut conn = config.connect().await.unwrap();
+        conn.execute("create table counters as select 1 as id, 0 as n;")
+            .await
+            .unwrap();
+
+        // This always changes a row, so it would never finish on its own.
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index
+            .create(MigrationParams {
+                id: MigrationId(1),
+                name: String::from("endless"),
+                up_sql: String::from(
+                    "--squill:batched\n--squill:max-batches=3\nupdate counters set n = n + 1;",
+                ),
+                down_sql: None,
+            })
+            .unwrap();
+
+        let err = one.up(&mut conn).await.unwrap_err();
+        assert!(
+            matches!(err, MigrateError::TooManyBatches { max: 3, .. }),
+            "{err:?}"
+        );
+
+        // The batches before the limit were committed, but the migration wasn't recorded.
+        let n: i32 = sqlx::query_scalar("select n from counters")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(3, n);
+
+        let log = MigrationLog::new(&mut conn).await.unwrap();
+        assert!(log.get(MigrationId(1)).is_none());
+    }
+
+    #[tokio::test]
+    async fn depends_on_and_only_up() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+        let two = index
+            .create(MigrationParams {
+                id: MigrationId(2),
+                name: String::from("needs_one"),
+                up_sql: String::from(
+                    "--squill:depends-on 1\n--squill:only-up\ncreate table needs_one (id int);",
+                ),
+                down_sql: Some(String::from("drop table needs_one;")),
+            })
+            .unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        match two.up(&mut conn).await {
+            Err(MigrateError::MissingDependency { id, dependency }) => {
+                assert_eq!((MigrationId(2), MigrationId(1)), (id, dependency))
+            }
+            res => panic!("Unexpected result: {res:?}"),
+        }
+
+        one.up(&mut conn).await.unwrap();
+        two.up(&mut conn).await.unwrap();
+
+        match two.down(&mut conn, false).await {
+            Err(MigrateError::OnlyUpMigration(id)) => assert_eq!(MigrationId(2), id),
+            res => panic!("Unexpected result: {res:?}"),
+        }
+
+        let log = MigrationLog::new(&mut conn).await.unwrap();
+        assert!(log.get(MigrationId(2)).is_some());
+    }
+
+    #[tokio::test]
+    async fn profile_files() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index
+            .create(MigrationParams {
+                id: MigrationId(1),
+                name: String::from("seed"),
+                up_sql: String::from(
+                    "create table seeded (id int); insert into seeded values (1);",
+                ),
+                down_sql: Some(String::from("drop table seeded;")),
+            })
+            .unwrap();
+        std::fs::write(
+            one.dir.join("up.production.sql"),
+            "create table seeded (id int);",
+        )
+        .unwrap();
+
+        assert_eq!(one.up_path, one.sql_path(Direction::Up, None));
+        assert_eq!(one.up_path, one.sql_path(Direction::Up, Some("staging")));
+        assert_eq!(
+            one.down_path,
+            one.sql_path(Direction::Down, Some("production"))
+        );
+
+        let opts = MigrateOptions {
+            profile: Some(String::from("production")),
+            ..Default::default()
+        };
+
+        let mut conn = config.connect().await.unwrap();
+        one.up_with(&mut conn, &opts).await.unwrap();
+
+        let count: i64 = sqlx::query_scalar("select count(*) from seeded")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(0, count);
+    }
+
+    #[test]
+    fn timestamp_ids() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let id = MigrationId::from_timestamp(time);
+        assert_eq!(MigrationId(1_700_000_000), id);
+        assert_eq!(Some(time), id.timestamp());
+
+        assert_eq!(
+            MigrationId(0),
+            MigrationId::from_timestamp(UNIX_EPOCH - Duration::from_secs(1))
+        );
+        assert!(MigrationId::now().timestamp().is_some());
+
+        // Sequential, datetime, and millisecond IDs aren't Unix seconds.
+        assert_eq!(None, MigrationId(12).timestamp());
+        assert_eq!(None, MigrationId(20240131123000).timestamp());
+        assert_eq!(None, MigrationId(1_700_000_000_000).timestamp());
+    }
+
     #[test]
     fn migration_ids() {
         MigrationId::try_from(0).unwrap();
@@ -0,0 +1,182 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// How migration directories (and single-file migrations) are named.
+///
+/// The pattern has one `{id}` and one `{name}` placeholder, like the default `{id}-{name}`,
+/// `{id}_{name}`, or Flyway-style `V{id}__{name}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct MigrationNaming {
+    pattern: String,
+    regex: Regex,
+}
+
+/// The parts of a migration directory name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParsedName<'a> {
+    /// The ID as written, including any zero padding.
+    pub id: &'a str,
+    pub name: &'a str,
+}
+
+pub const DEFAULT_NAMING: &str = "{id}-{name}";
+
+impl MigrationNaming {
+    pub fn new(pattern: &str) -> Result<Self, NamingError> {
+        for placeholder in ["{id}", "{name}"] {
+            if pattern.matches(placeholder).count() != 1 {
+                return Err(NamingError::Placeholder {
+                    pattern: pattern.to_owned(),
+                    placeholder,
+                });
+            }
+        }
+
+        if pattern.contains(['/', '\\']) {
+            return Err(NamingError::Separator(pattern.to_owned()));
+        }
+
+        let mut regex = String::from("^");
+        let mut rest = pattern;
+        while let Some(start) = rest.find('{') {
+            let (literal, tail) = rest.split_at(start);
+            regex.push_str(&regex::escape(literal));
+
+            if let Some(tail) = tail.strip_prefix("{id}") {
+                regex.push_str(r"(?P<id>\d+)");
+                rest = tail;
+            } else if let Some(tail) = tail.strip_prefix("{name}") {
+                regex.push_str("(?P<name>.*)");
+                rest = tail;
+            } else {
+                regex.push_str(r"\{");
+                rest = &tail[1..];
+            }
+        }
+        regex.push_str(&regex::escape(rest));
+        regex.push('$');
+
+        Ok(Self {
+            pattern: pattern.to_owned(),
+            regex: Regex::new(&regex).expect("escaped pattern"),
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Split a directory name into its ID and name, if it matches the pattern.
+    pub fn parse<'a>(&self, dir_name: &'a str) -> Option<ParsedName<'a>> {
+        let caps = self.regex.captures(dir_name)?;
+
+        Some(ParsedName {
+            id: caps.name("id").expect("static capture group").as_str(),
+            name: caps.name("name").expect("static capture group").as_str(),
+        })
+    }
+
+    /// The directory name for this ID (as it should be written) and name.
+    pub fn format(&self, id: &str, name: &str) -> String {
+        self.pattern.replace("{id}", id).replace("{name}", name)
+    }
+}
+
+impl Default for MigrationNaming {
+    fn default() -> Self {
+        Self::new(DEFAULT_NAMING).expect("valid default pattern")
+    }
+}
+
+impl PartialEq for MigrationNaming {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl Eq for MigrationNaming {}
+
+impl std::fmt::Display for MigrationNaming {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+impl TryFrom<String> for MigrationNaming {
+    type Error = NamingError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::new(&value)
+    }
+}
+
+impl From<MigrationNaming> for String {
+    fn from(value: MigrationNaming) -> Self {
+        value.pattern
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone)]
+#[non_exhaustive]
+pub enum NamingError {
+    #[error("naming pattern must have exactly one {placeholder}: {pattern:?}")]
+    Placeholder {
+        pattern: String,
+        placeholder: &'static str,
+    },
+
+    #[error("naming pattern can't have path separators: {0:?}")]
+    Separator(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        let parsed = |pattern: &str, dir_name: &'static str| {
+            MigrationNaming::new(pattern)
+                .unwrap()
+                .parse(dir_name)
+                .map(|p| (p.id, p.name))
+        };
+
+        assert_eq!(
+            Some(("0042", "create_users")),
+            parsed("{id}-{name}", "0042-create_users")
+        );
+        assert_eq!(Some(("1", "a-b")), parsed("{id}_{name}", "1_a-b"));
+        assert_eq!(Some(("7", "users")), parsed("V{id}__{name}", "V7__users"));
+        assert_eq!(Some(("7", "v.2")), parsed("{name}.{id}", "v.2.7"));
+
+        assert_eq!(None, parsed("{id}_{name}", "1-users"));
+        assert_eq!(None, parsed("V{id}__{name}", "7__users"));
+
+        let flyway = MigrationNaming::new("V{id}__{name}").unwrap();
+        assert_eq!("V0042__users", flyway.format("0042", "users"));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert!(matches!(
+            MigrationNaming::new("{name}"),
+            Err(NamingError::Placeholder {
+                placeholder: "{id}",
+                ..
+            })
+        ));
+        assert!(matches!(
+            MigrationNaming::new("{id}-{name}-{name}"),
+            Err(NamingError::Placeholder {
+                placeholder: "{name}",
+                ..
+            })
+        ));
+        assert!(matches!(
+            MigrationNaming::new("{id}/{name}"),
+            Err(NamingError::Separator(_))
+        ));
+    }
+}
@@ -0,0 +1,548 @@
+use std::time::Duration;
+
+use futures::future::{self, Either};
+#[cfg(feature = "email")]
+use lettre::transport::smtp::authentication::Credentials;
+#[cfg(feature = "email")]
+use lettre::{Message, SmtpTransport, Transport as _};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::error_chain;
+use crate::migrate::MigrationDirectory;
+
+/// What happened during a migration run.
+#[derive(Debug, Clone, Copy)]
+pub enum Notification<'a> {
+    Migrated {
+        applied: &'a [MigrationDirectory],
+    },
+    Failed {
+        applied: &'a [MigrationDirectory],
+        migration: &'a MigrationDirectory,
+        error: &'a str,
+    },
+}
+
+impl Notification<'_> {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Notification::Migrated { .. } => "migrated",
+            Notification::Failed { .. } => "failed",
+        }
+    }
+
+    pub fn succeeded(&self) -> bool {
+        matches!(self, Notification::Migrated { .. })
+    }
+
+    pub fn title(&self) -> String {
+        match self {
+            Notification::Migrated { applied } => match applied.len() {
+                1 => String::from("Applied 1 migration"),
+                n => format!("Applied {n} migrations"),
+            },
+            Notification::Failed { migration, .. } => format!("Migration failed: {migration}"),
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let list = |migrations: &[MigrationDirectory]| {
+            let lines: Vec<_> = migrations.iter().map(|m| format!("- {m}")).collect();
+            lines.join("\n")
+        };
+
+        match self {
+            Notification::Migrated { applied } => list(applied),
+            Notification::Failed {
+                applied,
+                migration,
+                error,
+            } => {
+                let mut text = format!("{migration}: {error}");
+                if !applied.is_empty() {
+                    text.push_str("\n\nApplied before the failure:\n");
+                    text.push_str(&list(applied));
+                }
+                text
+            }
+        }
+    }
+
+    #[cfg_attr(not(feature = "webhook"), allow(dead_code))]
+    fn payload(&self) -> serde_json::Value {
+        let entries = |migrations: &[MigrationDirectory]| {
+            let entries: Vec<_> = migrations
+                .iter()
+                .map(|m| json!({ "id": m.id.as_i64(), "name": m.name }))
+                .collect();
+            entries
+        };
+
+        let mut payload = json!({
+            "event": self.name(),
+            "title": self.title(),
+            "text": self.text(),
+        });
+
+        match self {
+            Notification::Migrated { applied } => {
+                payload["applied"] = entries(applied).into();
+            }
+            Notification::Failed {
+                applied,
+                migration,
+                error,
+            } => {
+                payload["applied"] = entries(applied).into();
+                payload["failed"] = json!({
+                    "id": migration.id.as_i64(),
+                    "name": migration.name,
+                    "error": error,
+                });
+            }
+        }
+
+        payload
+    }
+}
+
+/// How long a transport gets to deliver a notification before it's given up on.
+pub const NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An owned copy of a [`Notification`], so it can be sent from another thread.
+#[derive(Debug, Clone)]
+struct OwnedNotification {
+    applied: Vec<MigrationDirectory>,
+    failed: Option<(MigrationDirectory, String)>,
+}
+
+impl OwnedNotification {
+    fn new(notification: &Notification) -> Self {
+        match *notification {
+            Notification::Migrated { applied } => Self {
+                applied: applied.to_vec(),
+                failed: None,
+            },
+            Notification::Failed {
+                applied,
+                migration,
+                error,
+            } => Self {
+                applied: applied.to_vec(),
+                failed: Some((migration.clone(), error.to_owned())),
+            },
+        }
+    }
+
+    fn as_notification(&self) -> Notification<'_> {
+        match &self.failed {
+            None => Notification::Migrated {
+                applied: &self.applied,
+            },
+            Some((migration, error)) => Notification::Failed {
+                applied: &self.applied,
+                migration,
+                error,
+            },
+        }
+    }
+}
+
+/// A way to deliver notifications.
+///
+/// Sending blocks the current thread until the message is delivered (or fails), so
+/// [`notify_all`] runs each one on its own thread.
+pub trait Transport {
+    fn send(&self, notification: &Notification) -> Result<(), NotifyError>;
+}
+
+/// POST a JSON summary of the run to a URL.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+}
+
+impl Transport for Webhook {
+    #[cfg(feature = "webhook")]
+    fn send(&self, notification: &Notification) -> Result<(), NotifyError> {
+        post_json("webhook", &self.url, &notification.payload())
+    }
+
+    #[cfg(not(feature = "webhook"))]
+    fn send(&self, _: &Notification) -> Result<(), NotifyError> {
+        Err(NotifyError::Disabled { feature: "webhook" })
+    }
+}
+
+/// Post a message card to a Microsoft Teams incoming webhook.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Teams {
+    pub url: String,
+}
+
+impl Teams {
+    #[cfg_attr(not(feature = "teams"), allow(dead_code))]
+    fn card(notification: &Notification) -> serde_json::Value {
+        let color = if notification.succeeded() {
+            "2EB886"
+        } else {
+            "D63333"
+        };
+
+        // Teams only renders line breaks in card text as Markdown paragraphs.
+        let text = notification.text().replace('\n', "\n\n");
+
+        json!({
+            "@type": "MessageCard",
+            "@context": "https://schema.org/extensions",
+            "themeColor": color,
+            "summary": notification.title(),
+            "title": notification.title(),
+            "text": text,
+        })
+    }
+}
+
+impl Transport for Teams {
+    #[cfg(feature = "teams")]
+    fn send(&self, notification: &Notification) -> Result<(), NotifyError> {
+        post_json("teams", &self.url, &Self::card(notification))
+    }
+
+    #[cfg(not(feature = "teams"))]
+    fn send(&self, _: &Notification) -> Result<(), NotifyError> {
+        Err(NotifyError::Disabled { feature: "teams" })
+    }
+}
+
+#[cfg(any(feature = "webhook", feature = "teams"))]
+fn post_json(
+    transport: &'static str,
+    url: &str,
+    body: &serde_json::Value,
+) -> Result<(), NotifyError> {
+    ureq::AgentBuilder::new()
+        .timeout(NOTIFY_TIMEOUT)
+        .build()
+        .post(url)
+        .set("Content-Type", "application/json")
+        .send_string(&body.to_string())
+        .map_err(|err| NotifyError::Http {
+            transport,
+            err: Box::new(err),
+        })?;
+
+    Ok(())
+}
+
+/// Send a plain-text email through an SMTP server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Email {
+    pub host: String,
+
+    #[serde(default)]
+    pub port: Option<u16>,
+
+    /// Connect without TLS. This is only meant for local test servers.
+    #[serde(default)]
+    pub insecure: bool,
+
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub password: Option<String>,
+
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+#[cfg(feature = "email")]
+impl Email {
+    fn message(&self, notification: &Notification) -> Result<Message, NotifyError> {
+        let address = |addr: &str| {
+            addr.parse().map_err(|err| NotifyError::Address {
+                address: addr.to_owned(),
+                err,
+            })
+        };
+
+        let mut builder = Message::builder()
+            .from(address(&self.from)?)
+            .subject(format!("[squill] {}", notification.title()));
+
+        for to in &self.to {
+            builder = builder.to(address(to)?);
+        }
+
+        builder
+            .body(notification.text())
+            .map_err(NotifyError::Message)
+    }
+}
+
+impl Transport for Email {
+    #[cfg(not(feature = "email"))]
+    fn send(&self, _: &Notification) -> Result<(), NotifyError> {
+        Err(NotifyError::Disabled { feature: "email" })
+    }
+
+    #[cfg(feature = "email")]
+    fn send(&self, notification: &Notification) -> Result<(), NotifyError> {
+        let message = self.message(notification)?;
+
+        let mut builder = if self.insecure {
+            SmtpTransport::builder_dangerous(&self.host)
+        } else {
+            SmtpTransport::relay(&self.host).map_err(NotifyError::Smtp)?
+        };
+
+        if let Some(port) = self.port {
+            builder = builder.port(port);
+        }
+        builder = builder.timeout(Some(NOTIFY_TIMEOUT));
+
+        if let Some(username) = &self.username {
+            let password = self.password.clone().unwrap_or_default();
+            builder = builder.credentials(Credentials::new(username.clone(), password));
+        }
+
+        builder.build().send(&message).map_err(NotifyError::Smtp)?;
+
+        Ok(())
+    }
+}
+
+/// One of the built-in transports, as written in the config file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "transport", rename_all = "snake_case")]
+pub enum Target {
+    Webhook(Webhook),
+    Email(Email),
+    Teams(Teams),
+}
+
+impl Target {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Target::Webhook(_) => "webhook",
+            Target::Email(_) => "email",
+            Target::Teams(_) => "teams",
+        }
+    }
+}
+
+impl Transport for Target {
+    fn send(&self, notification: &Notification) -> Result<(), NotifyError> {
+        match self {
+            Target::Webhook(t) => t.send(notification),
+            Target::Email(t) => t.send(notification),
+            Target::Teams(t) => t.send(notification),
+        }
+    }
+}
+
+/// A configured notification target.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotificationTarget {
+    #[serde(flatten)]
+    pub target: Target,
+
+    /// Only notify this target when one of these profiles is active. If this is empty, the target
+    /// is used for every profile.
+    #[serde(default)]
+    pub profiles: Vec<String>,
+}
+
+impl NotificationTarget {
+    pub fn is_active(&self, profile: Option<&str>) -> bool {
+        if self.profiles.is_empty() {
+            return true;
+        }
+
+        match profile {
+            Some(profile) => self.profiles.iter().any(|p| p == profile),
+            None => false,
+        }
+    }
+}
+
+/// Send the notification to every target that's active for this profile, all at once.
+///
+/// Notifications are best-effort, so this keeps going after a failure and returns every error
+/// instead of stopping at the first one. Each target gets [`NOTIFY_TIMEOUT`] to deliver it.
+pub async fn notify_all(
+    targets: &[NotificationTarget],
+    profile: Option<&str>,
+    notification: &Notification<'_>,
+) -> Vec<NotifyError> {
+    let sends = targets
+        .iter()
+        .filter(|t| t.is_active(profile))
+        .map(|target| {
+            // Don't log the whole target, since it can include credentials.
+            tracing::info!(
+                "Sending {} notification with {}",
+                notification.name(),
+                target.target.name()
+            );
+
+            send_detached(target.target.clone(), notification)
+        });
+
+    let mut errors = Vec::new();
+    for result in future::join_all(sends).await {
+        if let Err(err) = result {
+            tracing::warn!("Failed to send notification: {}", error_chain(&err));
+            errors.push(err);
+        }
+    }
+
+    errors
+}
+
+/// Send on a thread of its own, since the transports block. This works with any async runtime
+/// (unlike `spawn_blocking`), and a transport that hangs past the timeout is left behind instead
+/// of holding up the migration.
+async fn send_detached(target: Target, notification: &Notification<'_>) -> Result<(), NotifyError> {
+    let transport = target.name();
+    let owned = OwnedNotification::new(notification);
+
+    let (tx, rx) = futures::channel::oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(target.send(&owned.as_notification()));
+    });
+
+    match future::select(rx, futures_timer::Delay::new(NOTIFY_TIMEOUT)).await {
+        Either::Left((Ok(result), _)) => result,
+        Either::Left((Err(_), _)) => Err(NotifyError::Crashed { transport }),
+        Either::Right(_) => Err(NotifyError::Timeout {
+            transport,
+            timeout: NOTIFY_TIMEOUT,
+        }),
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum NotifyError {
+    #[error("squill was built without the {feature:?} feature for this notification transport")]
+    Disabled { feature: &'static str },
+
+    #[error("{transport} notification timed out after {timeout:?}")]
+    Timeout {
+        transport: &'static str,
+        timeout: Duration,
+    },
+
+    #[error("{transport} notification stopped without a result")]
+    Crashed { transport: &'static str },
+
+    #[cfg(any(feature = "webhook", feature = "teams"))]
+    #[error("failed to send {transport} notification")]
+    Http {
+        transport: &'static str,
+        #[source]
+        err: Box<ureq::Error>,
+    },
+
+    #[cfg(feature = "email")]
+    #[error("invalid email address {address:?}")]
+    Address {
+        address: String,
+        #[source]
+        err: lettre::address::AddressError,
+    },
+
+    #[cfg(feature = "email")]
+    #[error("failed to build notification email")]
+    Message(#[source] lettre::error::Error),
+
+    #[cfg(feature = "email")]
+    #[error("failed to send notification email")]
+    Smtp(#[source] lettre::transport::smtp::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::MigrationIndex;
+    use crate::testing::*;
+
+    use super::*;
+
+    #[test]
+    fn payloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut index = MigrationIndex::new(dir.path()).unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+        let two = index.create(fake_migration(2, "two")).unwrap();
+
+        let applied = [one];
+        let failed = Notification::Failed {
+            applied: &applied,
+            migration: &two,
+            error: "syntax error",
+        };
+
+        let payload = failed.payload();
+        assert_eq!("failed", payload["event"]);
+        assert_eq!(json!([{ "id": 1, "name": "one" }]), payload["applied"]);
+        assert_eq!(2, payload["failed"]["id"]);
+        assert_eq!("syntax error", payload["failed"]["error"]);
+
+        let card = Teams::card(&failed);
+        assert_eq!("MessageCard", card["@type"]);
+        assert_eq!("D63333", card["themeColor"]);
+        assert_eq!(failed.title(), card["title"]);
+
+        let migrated = Notification::Migrated { applied: &applied };
+        assert_eq!("Applied 1 migration", migrated.title());
+        assert_eq!("2EB886", Teams::card(&migrated)["themeColor"]);
+    }
+
+    #[cfg(feature = "email")]
+    #[test]
+    fn email_addresses() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut index = MigrationIndex::new(dir.path()).unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+
+        let applied = [one];
+        let migrated = Notification::Migrated { applied: &applied };
+
+        let email = Email {
+            host: String::from("localhost"),
+            port: None,
+            insecure: true,
+            username: None,
+            password: None,
+            from: String::from("squill@example.com"),
+            to: vec![String::from("not an address")],
+        };
+        match email.message(&migrated) {
+            Err(NotifyError::Address { address, .. }) => assert_eq!("not an address", address),
+
+            Err(err) => panic!("Unexpected error: {:?}", err),
+            Ok(_) => panic!("Unexpected success"),
+        }
+    }
+
+    #[test]
+    fn profiles() {
+        let targets: Vec<NotificationTarget> = serde_json::from_value(json!([
+            { "transport": "webhook", "url": "http://localhost/hook" },
+            { "transport": "teams", "url": "http://localhost/teams", "profiles": ["production"] },
+        ]))
+        .unwrap();
+
+        assert!(matches!(targets[0].target, Target::Webhook(_)));
+        assert!(targets[0].is_active(None));
+        assert!(targets[0].is_active(Some("staging")));
+
+        assert!(matches!(targets[1].target, Target::Teams(_)));
+        assert!(!targets[1].is_active(None));
+        assert!(!targets[1].is_active(Some("staging")));
+        assert!(targets[1].is_active(Some("production")));
+    }
+}
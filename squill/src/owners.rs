@@ -0,0 +1,297 @@
+use std::path::PathBuf;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use sqlx::{Acquire, Postgres};
+
+use crate::index::MigrationIndex;
+use crate::migrate::MigrationId;
+
+/// A schema object that a migration's up.sql appears to create.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TouchedObject {
+    pub kind: String,
+    pub schema: Option<String>,
+    pub name: String,
+}
+
+/// Find the objects created by the statements in this SQL.
+///
+/// This is a simple pattern match rather than a real parser, so it only recognizes plain
+/// `create` statements for tables, views, sequences, types, and functions.
+pub fn touched_objects(sql: &str) -> Vec<TouchedObject> {
+    lazy_static! {
+        static ref RE_CREATE: Regex = Regex::new(
+            r#"(?ix)
+            \bcreate \s+
+            (?: or \s+ replace \s+ )?
+            (?: (?: global | local ) \s+ )?
+            (?: (?: temp | temporary | unlogged ) \s+ )?
+            (?P<kind> table | materialized \s+ view | view | sequence | type | function )
+            \s+
+            (?: if \s+ not \s+ exists \s+ )?
+            (?P<name> (?: "[^"]+" | [\w$]+ ) (?: \. (?: "[^"]+" | [\w$]+ ) )? )
+            "#
+        )
+        .expect("static pattern");
+    }
+
+    RE_CREATE
+        .captures_iter(sql)
+        .map(|m| {
+            let kind = m.name("kind").expect("static capture group").as_str();
+            let kind = kind.split_whitespace().collect::<Vec<_>>().join(" ");
+
+            let name = m.name("name").expect("static capture group").as_str();
+            let (schema, name) = split_qualified(name);
+
+            TouchedObject {
+                kind: kind.to_lowercase(),
+                schema,
+                name,
+            }
+        })
+        .collect()
+}
+
+fn split_qualified(name: &str) -> (Option<String>, String) {
+    lazy_static! {
+        static ref RE_PART: Regex = Regex::new(r#""([^"]+)"|([\w$]+)"#).expect("static pattern");
+    }
+
+    let mut parts: Vec<String> = RE_PART
+        .captures_iter(name)
+        .map(|m| match (m.get(1), m.get(2)) {
+            // Quoted identifiers are case-sensitive.
+            (Some(quoted), _) => quoted.as_str().to_owned(),
+            // Unquoted identifiers are folded to lowercase.
+            (_, Some(bare)) => bare.as_str().to_lowercase(),
+            (None, None) => unreachable!("one alternative always matches"),
+        })
+        .collect();
+
+    let name = parts.pop().unwrap_or_default();
+    (parts.pop(), name)
+}
+
+/// An object found in the database catalog.
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DatabaseObject {
+    pub kind: String,
+    pub schema: String,
+    pub name: String,
+}
+
+impl DatabaseObject {
+    fn matches(&self, touched: &TouchedObject) -> bool {
+        let schema_matches = match &touched.schema {
+            Some(schema) => schema == &self.schema,
+            None => true,
+        };
+
+        schema_matches && touched.kind == self.kind && touched.name == self.name
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ownership {
+    pub object: DatabaseObject,
+    pub owner: Option<MigrationId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnershipReport {
+    pub entries: Vec<Ownership>,
+}
+
+impl OwnershipReport {
+    /// Objects that exist in the database, but that no migration seems to have created.
+    pub fn unowned(&self) -> impl Iterator<Item = &Ownership> {
+        self.entries.iter().filter(|e| e.owner.is_none())
+    }
+}
+
+const CATALOG_OBJECTS: &str = r#"
+select
+    case c.relkind
+        when 'v' then 'view'
+        when 'm' then 'materialized view'
+        when 'S' then 'sequence'
+        else 'table'
+    end as kind,
+    n.nspname::text as schema,
+    c.relname::text as name
+from pg_class c
+join pg_namespace n on n.oid = c.relnamespace
+where c.relkind in ('r', 'p', 'v', 'm', 'S')
+  and n.nspname not in ('pg_catalog', 'information_schema')
+  and n.nspname not like 'pg_toast%'
+  and n.nspname not like 'pg_temp%'
+  -- Skip anything created by an extension.
+  and not exists (select 1 from pg_depend d where d.objid = c.oid and d.deptype = 'e')
+  -- Skip sequences created implicitly for serial and identity columns.
+  and not exists (select 1 from pg_depend d where d.objid = c.oid and d.deptype in ('a', 'i') and c.relkind = 'S')
+
+union all
+
+select 'type', n.nspname::text, t.typname::text
+from pg_type t
+join pg_namespace n on n.oid = t.typnamespace
+where t.typtype in ('e', 'd', 'r')
+  and n.nspname not in ('pg_catalog', 'information_schema')
+  and not exists (select 1 from pg_depend d where d.objid = t.oid and d.deptype in ('e', 'i'))
+
+union all
+
+select 'function', n.nspname::text, p.proname::text
+from pg_proc p
+join pg_namespace n on n.oid = p.pronamespace
+where n.nspname not in ('pg_catalog', 'information_schema')
+  -- Skip extension functions and the constructors that come with range types.
+  and not exists (select 1 from pg_depend d where d.objid = p.oid and d.deptype in ('e', 'i'))
+
+order by schema, name, kind
+"#;
+
+const SQUILL_TABLES: &[&str] = &[
+    "schema_migration_stats",
+    "schema_migration_metadata",
+    "schema_migration_failures",
+    "schema_migration_checkpoints",
+];
+
+/// Match every object in the database to the migration that created it (if any).
+pub async fn ownership_report<'c, A>(
+    conn: A,
+    index: &MigrationIndex,
+) -> Result<OwnershipReport, OwnersError>
+where
+    A: Acquire<'c, Database = Postgres>,
+{
+    let mut touched = Vec::new();
+    for migration in index.iter() {
+        let sql = std::fs::read_to_string(&migration.up_path).map_err(|err| OwnersError::Read {
+            path: migration.up_path.clone(),
+            err,
+        })?;
+
+        for object in touched_objects(&sql) {
+            touched.push((migration.id, object));
+        }
+    }
+
+    let mut conn = conn.acquire().await.map_err(OwnersError::Query)?;
+
+    let mut objects: Vec<DatabaseObject> = sqlx::query_as(CATALOG_OBJECTS)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(OwnersError::Query)?;
+
+    // Squill creates some bookkeeping tables on its own, outside of any migration.
+    objects.retain(|o| !(o.kind == "table" && SQUILL_TABLES.contains(&o.name.as_str())));
+
+    let entries = objects
+        .into_iter()
+        .map(|object| {
+            // If more than one migration creates the same name (e.g. after a drop), the latest
+            // one is the most likely owner of what's there now.
+            let owner = touched
+                .iter()
+                .filter(|(_, t)| object.matches(t))
+                .map(|(id, _)| *id)
+                .max();
+
+            Ownership { object, owner }
+        })
+        .collect();
+
+    Ok(OwnershipReport { entries })
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum OwnersError {
+    #[error("failed to read migration file: {path}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+
+    #[error("failed to query database objects")]
+    Query(#[source] sqlx::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::Executor;
+
+    use crate::testing::*;
+
+    use super::*;
+
+    #[test]
+    fn parse_touched_objects() {
+        let sql = r#"
+            create table users (id int);
+            CREATE TABLE IF NOT EXISTS app."Profiles" (id int);
+            create or replace view active_users as select * from users;
+            create materialized view  user_counts as select count(*) from users;
+            create type mood as enum ('happy', 'sad');
+            create function do_thing() returns void as $$ $$ language sql;
+            create index users_id on users (id);
+        "#;
+
+        let actual = touched_objects(sql);
+
+        let touched = |kind: &str, schema: Option<&str>, name: &str| TouchedObject {
+            kind: kind.to_owned(),
+            schema: schema.map(|s| s.to_owned()),
+            name: name.to_owned(),
+        };
+
+        let expected = vec![
+            touched("table", None, "users"),
+            touched("table", Some("app"), "Profiles"),
+            touched("view", None, "active_users"),
+            touched("materialized view", None, "user_counts"),
+            touched("type", None, "mood"),
+            touched("function", None, "do_thing"),
+        ];
+
+        assert_eq!(expected, actual);
+    }
+
+    #[tokio::test]
+    async fn unowned_objects() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        one.up(&mut conn).await.unwrap();
+
+        conn.execute("create table sneaky (id int)").await.unwrap();
+
+        let report = ownership_report(&mut conn, &index).await.unwrap();
+
+        let owner = |name: &str| {
+            report
+                .entries
+                .iter()
+                .find(|e| e.object.name == name)
+                .unwrap()
+                .owner
+        };
+
+        assert_eq!(Some(MigrationId(0)), owner("schema_migrations"));
+        assert_eq!(Some(MigrationId(0)), owner("_squill_claim_migration"));
+        assert_eq!(Some(MigrationId(1)), owner("tbl_one"));
+        assert_eq!(None, owner("sneaky"));
+
+        let unowned: Vec<_> = report.unowned().map(|e| e.object.name.as_str()).collect();
+        assert_eq!(vec!["sneaky"], unowned);
+    }
+}
@@ -0,0 +1,177 @@
+//! Gate traffic on migrations for apps that migrate at startup.
+//!
+//! Create a [`Readiness`], serve its probe while [`Readiness::run_pending_migrations`] runs in the
+//! background, and point the deployment's readiness check at it. The probe returns 503 until the
+//! migrations are done, so new instances don't get requests before the schema is ready.
+//!
+//! With the `axum` or `actix-web` feature, [`Readiness::axum_router`] and
+//! [`Readiness::actix_resource`] provide the probe as a route.
+
+use std::sync::{Arc, RwLock};
+
+use sqlx::{Acquire, Postgres};
+
+use crate::config::Config;
+use crate::report::MigrateReport;
+use crate::startup::run_pending_migrations_with;
+use crate::{error_chain, MigrateAllError};
+
+/// How far an app has gotten with its startup migrations.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum MigrationPhase {
+    /// Migrations haven't started yet.
+    #[default]
+    Pending,
+
+    /// Migrations are running (or waiting for another instance to finish them).
+    Running,
+
+    /// Every pending migration was applied.
+    Done,
+
+    /// A migration failed, with the error message.
+    Failed(String),
+}
+
+/// A shared view of the startup migrations, for readiness probes.
+///
+/// Clones share the same state, so keep one for the probe and use another to run the migrations.
+#[derive(Debug, Clone, Default)]
+pub struct Readiness {
+    phase: Arc<RwLock<MigrationPhase>>,
+}
+
+impl Readiness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn phase(&self) -> MigrationPhase {
+        self.phase.read().expect("readiness lock poisoned").clone()
+    }
+
+    /// Whether the migrations are done, so the app can serve traffic.
+    pub fn is_ready(&self) -> bool {
+        self.phase() == MigrationPhase::Done
+    }
+
+    fn set(&self, phase: MigrationPhase) {
+        *self.phase.write().expect("readiness lock poisoned") = phase;
+    }
+
+    /// Like [`run_pending_migrations_with`], but keeping track of the phase.
+    pub async fn run_pending_migrations<'c, A>(
+        &self,
+        conn: A,
+        config: &Config,
+    ) -> Result<MigrateReport, MigrateAllError>
+    where
+        A: Acquire<'c, Database = Postgres>,
+    {
+        self.set(MigrationPhase::Running);
+
+        let result = run_pending_migrations_with(conn, config).await;
+
+        self.set(match &result {
+            Ok(_) => MigrationPhase::Done,
+            Err(err) => MigrationPhase::Failed(error_chain(err)),
+        });
+
+        result
+    }
+
+    /// The HTTP status code and body for a readiness probe.
+    pub fn probe(&self) -> (u16, String) {
+        match self.phase() {
+            MigrationPhase::Done => (200, String::from("ready")),
+            MigrationPhase::Pending => (503, String::from("migrations pending")),
+            MigrationPhase::Running => (503, String::from("migrations running")),
+            MigrationPhase::Failed(err) => (503, format!("migrations failed: {err}")),
+        }
+    }
+}
+
+#[cfg(feature = "axum")]
+impl Readiness {
+    /// A router that serves the readiness probe at this path. Merge it into the app's router.
+    pub fn axum_router<S>(&self, path: &str) -> axum::Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        use axum::http::StatusCode;
+
+        let readiness = self.clone();
+        let probe = move || {
+            let (code, body) = readiness.probe();
+            async move {
+                let code = StatusCode::from_u16(code).expect("valid status code");
+                (code, body)
+            }
+        };
+
+        axum::Router::new().route(path, axum::routing::get(probe))
+    }
+}
+
+#[cfg(feature = "actix-web")]
+impl Readiness {
+    /// A resource that serves the readiness probe at this path. Add it to the app as a service.
+    pub fn actix_resource(&self, path: &str) -> actix_web::Resource {
+        use actix_web::http::StatusCode;
+        use actix_web::{web, HttpResponse};
+
+        let readiness = self.clone();
+        let probe = move || {
+            let (code, body) = readiness.probe();
+            async move {
+                let code = StatusCode::from_u16(code).expect("valid status code");
+                HttpResponse::build(code).body(body)
+            }
+        };
+
+        web::resource(path).route(web::get().to(probe))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::MigrationIndex;
+    use crate::testing::*;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn phases() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let readiness = Readiness::new();
+        assert_eq!((503, String::from("migrations pending")), readiness.probe());
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        index.create(fake_migration(1, "one")).unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        readiness
+            .clone()
+            .run_pending_migrations(&mut conn, &config)
+            .await
+            .unwrap();
+        assert!(readiness.is_ready());
+        assert_eq!(200, readiness.probe().0);
+
+        let mut params = fake_migration(2, "broken");
+        params.up_sql = String::from("select * from missing;");
+        index.create(params).unwrap();
+
+        readiness
+            .run_pending_migrations(&mut conn, &config)
+            .await
+            .unwrap_err();
+        match readiness.phase() {
+            MigrationPhase::Failed(err) => assert!(err.contains("2-broken"), "{err}"),
+            phase => panic!("Unexpected phase: {phase:?}"),
+        }
+        assert_eq!(503, readiness.probe().0);
+    }
+}
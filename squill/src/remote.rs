@@ -0,0 +1,201 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// A git repository of shared templates, optionally pinned to a revision.
+///
+/// This is written as `<url>#<rev>`, where the rev can be any branch, tag, or commit the remote
+/// will serve. Without a rev, the remote's default branch is used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateRepo {
+    pub url: String,
+    pub rev: Option<String>,
+}
+
+impl FromStr for TemplateRepo {
+    type Err = ParseTemplateRepoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (url, rev) = match s.rsplit_once('#') {
+            Some((url, rev)) if !rev.is_empty() => (url, Some(rev.to_owned())),
+            Some((url, _)) => (url, None),
+            None => (s, None),
+        };
+
+        if url.is_empty() {
+            return Err(ParseTemplateRepoError(s.to_owned()));
+        }
+
+        Ok(Self {
+            url: url.to_owned(),
+            rev,
+        })
+    }
+}
+
+impl fmt::Display for TemplateRepo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.rev {
+            Some(rev) => write!(f, "{}#{}", self.url, rev),
+            None => write!(f, "{}", self.url),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("invalid templates repo (expected <url>#<rev>): {0:?}")]
+pub struct ParseTemplateRepoError(String);
+
+impl TemplateRepo {
+    fn cache_key(&self) -> String {
+        self.to_string()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect()
+    }
+
+    /// Get a local checkout of this repo, cloning it into the cache dir if needed.
+    ///
+    /// Checkouts are never updated once they're cached, so pin a tag or commit to get
+    /// reproducible templates. To pick up new changes from a branch, delete the cached copy.
+    pub fn checkout(&self, cache_dir: &Path) -> Result<PathBuf, RemoteError> {
+        let templates_cache = cache_dir.join("templates");
+        let dir = templates_cache.join(self.cache_key());
+
+        if dir.is_dir() {
+            return Ok(dir);
+        }
+
+        tracing::info!("Fetching templates from {}", self);
+
+        // Work in a scratch directory so a failed fetch doesn't leave a broken checkout behind.
+        let partial = templates_cache.join(format!("{}.partial", self.cache_key()));
+        if partial.exists() {
+            std::fs::remove_dir_all(&partial).map_err(|err| RemoteError::Cache {
+                path: partial.clone(),
+                err,
+            })?;
+        }
+        std::fs::create_dir_all(&partial).map_err(|err| RemoteError::Cache {
+            path: partial.clone(),
+            err,
+        })?;
+
+        let rev = self.rev.as_deref().unwrap_or("HEAD");
+        git(&partial, &["init", "--quiet"])?;
+        git(
+            &partial,
+            &["fetch", "--quiet", "--depth", "1", &self.url, rev],
+        )?;
+        git(&partial, &["checkout", "--quiet", "FETCH_HEAD"])?;
+
+        std::fs::rename(&partial, &dir).map_err(|err| RemoteError::Cache {
+            path: dir.clone(),
+            err,
+        })?;
+
+        Ok(dir)
+    }
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<(), RemoteError> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(RemoteError::Spawn)?;
+
+    if !output.status.success() {
+        return Err(RemoteError::Git {
+            args: args.join(" "),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        });
+    }
+
+    Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum RemoteError {
+    #[error("failed to run git")]
+    Spawn(#[source] std::io::Error),
+
+    #[error("git {args} failed: {stderr}")]
+    Git { args: String, stderr: String },
+
+    #[error("failed to update template cache: {path}")]
+    Cache {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let repo: TemplateRepo = "https://example.com/sql-templates.git#v3".parse().unwrap();
+        assert_eq!(
+            TemplateRepo {
+                url: String::from("https://example.com/sql-templates.git"),
+                rev: Some(String::from("v3")),
+            },
+            repo
+        );
+        assert_eq!("https://example.com/sql-templates.git#v3", repo.to_string());
+
+        let repo: TemplateRepo = "https://example.com/sql-templates.git".parse().unwrap();
+        assert_eq!(None, repo.rev);
+
+        assert!("#v3".parse::<TemplateRepo>().is_err());
+    }
+
+    #[test]
+    fn checkout() {
+        let origin = tempfile::tempdir().unwrap();
+        let run = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .arg("-C")
+                .arg(origin.path())
+                .args([
+                    "-c",
+                    "user.name=squill",
+                    "-c",
+                    "user.email=squill@example.com",
+                ])
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {:?}", args);
+        };
+
+        std::fs::create_dir(origin.path().join("table")).unwrap();
+        std::fs::write(origin.path().join("table/new.up.sql"), "-- v1").unwrap();
+        run(&["init", "--quiet"]);
+        run(&["add", "."]);
+        run(&["commit", "--quiet", "-m", "v1"]);
+        run(&["tag", "v1"]);
+
+        std::fs::write(origin.path().join("table/new.up.sql"), "-- v2").unwrap();
+        run(&["commit", "--quiet", "-am", "v2"]);
+
+        let repo = TemplateRepo {
+            url: format!("file://{}", origin.path().to_string_lossy()),
+            rev: Some(String::from("v1")),
+        };
+
+        let cache = tempfile::tempdir().unwrap();
+        let dir = repo.checkout(cache.path()).unwrap();
+
+        let content = std::fs::read_to_string(dir.join("table/new.up.sql")).unwrap();
+        assert_eq!("-- v1", content);
+
+        // The second checkout comes straight from the cache.
+        assert_eq!(dir, repo.checkout(cache.path()).unwrap());
+    }
+}
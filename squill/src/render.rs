@@ -0,0 +1,209 @@
+use serde::{Deserialize, Serialize};
+
+use crate::migrate::MigrationDirectory;
+use crate::report::{MigrateReport, ReportedMigration};
+
+/// How to format plans and reports as text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderFormat {
+    /// A box-drawn table, like the CLI prints.
+    #[default]
+    Table,
+
+    /// A GitHub-flavored Markdown table, for chat and pull request comments.
+    Markdown,
+
+    Json,
+}
+
+/// Render the migrations that `squill migrate` would run, the same way `squill plan` prints them.
+pub fn render_plan(pending: &[MigrationDirectory], format: RenderFormat) -> String {
+    let planned: Vec<ReportedMigration> = pending.iter().map(Into::into).collect();
+
+    if format == RenderFormat::Json {
+        return to_json(&planned);
+    }
+
+    if planned.is_empty() {
+        return String::from("Database is up-to-date.");
+    }
+
+    let rows: Vec<_> = planned
+        .into_iter()
+        .map(|m| vec![m.id.to_string(), m.name, m.directory])
+        .collect();
+
+    render_table(format, &["id", "name", "directory"], &rows)
+}
+
+/// Render a summary of a migrate run.
+pub fn render_report(report: &MigrateReport, format: RenderFormat) -> String {
+    if format == RenderFormat::Json {
+        return to_json(report);
+    }
+
+    let mut out = String::new();
+
+    if report.applied.is_empty() {
+        out.push_str("No migrations were applied.\n");
+    } else {
+        let rows: Vec<_> = report
+            .applied
+            .iter()
+            .map(|m| {
+                vec![
+                    m.migration.id.to_string(),
+                    m.migration.name.clone(),
+                    format!("{} ms", m.duration_ms),
+                    m.statements.to_string(),
+                ]
+            })
+            .collect();
+
+        out.push_str(&render_table(
+            format,
+            &["id", "name", "duration", "statements"],
+            &rows,
+        ));
+    }
+
+    if let Some(failed) = &report.failed {
+        out.push('\n');
+        out.push_str(&format!(
+            "Failed: {} ({})\n",
+            failed.migration.directory, failed.error
+        ));
+    }
+
+    if !report.remaining.is_empty() {
+        out.push('\n');
+        out.push_str("Not started:\n");
+        for m in &report.remaining {
+            out.push_str(&format!("- {}\n", m.directory));
+        }
+    }
+
+    out.trim_end().to_owned()
+}
+
+fn to_json(value: &impl Serialize) -> String {
+    serde_json::to_string_pretty(value).expect("plain data always serializes")
+}
+
+fn render_table(format: RenderFormat, headers: &[&str], rows: &[Vec<String>]) -> String {
+    match format {
+        RenderFormat::Markdown => markdown_table(headers, rows),
+        _ => box_table(headers, rows),
+    }
+}
+
+fn column_widths(headers: &[&str], rows: &[Vec<String>]) -> Vec<usize> {
+    headers
+        .iter()
+        .enumerate()
+        .map(|(i, h)| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain([h.chars().count()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+fn box_table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let widths = column_widths(headers, rows);
+
+    let rule = |left: &str, mid: &str, right: &str| {
+        let segments: Vec<_> = widths.iter().map(|w| "─".repeat(w + 2)).collect();
+        format!("{left}{}{right}", segments.join(mid))
+    };
+
+    let line = |cells: Vec<&str>| {
+        let cells: Vec<_> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!(" {cell:<width$} "))
+            .collect();
+        format!("│{}│", cells.join("│"))
+    };
+
+    let mut lines = vec![rule("┌", "┬", "┐"), line(headers.to_vec())];
+    lines.push(rule("├", "┼", "┤"));
+    for row in rows {
+        lines.push(line(row.iter().map(String::as_str).collect()));
+    }
+    lines.push(rule("└", "┴", "┘"));
+
+    lines.join("\n")
+}
+
+fn markdown_table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let line = |cells: Vec<String>| format!("| {} |", cells.join(" | "));
+
+    let mut lines = vec![
+        line(headers.iter().map(|h| h.to_string()).collect()),
+        line(headers.iter().map(|_| String::from("---")).collect()),
+    ];
+    for row in rows {
+        // A pipe would end the cell early.
+        lines.push(line(row.iter().map(|c| c.replace('|', "\\|")).collect()));
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::index::MigrationIndex;
+    use crate::report::AppliedMigration;
+    use crate::testing::*;
+
+    use super::*;
+
+    #[test]
+    fn plans() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut index = MigrationIndex::new(dir.path()).unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+        let two = index.create(fake_migration(22, "two")).unwrap();
+        let pending = vec![one.clone(), two.clone()];
+
+        let table = render_plan(&pending, RenderFormat::Table);
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!(6, lines.len(), "{table}");
+        assert!(lines[1].starts_with("│ id │ name │ directory"), "{table}");
+        assert!(lines[3].starts_with("│ 1  │ one  │ "), "{table}");
+        // Every line is the same width.
+        let width = lines[0].chars().count();
+        assert!(lines.iter().all(|l| l.chars().count() == width), "{table}");
+
+        let markdown = render_plan(&pending, RenderFormat::Markdown);
+        assert_eq!(
+            format!("| id | name | directory |\n| --- | --- | --- |\n| 1 | one | {one} |\n| 22 | two | {two} |"),
+            markdown
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&render_plan(&pending, RenderFormat::Json)).unwrap();
+        assert_eq!(22, json[1]["id"]);
+
+        assert_eq!(
+            "Database is up-to-date.",
+            render_plan(&[], RenderFormat::Markdown)
+        );
+        assert_eq!("[]", render_plan(&[], RenderFormat::Json));
+
+        let report = MigrateReport {
+            applied: vec![AppliedMigration::new(&one, Duration::from_millis(5))],
+            failed: None,
+            remaining: vec![(&two).into()],
+        };
+        let text = render_report(&report, RenderFormat::Markdown);
+        assert!(text.contains("| 1 | one | 5 ms | 1 |"), "{text}");
+        assert!(text.ends_with(&format!("Not started:\n- {two}")), "{text}");
+    }
+}
@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error_chain;
+use crate::migrate::{MigrateError, MigrationDirectory, MigrationId};
+use crate::sql::split_statements;
+
+/// A machine-readable summary of a migrate run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrateReport {
+    /// The migrations that were applied, in the order they ran.
+    pub applied: Vec<AppliedMigration>,
+
+    /// The migration that stopped the run, if one failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failed: Option<FailedMigration>,
+
+    /// Pending migrations that weren't started.
+    #[serde(default)]
+    pub remaining: Vec<ReportedMigration>,
+}
+
+impl MigrateReport {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_none()
+    }
+
+    /// The IDs of the applied migrations, in the order they ran.
+    pub fn applied_ids(&self) -> Vec<MigrationId> {
+        self.applied.iter().map(|m| m.migration.id).collect()
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReportedMigration {
+    pub id: MigrationId,
+    pub name: String,
+    pub directory: String,
+}
+
+impl From<&MigrationDirectory> for ReportedMigration {
+    fn from(m: &MigrationDirectory) -> Self {
+        Self {
+            id: m.id,
+            name: m.name.clone(),
+            directory: m.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppliedMigration {
+    #[serde(flatten)]
+    pub migration: ReportedMigration,
+
+    pub duration_ms: u64,
+
+    /// How many statements the up file contains. They all ran, since the migration succeeded.
+    pub statements: usize,
+}
+
+impl AppliedMigration {
+    pub fn new(migration: &MigrationDirectory, duration: Duration) -> Self {
+        Self {
+            migration: migration.into(),
+            duration_ms: duration.as_millis() as u64,
+            statements: count_statements(migration),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailedMigration {
+    #[serde(flatten)]
+    pub migration: ReportedMigration,
+
+    pub duration_ms: u64,
+    pub error: String,
+
+    /// The SQLSTATE code, if the database rejected a statement.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
+
+impl FailedMigration {
+    pub fn new(migration: &MigrationDirectory, duration: Duration, err: &MigrateError) -> Self {
+        let code = match err {
+            MigrateError::Execute {
+                err: sqlx::Error::Database(db_err),
+                ..
+            }
+            | MigrateError::Verify {
+                err: sqlx::Error::Database(db_err),
+                ..
+            } => db_err.code().map(|c| c.into_owned()),
+            _ => None,
+        };
+
+        Self {
+            migration: migration.into(),
+            duration_ms: duration.as_millis() as u64,
+            error: error_chain(err),
+            code,
+        }
+    }
+}
+
+/// The file was just read to run it, so a read error here is unlikely and not worth reporting.
+fn count_statements(migration: &MigrationDirectory) -> usize {
+    std::fs::read_to_string(&migration.up_path)
+        .map(|sql| split_statements(&sql).len())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::index::MigrationIndex;
+    use crate::testing::*;
+
+    use super::*;
+
+    #[test]
+    fn report_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut index = MigrationIndex::new(dir.path()).unwrap();
+
+        let mut params = fake_migration(1, "one");
+        params.up_sql = String::from("create table one (id int);\ncreate index on one (id);");
+        let one = index.create(params).unwrap();
+        let two = index.create(fake_migration(2, "two")).unwrap();
+
+        let report = MigrateReport {
+            applied: vec![AppliedMigration::new(&one, Duration::from_millis(25))],
+            failed: None,
+            remaining: vec![(&two).into()],
+        };
+
+        let value: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(
+            json!({
+                "applied": [{
+                    "id": 1,
+                    "name": "one",
+                    "directory": one.to_string(),
+                    "duration_ms": 25,
+                    "statements": 2,
+                }],
+                "remaining": [{ "id": 2, "name": "two", "directory": two.to_string() }],
+            }),
+            value
+        );
+        assert!(report.is_success());
+        assert_eq!(vec![MigrationId(1)], report.applied_ids());
+    }
+}
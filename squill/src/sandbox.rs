@@ -0,0 +1,106 @@
+use sqlx::postgres::PgConnectOptions;
+use sqlx::Executor;
+
+use crate::config::{Config, ConnectError};
+
+/// A disposable database for trying out migrations without touching the configured one.
+///
+/// The database isn't dropped automatically, since that needs a connection. Call
+/// [`drop_database`](Sandbox::drop_database) when finished with it.
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    name: String,
+    admin: Config,
+    connect_options: PgConnectOptions,
+}
+
+impl Sandbox {
+    /// Create an empty database on the configured server, using the configured connection.
+    ///
+    /// The database name starts with the prefix and is unique enough that sandboxes created at the
+    /// same time won't collide.
+    pub async fn create(config: &Config, prefix: &str) -> Result<Self, SandboxError> {
+        let Some(server) = &config.database_connect_options else {
+            return Err(SandboxError::Connect(ConnectError::NotConfigured));
+        };
+
+        let nanos = time::OffsetDateTime::now_utc().unix_timestamp_nanos();
+        let name = format!("{prefix}_{}_{}", std::process::id(), nanos);
+
+        let mut conn = config.connect().await.map_err(SandboxError::Connect)?;
+
+        // The name is generated here, so interpolating it is okay. Postgres doesn't support using a
+        // prepared statement to create a database.
+        conn.execute(&*format!("create database {name}"))
+            .await
+            .map_err(|err| SandboxError::Create {
+                name: name.clone(),
+                err,
+            })?;
+
+        Ok(Self {
+            connect_options: server.clone().database(&name),
+            name,
+            admin: config.clone(),
+        })
+    }
+
+    /// The name of the sandbox database.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn connect_options(&self) -> &PgConnectOptions {
+        &self.connect_options
+    }
+
+    /// A copy of the original config that uses the sandbox database.
+    ///
+    /// Notifications, the state file, other configured databases, and the expected database
+    /// identity are never used for a sandbox.
+    pub fn config(&self) -> Config {
+        let mut config = self.admin.clone();
+        config.database_connect_options = Some(self.connect_options.clone());
+        config.state_file = None;
+        config.notifications = Vec::new();
+        config.databases.clear();
+        config.expected_database_name = None;
+        config.expected_server_fingerprint = None;
+        config
+    }
+
+    /// Drop the sandbox database. Close any connections to it first.
+    pub async fn drop_database(self) -> Result<(), SandboxError> {
+        let mut conn = self.admin.connect().await.map_err(SandboxError::Connect)?;
+
+        conn.execute(&*format!("drop database if exists {}", self.name))
+            .await
+            .map_err(|err| SandboxError::Drop {
+                name: self.name.clone(),
+                err,
+            })?;
+
+        Ok(())
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum SandboxError {
+    #[error(transparent)]
+    Connect(ConnectError),
+
+    #[error("failed to create database {name}")]
+    Create {
+        name: String,
+        #[source]
+        err: sqlx::Error,
+    },
+
+    #[error("failed to drop database {name}")]
+    Drop {
+        name: String,
+        #[source]
+        err: sqlx::Error,
+    },
+}
@@ -0,0 +1,199 @@
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::index::MigrationIndex;
+use crate::{create_init_migration, NewMigrationError};
+
+const SQUILL_TOML: &str = r#"# The connection string for the database to run migrations on.
+#
+# You might prefer to set this using the SQUILL_DATABASE_URL environment variable.
+# database_url = "postgres://localhost:5432/postgres"
+
+migrations_dir = "migrations"
+templates_dir = ".squill/templates"
+"#;
+
+const GITIGNORE_ENTRY: &str = ".squill/cache/";
+
+// The starter templates are plain files in the new project, so these are copied as-is instead of
+// being rendered.
+const STARTER_TEMPLATES: &[(&str, &str)] = &[
+    ("new.up.sql", include_str!("templates/new.up.sql")),
+    ("new.down.sql", include_str!("templates/new.down.sql")),
+    (
+        "create_table/new.up.sql",
+        include_str!("templates/starter/create_table/new.up.sql"),
+    ),
+    (
+        "create_table/new.down.sql",
+        include_str!("templates/starter/create_table/new.down.sql"),
+    ),
+];
+
+/// The files that were written (or left alone) while setting up a project.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scaffold {
+    /// New files, and existing files that were added to (like .gitignore).
+    pub written: Vec<PathBuf>,
+
+    /// Files that already existed, so they were not changed.
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Set up a complete Squill project layout in this directory.
+///
+/// This writes a squill.toml, the init migration, starter templates, and a .gitignore entry for
+/// the cache dir. Existing files are never overwritten, so this is safe to run again.
+pub fn init_project(root: &Path) -> Result<Scaffold, ScaffoldError> {
+    let mut scaffold = Scaffold::default();
+
+    scaffold.write_new(&root.join("squill.toml"), SQUILL_TOML)?;
+
+    let templates_dir = root.join(".squill/templates");
+    for (name, content) in STARTER_TEMPLATES {
+        scaffold.write_new(&templates_dir.join(name), content)?;
+    }
+
+    scaffold.append_line(&root.join(".gitignore"), GITIGNORE_ENTRY)?;
+
+    let config = Config {
+        migrations_dir: root.join("migrations"),
+        templates_dir: Some(templates_dir),
+        ..Default::default()
+    };
+
+    let index = MigrationIndex::new(&config.migrations_dir).map_err(NewMigrationError::Index)?;
+    if let Some(existing) = index.iter().next() {
+        scaffold.skipped.push(existing.dir.clone());
+    } else {
+        let init = create_init_migration(&config)?;
+        scaffold.written.push(init.up_path);
+        scaffold.written.push(init.down_path);
+    }
+
+    Ok(scaffold)
+}
+
+impl Scaffold {
+    fn write_new(&mut self, path: &Path, content: &str) -> Result<(), ScaffoldError> {
+        if path.exists() {
+            self.skipped.push(path.to_path_buf());
+            return Ok(());
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|err| ScaffoldError::Write {
+                path: parent.to_path_buf(),
+                err,
+            })?;
+        }
+
+        std::fs::write(path, content).map_err(|err| ScaffoldError::Write {
+            path: path.to_path_buf(),
+            err,
+        })?;
+
+        self.written.push(path.to_path_buf());
+        Ok(())
+    }
+
+    fn append_line(&mut self, path: &Path, line: &str) -> Result<(), ScaffoldError> {
+        let existing = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => {
+                return Err(ScaffoldError::Write {
+                    path: path.to_path_buf(),
+                    err,
+                });
+            }
+        };
+
+        if existing.lines().any(|l| l.trim() == line) {
+            self.skipped.push(path.to_path_buf());
+            return Ok(());
+        }
+
+        let mut content = existing;
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(line);
+        content.push('\n');
+
+        std::fs::write(path, content).map_err(|err| ScaffoldError::Write {
+            path: path.to_path_buf(),
+            err,
+        })?;
+
+        self.written.push(path.to_path_buf());
+        Ok(())
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum ScaffoldError {
+    #[error("failed to write project file: {path}")]
+    Write {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+
+    #[error(transparent)]
+    Migration(#[from] NewMigrationError),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::migrate::MigrationId;
+    use crate::template::{TemplateContext, TemplateGroup, TemplateId, Templates};
+
+    use super::*;
+
+    #[test]
+    fn new_project() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+
+        std::fs::write(root.join(".gitignore"), "target/").unwrap();
+
+        let scaffold = init_project(root).unwrap();
+        assert!(scaffold.skipped.is_empty(), "{:?}", scaffold.skipped);
+
+        let index = MigrationIndex::new(&root.join("migrations")).unwrap();
+        let ids: Vec<_> = index.iter().map(|m| m.id.as_i64()).collect();
+        assert_eq!(vec![0], ids);
+
+        let templates = Templates::new(root.join(".squill/templates")).unwrap();
+        let group = TemplateGroup::Named(String::from("create_table"));
+        let ctx = TemplateContext {
+            id: MigrationId(1),
+            name: String::from("users"),
+            vars: Default::default(),
+            schema: None,
+            created_at: None,
+            author: None,
+            profile: None,
+        };
+        let up = templates.render(&group, TemplateId::NewUp, &ctx).unwrap();
+        assert!(up.contains("create table"), "{up:?}");
+
+        let gitignore = std::fs::read_to_string(root.join(".gitignore")).unwrap();
+        assert_eq!("target/\n.squill/cache/\n", gitignore);
+    }
+
+    #[test]
+    fn existing_project() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+
+        let first = init_project(root).unwrap();
+        let second = init_project(root).unwrap();
+
+        assert!(second.written.is_empty(), "{:?}", second.written);
+        assert_eq!(STARTER_TEMPLATES.len() + 3, second.skipped.len());
+        assert_eq!(first.written.len(), STARTER_TEMPLATES.len() + 4);
+    }
+}
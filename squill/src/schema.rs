@@ -0,0 +1,215 @@
+use sqlx::{Acquire, Postgres};
+
+// Every query returns (schema, name, definition) for one kind of object, skipping system schemas
+// and anything that belongs to an extension.
+//
+// The output is meant for reviewing schema changes, not for restoring a database, so it doesn't
+// try to order objects by their dependencies.
+
+const TYPES: &str = r#"
+select n.nspname::text, t.typname::text,
+    format('create type %I.%I as enum (%s);', n.nspname, t.typname, (
+        select string_agg(quote_literal(e.enumlabel), ', ' order by e.enumsortorder)
+        from pg_enum e
+        where e.enumtypid = t.oid
+    ))
+from pg_type t
+join pg_namespace n on n.oid = t.typnamespace
+where t.typtype = 'e'
+  and n.nspname not in ('pg_catalog', 'information_schema')
+  and not exists (select 1 from pg_depend d where d.objid = t.oid and d.deptype = 'e')
+order by 1, 2
+"#;
+
+const TABLES: &str = r#"
+select n.nspname::text, c.relname::text,
+    format(E'create table %I.%I (%s\n);', n.nspname, c.relname, coalesce((
+        select string_agg(
+            format(E'\n    %I %s%s%s%s',
+                a.attname,
+                format_type(a.atttypid, a.atttypmod),
+                case a.attidentity
+                    when 'a' then ' generated always as identity'
+                    when 'd' then ' generated by default as identity'
+                    else ''
+                end,
+                case
+                    when a.attgenerated = 's' then ' generated always as (' || pg_get_expr(ad.adbin, ad.adrelid) || ') stored'
+                    when ad.adbin is not null then ' default ' || pg_get_expr(ad.adbin, ad.adrelid)
+                    else ''
+                end,
+                case when a.attnotnull then ' not null' else '' end
+            ),
+            ',' order by a.attnum
+        )
+        from pg_attribute a
+        left join pg_attrdef ad on ad.adrelid = a.attrelid and ad.adnum = a.attnum
+        where a.attrelid = c.oid and a.attnum > 0 and not a.attisdropped
+    ), ''))
+from pg_class c
+join pg_namespace n on n.oid = c.relnamespace
+where c.relkind in ('r', 'p')
+  and n.nspname not in ('pg_catalog', 'information_schema')
+  and n.nspname not like 'pg_toast%'
+  and n.nspname not like 'pg_temp%'
+  and not exists (select 1 from pg_depend d where d.objid = c.oid and d.deptype = 'e')
+order by 1, 2
+"#;
+
+const SEQUENCES: &str = r#"
+select n.nspname::text, c.relname::text,
+    format('create sequence %I.%I;', n.nspname, c.relname)
+from pg_class c
+join pg_namespace n on n.oid = c.relnamespace
+where c.relkind = 'S'
+  and n.nspname not in ('pg_catalog', 'information_schema')
+  -- Skip extension sequences and the ones that belong to serial and identity columns.
+  and not exists (select 1 from pg_depend d where d.objid = c.oid and d.deptype in ('e', 'a', 'i'))
+order by 1, 2
+"#;
+
+const CONSTRAINTS: &str = r#"
+select n.nspname::text, c.relname::text || '.' || con.conname::text,
+    format('alter table %I.%I add constraint %I %s;', n.nspname, c.relname, con.conname, pg_get_constraintdef(con.oid))
+from pg_constraint con
+join pg_class c on c.oid = con.conrelid
+join pg_namespace n on n.oid = c.relnamespace
+where c.relkind in ('r', 'p')
+  -- Not-null constraints are already part of the column definitions.
+  and con.contype <> 'n'
+  and n.nspname not in ('pg_catalog', 'information_schema')
+  and n.nspname not like 'pg_toast%'
+  and not exists (select 1 from pg_depend d where d.objid = c.oid and d.deptype = 'e')
+order by 1, 2
+"#;
+
+const INDEXES: &str = r#"
+select n.nspname::text, i.relname::text, pg_get_indexdef(i.oid) || ';'
+from pg_index x
+join pg_class i on i.oid = x.indexrelid
+join pg_class c on c.oid = x.indrelid
+join pg_namespace n on n.oid = i.relnamespace
+where n.nspname not in ('pg_catalog', 'information_schema')
+  and n.nspname not like 'pg_toast%'
+  -- Indexes that back constraints are already covered by the constraint definitions.
+  and not exists (
+      select 1 from pg_constraint con
+      where con.conindid = i.oid and con.conrelid = c.oid and con.contype in ('p', 'u', 'x')
+  )
+  and not exists (select 1 from pg_depend d where d.objid = c.oid and d.deptype = 'e')
+order by 1, 2
+"#;
+
+const VIEWS: &str = r#"
+select n.nspname::text, c.relname::text,
+    format(E'create %s %I.%I as\n%s',
+        case c.relkind when 'm' then 'materialized view' else 'view' end,
+        n.nspname, c.relname, pg_get_viewdef(c.oid))
+from pg_class c
+join pg_namespace n on n.oid = c.relnamespace
+where c.relkind in ('v', 'm')
+  and n.nspname not in ('pg_catalog', 'information_schema')
+  and not exists (select 1 from pg_depend d where d.objid = c.oid and d.deptype = 'e')
+order by 1, 2
+"#;
+
+const FUNCTIONS: &str = r#"
+select n.nspname::text, p.proname::text || '(' || pg_get_function_identity_arguments(p.oid) || ')',
+    rtrim(pg_get_functiondef(p.oid), E'\n') || ';'
+from pg_proc p
+join pg_namespace n on n.oid = p.pronamespace
+where p.prokind in ('f', 'p')
+  and n.nspname not in ('pg_catalog', 'information_schema')
+  and not exists (select 1 from pg_depend d where d.objid = p.oid and d.deptype in ('e', 'i'))
+order by 1, 2
+"#;
+
+const HEADER: &str = "-- This file is generated by `squill schema dump`. Do not edit it by hand.";
+
+/// Write out the database schema as canonical SQL, suitable for committing and diffing.
+///
+/// Objects are listed by kind, then sorted by schema and name, so the output only changes when
+/// the schema does.
+pub async fn dump_schema<'c, A>(conn: A) -> Result<String, sqlx::Error>
+where
+    A: Acquire<'c, Database = Postgres>,
+{
+    let mut conn = conn.acquire().await?;
+
+    let mut out = String::from(HEADER);
+    out.push('\n');
+
+    for query in [
+        TYPES,
+        TABLES,
+        SEQUENCES,
+        CONSTRAINTS,
+        INDEXES,
+        VIEWS,
+        FUNCTIONS,
+    ] {
+        let rows: Vec<(String, String, String)> =
+            sqlx::query_as(query).fetch_all(&mut *conn).await?;
+
+        for (_, _, definition) in rows {
+            out.push('\n');
+            out.push_str(definition.trim());
+            out.push('\n');
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::Executor;
+
+    use crate::testing::*;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn dump() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut conn = config.connect().await.unwrap();
+        conn.execute(
+            r#"
+            create type mood as enum ('happy', 'sad');
+            create table users (
+                id bigint generated always as identity primary key,
+                email text not null unique,
+                feeling mood default 'happy'
+            );
+            create index users_feeling on users (feeling);
+            create view happy_users as select id from users where feeling = 'happy';
+            "#,
+        )
+        .await
+        .unwrap();
+
+        let schema = dump_schema(&mut conn).await.unwrap();
+
+        for expected in [
+            "create type public.mood as enum ('happy', 'sad');",
+            "create table public.users (\n    id bigint generated always as identity not null,",
+            "    email text not null,",
+            "alter table public.users add constraint users_pkey PRIMARY KEY (id);",
+            "alter table public.users add constraint users_email_key UNIQUE (email);",
+            "CREATE INDEX users_feeling ON public.users USING btree (feeling);",
+            "create view public.happy_users as\n",
+            "create table public.schema_migrations (",
+            "FUNCTION public._squill_claim_migration(mid bigint, mname text)",
+        ] {
+            assert!(schema.contains(expected), "{expected:?} in {schema}");
+        }
+
+        // The implicit identity sequence isn't listed separately.
+        assert!(!schema.contains("create sequence"), "{schema}");
+
+        // The output is stable.
+        assert_eq!(schema, dump_schema(&mut conn).await.unwrap());
+    }
+}
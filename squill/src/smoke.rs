@@ -0,0 +1,192 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use sqlx::Executor;
+
+use crate::config::Config;
+use crate::sandbox::{Sandbox, SandboxError};
+use crate::{error_chain, migrate_all};
+
+/// What to run against the smoke test database.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SmokeOptions {
+    /// SQL files to run after the migrations. An assertion passes if its file runs without error,
+    /// so use something that raises (like `assert` in a `do` block) to check the schema.
+    pub assertions: Vec<PathBuf>,
+
+    /// Leave the database in place afterward instead of dropping it.
+    pub keep: bool,
+}
+
+/// The outcome of each step of a smoke test, in the order they ran.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmokeReport {
+    /// The name of the disposable database.
+    pub database: String,
+    pub steps: Vec<SmokeStep>,
+}
+
+impl SmokeReport {
+    pub fn passed(&self) -> bool {
+        self.steps.iter().all(|s| s.error.is_none())
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.steps.iter().map(|s| s.duration).sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmokeStep {
+    pub name: String,
+    pub duration: Duration,
+    pub error: Option<String>,
+}
+
+/// Create a new database, apply every migration to it, run the assertions, and drop it.
+///
+/// The database is created on the configured server using the configured connection. Failed steps
+/// are recorded in the report instead of returned, and the database is dropped even if an earlier
+/// step failed. Notifications and the state file are never used for the disposable database.
+pub async fn smoke_test(config: &Config, opts: &SmokeOptions) -> Result<SmokeReport, SmokeError> {
+    let mut steps = Vec::new();
+
+    let started = Instant::now();
+    let sandbox = Sandbox::create(config, "squill_smoke")
+        .await
+        .map_err(SmokeError::Sandbox)?;
+    let database = sandbox.name().to_owned();
+    steps.push(SmokeStep {
+        name: format!("create database {database}"),
+        duration: started.elapsed(),
+        error: None,
+    });
+
+    let smoke_config = sandbox.config();
+
+    let started = Instant::now();
+    let migrated = migrate_all(&smoke_config).await;
+    steps.push(SmokeStep {
+        name: match &migrated {
+            Ok(report) => format!("migrate ({} applied)", report.applied.len()),
+            Err(_) => String::from("migrate"),
+        },
+        duration: started.elapsed(),
+        error: migrated.as_ref().err().map(|err| error_chain(err)),
+    });
+
+    // Assertions against a partly-migrated schema would only add noise.
+    if migrated.is_ok() {
+        for path in &opts.assertions {
+            let started = Instant::now();
+            let error = run_assertion(&smoke_config, path).await.err();
+            steps.push(SmokeStep {
+                name: format!("assert {}", path.to_string_lossy()),
+                duration: started.elapsed(),
+                error,
+            });
+        }
+    }
+
+    if !opts.keep {
+        let started = Instant::now();
+        let dropped = sandbox.drop_database().await;
+        steps.push(SmokeStep {
+            name: format!("drop database {database}"),
+            duration: started.elapsed(),
+            error: dropped.err().map(|err| error_chain(&err)),
+        });
+    }
+
+    Ok(SmokeReport { database, steps })
+}
+
+async fn run_assertion(config: &Config, path: &Path) -> Result<(), String> {
+    let sql = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+
+    // Each assertion gets its own connection, so one can't leave session state for the next. The
+    // connection is closed before the database is dropped.
+    let mut conn = config.connect().await.map_err(|err| error_chain(&err))?;
+    (&mut conn)
+        .execute(&*sql)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum SmokeError {
+    #[error(transparent)]
+    Sandbox(SandboxError),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::create_init_migration;
+    use crate::index::MigrationIndex;
+    use crate::testing::*;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn smoke() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+
+        create_init_migration(&config).unwrap();
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        index.create(fake_migration(1, "one")).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let passing = dir.path().join("passing.sql");
+        std::fs::write(
+            &passing,
+            "do $$ begin assert (select count(*) from tbl_one) = 0; end $$;",
+        )
+        .unwrap();
+        let failing = dir.path().join("failing.sql");
+        std::fs::write(&failing, "select * from tbl_missing").unwrap();
+
+        let opts = SmokeOptions {
+            assertions: vec![passing.clone()],
+            keep: false,
+        };
+        let report = smoke_test(&config, &opts).await.unwrap();
+        assert!(report.passed(), "{:?}", report);
+        assert_eq!(4, report.steps.len(), "{:?}", report);
+        assert_eq!("migrate (2 applied)", report.steps[1].name);
+
+        // The smoke database is gone, and the configured one was never migrated.
+        let mut conn = config.connect().await.unwrap();
+        let exists: bool =
+            sqlx::query_scalar("select exists (select from pg_database where datname = $1)")
+                .bind(&report.database)
+                .fetch_one(&mut conn)
+                .await
+                .unwrap();
+        assert!(!exists);
+        let migrated: Option<String> =
+            sqlx::query_scalar("select to_regclass('schema_migrations')::text")
+                .fetch_one(&mut conn)
+                .await
+                .unwrap();
+        assert_eq!(None, migrated);
+
+        let opts = SmokeOptions {
+            assertions: vec![failing, passing],
+            keep: false,
+        };
+        let report = smoke_test(&config, &opts).await.unwrap();
+        assert!(!report.passed());
+        let failed: Vec<_> = report
+            .steps
+            .iter()
+            .filter(|s| s.error.is_some())
+            .map(|s| s.name.as_str())
+            .collect();
+        assert_eq!(1, failed.len(), "{:?}", report);
+        assert!(failed[0].ends_with("failing.sql"), "{:?}", report);
+    }
+}
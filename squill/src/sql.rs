@@ -0,0 +1,297 @@
+/// Split a SQL file into its individual statements.
+///
+/// Semicolons inside quoted strings (including `E'...'` strings with backslash escapes), quoted
+/// identifiers, comments, dollar-quoted bodies (like function definitions), and `BEGIN ATOMIC ...
+/// END` function bodies don't end a statement. Chunks that only contain whitespace and comments
+/// are dropped.
+///
+/// This is a lexer, not a parser, so it can't tell when a statement is invalid. Postgres gets to
+/// report those errors when the statement is run.
+pub fn split_statements(sql: &str) -> Vec<&str> {
+    let bytes = sql.as_bytes();
+
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut has_code = false;
+    let mut i = 0;
+
+    // How deep into `BEGIN ATOMIC` bodies (and the `CASE` expressions in them) this is, since
+    // their `END` is what ends them instead of a semicolon.
+    let mut atomic_depth = 0;
+    let mut after_begin = false;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b';' if atomic_depth > 0 => {
+                after_begin = false;
+                i += 1;
+            }
+
+            b';' => {
+                after_begin = false;
+                if has_code {
+                    statements.push(sql[start..=i].trim());
+                }
+                has_code = false;
+                start = i + 1;
+                i += 1;
+            }
+
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i = find_from(sql, i, "\n").map_or(bytes.len(), |end| end + 1);
+            }
+
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = skip_block_comment(bytes, i);
+            }
+
+            quote @ (b'\'' | b'"') => {
+                has_code = true;
+                after_begin = false;
+                i = skip_quoted(bytes, i, quote);
+            }
+
+            c if (c == b'_' || c.is_ascii_alphabetic()) && !continues_word(bytes, i) => {
+                has_code = true;
+
+                let end = word_end(bytes, i);
+                let word = &sql[i..end];
+
+                // An escape string, like E'it\'s', where backslashes escape the quote.
+                if word.eq_ignore_ascii_case("e") && bytes.get(end) == Some(&b'\'') {
+                    after_begin = false;
+                    i = skip_escape_string(bytes, end);
+                    continue;
+                }
+
+                if after_begin && word.eq_ignore_ascii_case("atomic") {
+                    atomic_depth += 1;
+                } else if atomic_depth > 0 && word.eq_ignore_ascii_case("case") {
+                    atomic_depth += 1;
+                } else if atomic_depth > 0 && word.eq_ignore_ascii_case("end") {
+                    atomic_depth -= 1;
+                }
+
+                after_begin = word.eq_ignore_ascii_case("begin");
+                i = end;
+            }
+
+            b'$' => {
+                has_code = true;
+                i = match dollar_tag(sql, i) {
+                    Some(tag) => {
+                        let body = i + tag.len();
+                        find_from(sql, body, tag).map_or(bytes.len(), |end| end + tag.len())
+                    }
+                    None => i + 1,
+                };
+            }
+
+            c => {
+                if !c.is_ascii_whitespace() {
+                    has_code = true;
+                    after_begin = false;
+                }
+                i += 1;
+            }
+        }
+    }
+
+    if has_code {
+        statements.push(sql[start..].trim());
+    }
+
+    statements
+}
+
+fn find_from(sql: &str, from: usize, needle: &str) -> Option<usize> {
+    sql[from..].find(needle).map(|offset| from + offset)
+}
+
+/// Skip past a quoted string or identifier. Doubled quotes are escapes, not the end.
+fn skip_quoted(bytes: &[u8], start: usize, quote: u8) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        if bytes[i] == quote {
+            if bytes.get(i + 1) == Some(&quote) {
+                i += 2;
+                continue;
+            }
+            return i + 1;
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+/// Whether the byte here is in the middle of an identifier (or a number), not the start of one.
+fn continues_word(bytes: &[u8], i: usize) -> bool {
+    i.checked_sub(1)
+        .is_some_and(|prev| is_word_byte(bytes[prev]))
+}
+
+fn word_end(bytes: &[u8], start: usize) -> usize {
+    let mut i = start;
+    while i < bytes.len() && is_word_byte(bytes[i]) {
+        i += 1;
+    }
+    i
+}
+
+fn is_word_byte(c: u8) -> bool {
+    c == b'_' || c == b'$' || c.is_ascii_alphanumeric() || !c.is_ascii()
+}
+
+/// Skip past an `E'...'` string starting at its quote. A backslash escapes the next character,
+/// and doubled quotes still work too.
+fn skip_escape_string(bytes: &[u8], start: usize) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'\'' if bytes.get(i + 1) == Some(&b'\'') => i += 2,
+            b'\'' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// Skip past a block comment, which can be nested in Postgres.
+fn skip_block_comment(bytes: &[u8], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1)) {
+            (b'/', Some(b'*')) => {
+                depth += 1;
+                i += 2;
+            }
+            (b'*', Some(b'/')) => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// Read the `$tag$` starting here, if this is the start of a dollar quote.
+///
+/// Positional parameters like `$1` and identifiers like `a$b` are not dollar quotes.
+fn dollar_tag(sql: &str, start: usize) -> Option<&str> {
+    let prev = start.checked_sub(1).map(|i| sql.as_bytes()[i]);
+    if prev.is_some_and(|c| c == b'_' || c.is_ascii_alphanumeric()) {
+        return None;
+    }
+
+    let rest = &sql[start + 1..];
+    let end = rest.find('$')?;
+    let tag = &rest[..end];
+
+    let valid = tag
+        .chars()
+        .enumerate()
+        .all(|(i, c)| c == '_' || c.is_alphabetic() || (i > 0 && c.is_ascii_digit()));
+
+    valid.then_some(&sql[start..start + end + 2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split() {
+        let sql = r#"
+            -- A comment; with a semicolon
+            create table t (id int, note text default 'a;b', "odd;name" int);
+
+            /* block; /* nested; */ comment */
+            create function f() returns int as $$
+                select 1;
+            $$ language sql;
+
+            create function g() returns int as $body$ begin return 2; end $body$ language plpgsql;
+
+            insert into t values ($1, 'it''s; fine');
+            -- trailing comment
+        "#;
+
+        let expected = vec![
+            r#"-- A comment; with a semicolon
+            create table t (id int, note text default 'a;b', "odd;name" int);"#,
+            "/* block; /* nested; */ comment */
+            create function f() returns int as $$
+                select 1;
+            $$ language sql;",
+            "create function g() returns int as $body$ begin return 2; end $body$ language plpgsql;",
+            "insert into t values ($1, 'it''s; fine');",
+        ];
+
+        assert_eq!(expected, split_statements(sql));
+    }
+
+    #[test]
+    fn escape_strings() {
+        let sql = r#"
+            insert into t values (E'it\'s; fine', e'back\\', 'plain\');
+            select 'done';
+        "#;
+
+        assert_eq!(
+            vec![
+                r#"insert into t values (E'it\'s; fine', e'back\\', 'plain\');"#,
+                "select 'done';",
+            ],
+            split_statements(sql)
+        );
+
+        // Only a lone E starts an escape string.
+        assert_eq!(
+            vec!["select name'x';", "select 2;"],
+            split_statements("select name'x'; select 2;")
+        );
+    }
+
+    #[test]
+    fn begin_atomic() {
+        let sql = r#"
+            begin;
+            create function add(a int, b int) returns int
+                language sql
+                BEGIN ATOMIC
+                    select case when a is null then 0 else a end + b;
+                    select 2;
+                END;
+            commit;
+        "#;
+
+        assert_eq!(
+            vec![
+                "begin;",
+                "create function add(a int, b int) returns int
+                language sql
+                BEGIN ATOMIC
+                    select case when a is null then 0 else a end + b;
+                    select 2;
+                END;",
+                "commit;",
+            ],
+            split_statements(sql)
+        );
+    }
+
+    #[test]
+    fn no_trailing_semicolon() {
+        assert_eq!(
+            vec!["select 1;", "select 2"],
+            split_statements("select 1; select 2")
+        );
+        assert!(split_statements("-- nothing to see here\n").is_empty());
+    }
+}
@@ -0,0 +1,160 @@
+use std::path::PathBuf;
+
+use sqlx::{Acquire, PgConnection, Postgres};
+
+use crate::config::{Config, ConnectError};
+use crate::lint::check_lint;
+use crate::report::MigrateReport;
+use crate::status::Status;
+use crate::{apply_pending, ApplyOptions, MigrateAllError};
+
+/// The advisory lock key that application instances hold while they migrate at startup.
+///
+/// This spells "squill" in ASCII.
+pub const MIGRATION_LOCK_KEY: i64 = 0x73_71_75_69_6c_6c;
+
+/// Apply the pending migrations in this directory at application startup.
+///
+/// This takes a pool (as `&PgPool`) or a connection, and uses defaults that suit an app that
+/// migrates itself when it starts:
+///
+/// - Only one instance migrates at a time. The others wait for an advisory lock, then find
+///   nothing left to do.
+/// - Down migrations are not allowed (only_up).
+///
+/// Use [`run_pending_migrations_with`] to change the other settings.
+pub async fn run_pending_migrations<'c, A>(
+    conn: A,
+    migrations_dir: impl Into<PathBuf>,
+) -> Result<MigrateReport, MigrateAllError>
+where
+    A: Acquire<'c, Database = Postgres>,
+{
+    let config = Config {
+        migrations_dir: migrations_dir.into(),
+        only_up: true,
+        ..Default::default()
+    };
+
+    run_pending_migrations_with(conn, &config).await
+}
+
+/// Like [`run_pending_migrations`], but with the rest of the settings from this config.
+///
+/// The connection settings in the config are ignored, since this uses the connection it's given.
+pub async fn run_pending_migrations_with<'c, A>(
+    conn: A,
+    config: &Config,
+) -> Result<MigrateReport, MigrateAllError>
+where
+    A: Acquire<'c, Database = Postgres>,
+{
+    let mut conn = conn
+        .acquire()
+        .await
+        .map_err(|err| MigrateAllError::Connect(ConnectError::Connect(err)))?;
+
+    tracing::info!("Waiting for the migration lock");
+    sqlx::query("select pg_advisory_lock($1)")
+        .bind(MIGRATION_LOCK_KEY)
+        .execute(&mut *conn)
+        .await
+        .map_err(MigrateAllError::Lock)?;
+
+    // The lock belongs to the session, so it has to be released even if migrating failed.
+    // Otherwise, a pooled connection would keep holding it.
+    let result = migrate_locked(&mut conn, config).await;
+
+    let unlocked = sqlx::query("select pg_advisory_unlock($1)")
+        .bind(MIGRATION_LOCK_KEY)
+        .execute(&mut *conn)
+        .await;
+    if let Err(err) = unlocked {
+        tracing::warn!("failed to release the migration lock: {}", err);
+    }
+
+    result
+}
+
+async fn migrate_locked(
+    conn: &mut PgConnection,
+    config: &Config,
+) -> Result<MigrateReport, MigrateAllError> {
+    // The app brought its own connection, so it hasn't been checked yet.
+    config
+        .check_identity(&mut *conn)
+        .await
+        .map_err(MigrateAllError::Connect)?;
+
+    // Another instance might have finished migrating while this one waited for the lock, so the
+    // status has to be read after getting it.
+    let status = Status::with_conn(config, &mut *conn)
+        .await
+        .map_err(MigrateAllError::Status)?;
+
+    let pending = status.pending();
+    status
+        .check_order(&pending, config.out_of_order)
+        .map_err(MigrateAllError::OutOfOrder)?;
+
+    check_lint(&pending, config.lint).map_err(MigrateAllError::Lint)?;
+
+    apply_pending(config, conn, pending, &ApplyOptions::default()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::postgres::PgPoolOptions;
+
+    use crate::create_init_migration;
+    use crate::index::MigrationIndex;
+    use crate::testing::*;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn run_pending() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+
+        create_init_migration(&config).unwrap();
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        index.create(fake_migration(1, "one")).unwrap();
+        index.create(fake_migration(2, "two")).unwrap();
+
+        let pool = PgPoolOptions::new()
+            .max_connections(2)
+            .connect_with(config.database_connect_options.clone().unwrap())
+            .await
+            .unwrap();
+
+        // Both instances start at once, but only one of them applies the migrations.
+        let (first, second) = tokio::join!(
+            run_pending_migrations(&pool, &config.migrations_dir),
+            run_pending_migrations(&pool, &config.migrations_dir),
+        );
+        let mut applied: Vec<_> = [first.unwrap(), second.unwrap()]
+            .iter()
+            .map(|report| report.applied.len())
+            .collect();
+        applied.sort();
+        assert_eq!(vec![0, 3], applied);
+
+        // The lock was released.
+        let locked: bool = sqlx::query_scalar(
+            r#"
+            select exists (
+                select 1 from pg_locks
+                where locktype = 'advisory' and (classid::int8 << 32 | objid::int8) = $1
+            )
+            "#,
+        )
+        .bind(MIGRATION_LOCK_KEY)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert!(!locked);
+
+        pool.close().await;
+    }
+}
@@ -0,0 +1,151 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::db::{MigrationLog, MigrationRecord};
+
+/// A snapshot of the migration log from the last time the database was queried.
+///
+/// This is saved to the configured state file so status can still be shown (as a best guess)
+/// when the database is unreachable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedLog {
+    pub fetched_at: OffsetDateTime,
+    pub log: MigrationLog,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StateFile {
+    fetched_at: OffsetDateTime,
+    applied: Vec<MigrationRecord>,
+}
+
+impl CachedLog {
+    pub fn now(log: MigrationLog) -> Self {
+        Self {
+            fetched_at: OffsetDateTime::now_utc(),
+            log,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, StateError> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Err(StateError::Missing(path.to_path_buf()));
+            }
+
+            Err(err) => {
+                return Err(StateError::Read {
+                    path: path.to_path_buf(),
+                    err,
+                });
+            }
+        };
+
+        let state: StateFile = serde_json::from_str(&content).map_err(|err| StateError::Parse {
+            path: path.to_path_buf(),
+            err,
+        })?;
+
+        Ok(Self {
+            fetched_at: state.fetched_at,
+            log: MigrationLog::from_records(state.applied),
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), StateError> {
+        let state = StateFile {
+            fetched_at: self.fetched_at,
+            applied: self.log.iter().cloned().collect(),
+        };
+
+        let content = serde_json::to_string_pretty(&state).map_err(StateError::Serialize)?;
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|err| StateError::Write {
+                path: parent.to_path_buf(),
+                err,
+            })?;
+        }
+
+        std::fs::write(path, content).map_err(|err| StateError::Write {
+            path: path.to_path_buf(),
+            err,
+        })
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum StateError {
+    #[error("no state_file configured")]
+    NotConfigured,
+
+    #[error("no cached state found: {0}")]
+    Missing(PathBuf),
+
+    #[error("failed to read state file: {path}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+
+    #[error("failed to write state file: {path}")]
+    Write {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+
+    #[error("failed to parse state file: {path}")]
+    Parse {
+        path: PathBuf,
+        #[source]
+        err: serde_json::Error,
+    },
+
+    #[error("failed to serialize state")]
+    Serialize(#[source] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn round_trip() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut conn = config.connect().await.unwrap();
+        let log = MigrationLog::new(&mut conn).await.unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested/state.json");
+
+        let expected = CachedLog::now(log);
+        expected.save(&path).unwrap();
+
+        let actual = CachedLog::load(&path).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+
+        match CachedLog::load(&path) {
+            Err(StateError::Missing(p)) => assert_eq!(path, p),
+
+            Ok(cached) => panic!("Unexpected success: {:?}", cached),
+            Err(err) => panic!("Unexpected error: {:?}", err),
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use sqlx::{Executor, PgConnection};
+
+use crate::migrate::MigrationId;
+
+/// Cumulative server statistics at one point in time.
+///
+/// Row counts come from `pg_stat_user_tables` and buffer counts from `pg_stat_database` (for the
+/// current database only).
+#[derive(sqlx::FromRow, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    pub rows_inserted: i64,
+    pub rows_updated: i64,
+    pub rows_deleted: i64,
+    pub blocks_hit: i64,
+    pub blocks_read: i64,
+}
+
+/// How much work the server did while running a migration.
+///
+/// Postgres collects these statistics asynchronously and shares them across sessions, so this is
+/// a best-effort summary: concurrent activity is included and recent changes may be missed.
+#[derive(sqlx::FromRow, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MigrationStats {
+    pub rows_inserted: i64,
+    pub rows_updated: i64,
+    pub rows_deleted: i64,
+    pub blocks_hit: i64,
+    pub blocks_read: i64,
+}
+
+const SNAPSHOT: &str = r#"
+select
+    coalesce(sum(t.n_tup_ins), 0)::bigint as rows_inserted,
+    coalesce(sum(t.n_tup_upd), 0)::bigint as rows_updated,
+    coalesce(sum(t.n_tup_del), 0)::bigint as rows_deleted,
+    (select blks_hit from pg_stat_database where datname = current_database())::bigint as blocks_hit,
+    (select blks_read from pg_stat_database where datname = current_database())::bigint as blocks_read
+from pg_stat_user_tables t
+"#;
+
+const CREATE_STATS_TABLE: &str = r#"
+create table if not exists schema_migration_stats (
+    id bigint primary key,
+    rows_inserted bigint not null,
+    rows_updated bigint not null,
+    rows_deleted bigint not null,
+    blocks_hit bigint not null,
+    blocks_read bigint not null,
+    recorded_at timestamp not null default current_timestamp
+)
+"#;
+
+impl StatsSnapshot {
+    pub async fn take(conn: &mut PgConnection) -> sqlx::Result<Self> {
+        // Statistics are cached for the rest of a transaction once they're read, so make sure
+        // this sees the latest values.
+        (&mut *conn)
+            .execute("select pg_stat_clear_snapshot()")
+            .await?;
+
+        sqlx::query_as(SNAPSHOT).fetch_one(conn).await
+    }
+
+    pub fn since(&self, before: &StatsSnapshot) -> MigrationStats {
+        MigrationStats {
+            rows_inserted: self.rows_inserted - before.rows_inserted,
+            rows_updated: self.rows_updated - before.rows_updated,
+            rows_deleted: self.rows_deleted - before.rows_deleted,
+            blocks_hit: self.blocks_hit - before.blocks_hit,
+            blocks_read: self.blocks_read - before.blocks_read,
+        }
+    }
+}
+
+impl MigrationStats {
+    /// Save these stats for the migration, replacing any from an earlier run.
+    ///
+    /// The schema_migration_stats table is created if it doesn't exist yet.
+    pub async fn record(&self, conn: &mut PgConnection, id: MigrationId) -> sqlx::Result<()> {
+        (&mut *conn).execute(CREATE_STATS_TABLE).await?;
+
+        let query = sqlx::query(
+            r#"
+            insert into schema_migration_stats
+                (id, rows_inserted, rows_updated, rows_deleted, blocks_hit, blocks_read)
+            values ($1, $2, $3, $4, $5, $6)
+            on conflict (id) do update set
+                rows_inserted = excluded.rows_inserted,
+                rows_updated = excluded.rows_updated,
+                rows_deleted = excluded.rows_deleted,
+                blocks_hit = excluded.blocks_hit,
+                blocks_read = excluded.blocks_read,
+                recorded_at = current_timestamp
+            "#,
+        )
+        .bind(id.as_i64())
+        .bind(self.rows_inserted)
+        .bind(self.rows_updated)
+        .bind(self.rows_deleted)
+        .bind(self.blocks_hit)
+        .bind(self.blocks_read);
+
+        conn.execute(query).await?;
+        Ok(())
+    }
+
+    /// Load the recorded stats for this migration, if there are any.
+    pub async fn load(conn: &mut PgConnection, id: MigrationId) -> sqlx::Result<Option<Self>> {
+        let exists: (bool,) =
+            sqlx::query_as("select to_regclass('schema_migration_stats') is not null")
+                .fetch_one(&mut *conn)
+                .await?;
+
+        if !exists.0 {
+            return Ok(None);
+        }
+
+        sqlx::query_as(
+            r#"
+            select rows_inserted, rows_updated, rows_deleted, blocks_hit, blocks_read
+            from schema_migration_stats
+            where id = $1
+            "#,
+        )
+        .bind(id.as_i64())
+        .fetch_optional(conn)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::MigrationIndex;
+    use crate::migrate::MigrateOptions;
+    use crate::testing::*;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn collect_stats() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+        let two = index.create(fake_migration(2, "two")).unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+
+        one.up(&mut conn).await.unwrap();
+        assert_eq!(None, MigrationStats::load(&mut conn, one.id).await.unwrap());
+
+        let opts = MigrateOptions {
+            collect_stats: true,
+            ..Default::default()
+        };
+        two.up_with(&mut conn, &opts).await.unwrap();
+
+        let stats = MigrationStats::load(&mut conn, two.id).await.unwrap();
+        assert!(stats.is_some());
+        assert_eq!(None, MigrationStats::load(&mut conn, one.id).await.unwrap());
+    }
+
+    #[test]
+    fn deltas() {
+        let before = StatsSnapshot {
+            rows_inserted: 10,
+            rows_updated: 1,
+            rows_deleted: 0,
+            blocks_hit: 100,
+            blocks_read: 5,
+        };
+        let after = StatsSnapshot {
+            rows_inserted: 15,
+            rows_updated: 1,
+            rows_deleted: 2,
+            blocks_hit: 180,
+            blocks_read: 6,
+        };
+
+        let expected = MigrationStats {
+            rows_inserted: 5,
+            rows_updated: 0,
+            rows_deleted: 2,
+            blocks_hit: 80,
+            blocks_read: 1,
+        };
+        assert_eq!(expected, after.since(&before));
+    }
+}
@@ -1,9 +1,16 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{Acquire, Postgres};
+use time::format_description::well_known::Iso8601;
 
 use crate::config::{Config, ConnectError};
 use crate::db::{MigrationLog, MigrationRecord, QueryError};
+use crate::error_chain;
 use crate::index::{IndexError, IoError, MigrationIndex};
 use crate::migrate::{MigrationDirectory, MigrationId};
+use crate::state::{CachedLog, StateError};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Status {
@@ -15,25 +22,244 @@ impl Status {
     pub async fn new(config: &Config) -> Result<Self, StatusError> {
         let mut conn = config.connect().await.map_err(StatusError::Connect)?;
 
-        let applied = MigrationLog::new(&mut conn)
-            .await
-            .map_err(StatusError::Query)?;
+        Self::with_conn(config, &mut conn).await
+    }
+
+    /// Like [`Status::new`], but using an existing connection (or pool) instead of opening a new
+    /// one.
+    pub async fn with_conn<'c, A>(config: &Config, conn: A) -> Result<Self, StatusError>
+    where
+        A: Acquire<'c, Database = Postgres>,
+    {
+        let applied = MigrationLog::new(conn).await.map_err(StatusError::Query)?;
 
-        let available = MigrationIndex::new(&config.migrations_dir).map_err(StatusError::Index)?;
+        save_state(config, &applied);
+
+        let available = config.migration_index().map_err(StatusError::Index)?;
 
         Ok(Self { applied, available })
     }
 
+    /// Build a best-effort status from the cached migration log instead of the database.
+    ///
+    /// The result may be out of date. Check `fetched_at` to see how old it is.
+    pub fn offline(config: &Config) -> Result<OfflineStatus, StatusError> {
+        let Some(path) = &config.state_file else {
+            return Err(StatusError::State(StateError::NotConfigured));
+        };
+
+        let cached = CachedLog::load(path).map_err(StatusError::State)?;
+
+        let available = config.migration_index().map_err(StatusError::Index)?;
+
+        Ok(OfflineStatus {
+            status: Self {
+                applied: cached.log,
+                available,
+            },
+            fetched_at: cached.fetched_at,
+        })
+    }
+
+    /// Migrations that haven't been applied yet.
+    ///
+    /// Retired migrations are never pending, since their replacements cover them.
     pub fn pending(&self) -> Vec<MigrationDirectory> {
+        let retired = self.available.retired();
+
         self.available
             .iter()
-            .filter(|m| !self.applied.log.contains_key(&m.id))
+            .filter(|m| !self.applied.log.contains_key(&m.id) && !retired.contains_key(&m.id))
             .cloned()
             .collect()
     }
+
+    /// Migrations that have been applied, but whose files are no longer in the migrations dir.
+    ///
+    /// Migrations that were squashed into another one don't count as missing.
+    pub fn missing(&self) -> Vec<MigrationRecord> {
+        let squashed = self.available.squashed_ids();
+
+        self.applied
+            .iter()
+            .filter(|r| self.available.get(r.id).is_none() && !squashed.contains(&r.id))
+            .cloned()
+            .collect()
+    }
+
+    /// Keep only the migrations that match this filter, like `squill status --pending`.
+    pub fn filtered(&self, filter: &StatusFilter) -> Status {
+        let pending: BTreeSet<_> = self.pending().into_iter().map(|m| m.id).collect();
+        let missing: BTreeSet<_> = self.missing().into_iter().map(|r| r.id).collect();
+
+        let any_state = !(filter.applied || filter.pending || filter.missing_files);
+        let since = |id: MigrationId, run_at: Option<time::PrimitiveDateTime>| match filter.since {
+            None => true,
+            Some(StatusSince::Id(since)) => id >= since,
+            // Pending migrations will be applied after any date that has already passed.
+            Some(StatusSince::Date(since)) => match run_at {
+                Some(run_at) => run_at.date() >= since,
+                None => true,
+            },
+        };
+
+        let applied = self
+            .applied
+            .iter()
+            .filter(|r| {
+                any_state || filter.applied || (filter.missing_files && missing.contains(&r.id))
+            })
+            .filter(|r| since(r.id, Some(r.run_at)))
+            .cloned();
+        let applied = MigrationLog::from_records(applied);
+
+        let mut available = self.available.clone();
+        available.index.retain(|id, _| {
+            let state = match self.applied.get(*id) {
+                Some(_) => filter.applied,
+                None => filter.pending && pending.contains(id),
+            };
+            let run_at = self.applied.get(*id).map(|r| r.run_at);
+
+            (any_state || state) && since(*id, run_at)
+        });
+
+        Status { applied, available }
+    }
+}
+
+/// Which migrations [`Status::filtered`] keeps.
+///
+/// The applied, pending, and missing-files states can be combined, and a migration only has to
+/// match one of them. Without any, every state is kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatusFilter {
+    pub applied: bool,
+    pub pending: bool,
+
+    /// Applied migrations whose files are no longer in the migrations directory.
+    pub missing_files: bool,
+
+    pub since: Option<StatusSince>,
+}
+
+/// Where a status listing starts: at a migration ID, or with the migrations applied on or after a
+/// date (plus the pending ones).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusSince {
+    Id(MigrationId),
+    Date(time::Date),
+}
+
+impl std::str::FromStr for StatusSince {
+    type Err = ParseStatusSinceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(id) = s.parse() {
+            return Ok(Self::Id(id));
+        }
+
+        time::Date::parse(s, &Iso8601::DATE)
+            .map(Self::Date)
+            .map_err(|_| ParseStatusSinceError(s.to_owned()))
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("expected a migration ID or a date like 2024-01-31, got {0:?}")]
+pub struct ParseStatusSinceError(String);
+
+/// What to do about pending migrations with lower IDs than an already-applied one.
+///
+/// Applying an older migration after newer ones is sometimes fine, but it can also mean two
+/// branches were merged without anyone checking how their migrations interact.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutOfOrderPolicy {
+    #[default]
+    Allow,
+    Warn,
+    Error,
+}
+
+impl Status {
+    /// Pending migrations whose IDs are lower than the highest applied ID.
+    pub fn out_of_order(&self) -> Vec<MigrationDirectory> {
+        let Some(latest) = self.applied.log.keys().next_back() else {
+            return Vec::new();
+        };
+
+        self.pending()
+            .into_iter()
+            .filter(|m| m.id < *latest)
+            .collect()
+    }
+
+    /// Apply the out-of-order policy to these pending migrations.
+    ///
+    /// This returns the out-of-order migrations unless the policy makes them an error. With the
+    /// warn policy, each one is also logged as a warning.
+    pub fn check_order(
+        &self,
+        pending: &[MigrationDirectory],
+        policy: OutOfOrderPolicy,
+    ) -> Result<Vec<MigrationDirectory>, OutOfOrderError> {
+        let Some(latest) = self.applied.log.keys().next_back() else {
+            return Ok(Vec::new());
+        };
+
+        let out_of_order: Vec<_> = pending.iter().filter(|m| m.id < *latest).cloned().collect();
+
+        if out_of_order.is_empty() {
+            return Ok(out_of_order);
+        }
+
+        match policy {
+            OutOfOrderPolicy::Allow => {}
+            OutOfOrderPolicy::Warn => {
+                for m in &out_of_order {
+                    tracing::warn!("Pending migration is older than {}: {}", latest, m);
+                }
+            }
+            OutOfOrderPolicy::Error => {
+                return Err(OutOfOrderError {
+                    latest: *latest,
+                    migrations: out_of_order,
+                })
+            }
+        }
+
+        Ok(out_of_order)
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+#[error("{} pending migrations are older than the latest applied migration ({latest})", .migrations.len())]
+pub struct OutOfOrderError {
+    pub latest: MigrationId,
+    pub migrations: Vec<MigrationDirectory>,
+}
+
+pub(crate) fn save_state(config: &Config, applied: &MigrationLog) {
+    let Some(path) = &config.state_file else {
+        return;
+    };
+
+    // The cache is only a convenience, so this shouldn't stop anything else from working.
+    if let Err(err) = CachedLog::now(applied.clone()).save(path) {
+        tracing::warn!("failed to save migration state: {}", error_chain(&err));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OfflineStatus {
+    pub status: Status,
+    pub fetched_at: time::OffsetDateTime,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum StatusError {
     #[error(transparent)]
     Connect(ConnectError),
@@ -46,6 +272,9 @@ pub enum StatusError {
 
     #[error(transparent)]
     Index(IndexError),
+
+    #[error(transparent)]
+    State(StateError),
 }
 
 #[derive(Debug, Clone)]
@@ -53,20 +282,55 @@ pub struct StatusEntry {
     pub id: MigrationId,
     pub name: String,
     pub run_at: Option<time::PrimitiveDateTime>,
+    pub duration: Option<Duration>,
     pub directory: Option<String>,
+
+    /// The migration that replaced this one, if it has been retired.
+    pub retired_by: Option<MigrationId>,
+
+    /// Whether the migration has no down file, so it can't be undone.
+    pub irreversible: bool,
+
+    /// Whether the migration is missing its up file, so it can't be applied.
+    pub broken: bool,
+
+    /// For an applied migration, the names if its directory doesn't match the name in the
+    /// migration log (like after the directory was renamed).
+    pub name_mismatch: Option<NameMismatch>,
+}
+
+/// An applied migration whose directory has a different name than the migration log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameMismatch {
+    /// The name in the migration log.
+    pub recorded: String,
+
+    /// The name in the migration's directory (or file) name.
+    pub current: String,
 }
 
 impl Status {
     pub fn full_status(&self) -> BTreeMap<MigrationId, StatusEntry> {
         let mut entries = BTreeMap::new();
+        let retired = self.available.retired();
 
         for (id, (row, dir)) in self.collate() {
-            entries.insert(id, Self::status_entry(id, row, dir));
+            let mut entry = Self::status_entry(id, row, dir);
+            entry.retired_by = retired.get(&id).copied();
+            entries.insert(id, entry);
         }
 
         entries
     }
 
+    /// Applied migrations whose directories have a different name than the migration log.
+    pub fn name_mismatches(&self) -> BTreeMap<MigrationId, NameMismatch> {
+        self.full_status()
+            .into_iter()
+            .filter_map(|(id, entry)| Some((id, entry.name_mismatch?)))
+            .collect()
+    }
+
     fn status_entry(
         id: MigrationId,
         row: Option<MigrationRecord>,
@@ -77,19 +341,37 @@ impl Status {
                 id,
                 name: row.name.clone(),
                 run_at: Some(row.run_at),
+                duration: row.duration,
                 directory: Some(dir.to_string()),
+                retired_by: None,
+                irreversible: !dir.is_reversible(),
+                broken: dir.is_broken(),
+                name_mismatch: (row.name != dir.name).then(|| NameMismatch {
+                    recorded: row.name.clone(),
+                    current: dir.name.clone(),
+                }),
             },
             (Some(row), None) => StatusEntry {
                 id,
                 name: row.name.clone(),
                 run_at: Some(row.run_at),
+                duration: row.duration,
                 directory: None,
+                retired_by: None,
+                irreversible: false,
+                broken: false,
+                name_mismatch: None,
             },
             (None, Some(dir)) => StatusEntry {
                 id,
                 name: dir.name.clone(),
                 run_at: None,
+                duration: None,
                 directory: Some(dir.to_string()),
+                retired_by: None,
+                irreversible: !dir.is_reversible(),
+                broken: dir.is_broken(),
+                name_mismatch: None,
             },
             (None, None) => unreachable!("empty status entry for id: {id}"),
         }
@@ -116,6 +398,7 @@ impl Status {
 
 #[cfg(test)]
 mod tests {
+    use crate::migrate::MigrateError;
     use crate::testing::*;
 
     use super::*;
@@ -157,6 +440,225 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[tokio::test]
+    async fn filters() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+
+        let one = index.create(fake_migration(1, "one")).unwrap();
+        let two = index.create(fake_migration(2, "two")).unwrap();
+        let _ = index.create(fake_migration(3, "three")).unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        one.up(&mut conn).await.unwrap();
+        two.up(&mut conn).await.unwrap();
+        std::fs::remove_dir_all(&two.dir).unwrap();
+
+        let status = Status::new(&config).await.unwrap();
+        let ids = |filter: StatusFilter| -> Vec<i64> {
+            let status = status.filtered(&filter);
+            status.full_status().keys().map(|id| id.as_i64()).collect()
+        };
+
+        assert_eq!(vec![0, 1, 2, 3], ids(StatusFilter::default()));
+        let applied = StatusFilter {
+            applied: true,
+            ..Default::default()
+        };
+        assert_eq!(vec![0, 1, 2], ids(applied));
+        let pending = StatusFilter {
+            pending: true,
+            ..Default::default()
+        };
+        assert_eq!(vec![3], ids(pending.clone()));
+        assert_eq!(
+            vec![3],
+            status
+                .filtered(&pending)
+                .pending()
+                .iter()
+                .map(|m| m.id.as_i64())
+                .collect::<Vec<_>>()
+        );
+        let missing_files = StatusFilter {
+            missing_files: true,
+            ..Default::default()
+        };
+        assert_eq!(vec![2], ids(missing_files));
+
+        let since = |s: &str| StatusFilter {
+            since: Some(s.parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(vec![2, 3], ids(since("2")));
+        assert_eq!(vec![0, 1, 2, 3], ids(since("2000-01-01")));
+        assert_eq!(vec![3], ids(since("2999-01-01")));
+        assert!("yesterday".parse::<StatusSince>().is_err());
+    }
+
+    #[tokio::test]
+    async fn out_of_order() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+
+        let one = index.create(fake_migration(1, "one")).unwrap();
+        let two = index.create(fake_migration(2, "two")).unwrap();
+        let three = index.create(fake_migration(3, "three")).unwrap();
+        let four = index.create(fake_migration(4, "four")).unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        two.up(&mut conn).await.unwrap();
+        four.up(&mut conn).await.unwrap();
+
+        let status = Status::new(&config).await.unwrap();
+        let pending = status.pending();
+        assert_eq!(vec![one.clone(), three.clone()], status.out_of_order());
+
+        for policy in [OutOfOrderPolicy::Allow, OutOfOrderPolicy::Warn] {
+            let actual = status.check_order(&pending, policy).unwrap();
+            assert_eq!(vec![one.clone(), three.clone()], actual);
+        }
+
+        let err = status
+            .check_order(&pending, OutOfOrderPolicy::Error)
+            .unwrap_err();
+        assert_eq!(MigrationId(4), err.latest);
+        assert_eq!(vec![one, three], err.migrations);
+    }
+
+    #[tokio::test]
+    async fn retired_migrations() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+
+        let mut params = fake_migration(1, "one");
+        params.up_sql = format!("--squill:retired-by 2\n{}", params.up_sql);
+        let one = index.create(params).unwrap();
+        let two = index.create(fake_migration(2, "two")).unwrap();
+
+        let status = Status::new(&config).await.unwrap();
+        assert_eq!(vec![two], status.pending());
+
+        let entries = status.full_status();
+        assert_eq!(Some(MigrationId(2)), entries[&one.id].retired_by);
+        assert_eq!(None, entries[&MigrationId(2)].retired_by);
+    }
+
+    #[tokio::test]
+    async fn irreversible_migrations() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+
+        let mut params = fake_migration(1, "forward_only");
+        params.down_sql = None;
+        let one = index.create(params).unwrap();
+        let two = index.create(fake_migration(2, "two")).unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        one.up(&mut conn).await.unwrap();
+
+        let entries = Status::new(&config).await.unwrap().full_status();
+        assert!(entries[&one.id].irreversible);
+        assert!(!entries[&two.id].irreversible);
+
+        assert!(matches!(
+            one.down(&mut conn, false).await,
+            Err(MigrateError::NoDownMigration(MigrationId(1)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn renamed_migrations() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+
+        let mut conn = config.connect().await.unwrap();
+        one.up(&mut conn).await.unwrap();
+
+        let status = Status::new(&config).await.unwrap();
+        assert!(status.name_mismatches().is_empty());
+
+        index.rename(one.id, "first").unwrap().execute().unwrap();
+
+        let status = Status::new(&config).await.unwrap();
+        assert_eq!(
+            BTreeMap::from([(
+                one.id,
+                NameMismatch {
+                    recorded: String::from("one"),
+                    current: String::from("first"),
+                }
+            )]),
+            status.name_mismatches()
+        );
+    }
+
+    #[tokio::test]
+    async fn shared_pool() {
+        let env = TestEnv::initialized().await.unwrap();
+        let config = env.config();
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+
+        let one = index.create(fake_migration(1, "one")).unwrap();
+        let _ = index.create(fake_migration(2, "two")).unwrap();
+
+        let pool = config.pool().await.unwrap();
+        one.up(&pool).await.unwrap();
+
+        let (a, b) = tokio::join!(
+            Status::with_conn(&config, &pool),
+            Status::with_conn(&config, &pool),
+        );
+
+        assert_eq!(a.unwrap().pending(), b.unwrap().pending());
+    }
+
+    #[tokio::test]
+    async fn offline_status() {
+        let env = TestEnv::initialized().await.unwrap();
+        let state_dir = tempfile::tempdir().unwrap();
+
+        let mut config = env.config();
+        config.state_file = Some(state_dir.path().join("state.json"));
+
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let _ = index.create(fake_migration(1, "one")).unwrap();
+
+        let online = Status::new(&config).await.unwrap();
+
+        // Make the database unreachable.
+        config.database_connect_options = None;
+        Status::new(&config).await.unwrap_err();
+
+        let offline = Status::offline(&config).unwrap();
+        assert_eq!(online, offline.status);
+        assert_eq!(1, offline.status.pending().len());
+    }
+
+    #[tokio::test]
+    async fn offline_not_configured() {
+        let env = TestEnv::initialized().await.unwrap();
+
+        match Status::offline(&env.config()) {
+            Err(StatusError::State(StateError::NotConfigured)) => (),
+
+            Ok(status) => panic!("Unexpected success: {:?}", status),
+            Err(err) => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
     #[tokio::test]
     async fn status_entries() {
         let env = TestEnv::initialized().await.unwrap();
@@ -203,5 +705,8 @@ mod tests {
             assert_eq!(None, two.run_at);
             assert!(two.directory.is_some());
         }
+
+        let missing: Vec<_> = status.missing().into_iter().map(|r| r.id).collect();
+        assert_eq!(vec![MigrationId(1)], missing);
     }
 }
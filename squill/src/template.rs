@@ -1,9 +1,13 @@
 use lazy_static::lazy_static;
 use std::borrow::Borrow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::ffi::OsString;
 use std::path::{Path, PathBuf};
-use tera::{Context, Tera};
+use tera::{Context, Tera, Value};
+use time::format_description::well_known::Rfc3339;
 
+use crate::config::Config;
+use crate::remote::RemoteError;
 use crate::MigrationId;
 
 // These migration files either have no parameters (init) or will be modified before being run
@@ -23,13 +27,79 @@ lazy_static! {
             ("init.down.sql", include_str!("templates/init.down.sql")),
             ("new.up.sql", include_str!("templates/new.up.sql")),
             ("new.down.sql", include_str!("templates/new.down.sql")),
+            // The built-in library of named groups. Groups in templates_dir (or the templates
+            // repo) with the same names replace these.
+            (
+                "add_column/new.up.sql",
+                include_str!("templates/library/add_column/new.up.sql"),
+            ),
+            (
+                "add_column/new.down.sql",
+                include_str!("templates/library/add_column/new.down.sql"),
+            ),
+            (
+                "backfill/new.up.sql",
+                include_str!("templates/library/backfill/new.up.sql"),
+            ),
+            (
+                "backfill/new.down.sql",
+                include_str!("templates/library/backfill/new.down.sql"),
+            ),
+            (
+                "create_enum/new.up.sql",
+                include_str!("templates/library/create_enum/new.up.sql"),
+            ),
+            (
+                "create_enum/new.down.sql",
+                include_str!("templates/library/create_enum/new.down.sql"),
+            ),
+            (
+                "create_index_concurrently/new.up.sql",
+                include_str!("templates/library/create_index_concurrently/new.up.sql"),
+            ),
+            (
+                "create_index_concurrently/new.down.sql",
+                include_str!("templates/library/create_index_concurrently/new.down.sql"),
+            ),
+            (
+                "create_table/new.up.sql",
+                include_str!("templates/library/create_table/new.up.sql"),
+            ),
+            (
+                "create_table/new.down.sql",
+                include_str!("templates/library/create_table/new.down.sql"),
+            ),
         ])
         .expect("static templates");
 
+        tera.register_filter("quote_ident", quote_ident);
+        tera.register_filter("quote_literal", quote_literal);
+
         tera
     };
 }
 
+/// Quote a string as an SQL identifier, like `{{ table | quote_ident }}`.
+///
+/// Unlike Postgres's `quote_ident`, this always adds the quotes, so the name is used exactly as
+/// written (including its case).
+fn quote_ident(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    let Some(s) = value.as_str() else {
+        return Err(tera::Error::msg("quote_ident can only quote a string"));
+    };
+
+    Ok(Value::String(format!("\"{}\"", s.replace('"', "\"\""))))
+}
+
+/// Quote a string as an SQL string literal, like `{{ comment | quote_literal }}`.
+fn quote_literal(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    let Some(s) = value.as_str() else {
+        return Err(tera::Error::msg("quote_literal can only quote a string"));
+    };
+
+    Ok(Value::String(format!("'{}'", s.replace('\'', "''"))))
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum TemplateId {
     InitUp,
@@ -56,6 +126,15 @@ pub enum TemplateGroup {
     Named(String),
 }
 
+impl std::fmt::Display for TemplateGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateGroup::Named(name) => write!(f, "{}", name),
+            TemplateGroup::Default => write!(f, "(default)"),
+        }
+    }
+}
+
 impl TemplateGroup {
     fn join(&self, id: TemplateId) -> String {
         match self {
@@ -69,69 +148,223 @@ impl TemplateGroup {
 pub struct TemplateContext {
     pub id: MigrationId,
     pub name: String,
+
+    /// Extra variables from the template_vars config and `squill new --var`. These can't replace
+    /// `id` or `name` (or any of the optional fields below that are set).
+    pub vars: BTreeMap<String, String>,
+
+    /// The schema config, if it's set.
+    pub schema: Option<String>,
+
+    /// When the migration was created. Templates get it in RFC 3339 format.
+    pub created_at: Option<time::OffsetDateTime>,
+
+    /// Who created the migration, from the author config or `git config user.name`.
+    pub author: Option<String>,
+
+    /// The active profile (like "staging"), if there is one.
+    pub profile: Option<String>,
 }
 
 impl TemplateContext {
     fn tera_context(&self) -> Context {
         let mut ctx = Context::new();
+        for (key, value) in &self.vars {
+            ctx.insert(key, value);
+        }
         ctx.insert("id", &self.id.as_i64());
         ctx.insert("name", &self.name);
+        if let Some(schema) = &self.schema {
+            ctx.insert("schema", schema);
+        }
+        if let Some(created_at) = self.created_at.and_then(|t| t.format(&Rfc3339).ok()) {
+            ctx.insert("created_at", &created_at);
+        }
+        if let Some(author) = &self.author {
+            ctx.insert("author", author);
+        }
+        if let Some(profile) = &self.profile {
+            ctx.insert("profile", profile);
+        }
         ctx
     }
 }
 
+/// The author for new migrations: the author config, or else git's user.name (if git has one).
+pub fn template_author(config: &Config) -> Option<String> {
+    if let Some(author) = &config.author {
+        return Some(author.clone());
+    }
+
+    let output = std::process::Command::new("git")
+        .args(["config", "user.name"])
+        .output()
+        .ok()?;
+
+    let name = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    (output.status.success() && !name.is_empty()).then_some(name)
+}
+
+/// Templates in this subdirectory can be included by any other template, like
+/// `{% include "partials/header.sql" %}`. It is never treated as a template group.
+pub const PARTIALS_DIR: &str = "partials";
+
 #[derive(Debug, Clone)]
 pub struct Templates {
     tera: Tera,
+
+    /// Where each template that didn't come with Squill was loaded from, by Tera name.
+    paths: BTreeMap<String, PathBuf>,
+}
+
+/// Where a template was loaded from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateSource {
+    /// Built into Squill.
+    Embedded,
+
+    /// A file in templates_dir or the templates_repo checkout.
+    File(PathBuf),
+}
+
+impl std::fmt::Display for TemplateSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateSource::Embedded => write!(f, "(embedded)"),
+            TemplateSource::File(path) => write!(f, "{}", path.to_string_lossy()),
+        }
+    }
+}
+
+/// One of the files a template group provides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateFile {
+    pub group: TemplateGroup,
+    pub id: TemplateId,
+    pub source: TemplateSource,
 }
 
 impl Templates {
     pub fn new(templates_dir: impl AsRef<Path>) -> Result<Self, TemplateError> {
-        let templates_dir = templates_dir.as_ref();
-
         let mut templates = Self::default();
+        templates.register_dir(templates_dir.as_ref())?;
+        Ok(templates)
+    }
 
-        // The default template is in the directory root.
-        templates.register_group(TemplateGroup::Default, templates_dir)?;
-
-        // Named templates are in subdirectories.
-        for subdir in named_template_dirs(templates_dir)? {
-            let name = subdir.file_name().expect("directory has name");
+    /// Load the templates for this config.
+    ///
+    /// Templates from the templates repo (if any) are loaded first, so a local templates_dir can
+    /// override them.
+    pub fn from_config(config: &Config) -> Result<Self, TemplateError> {
+        let mut templates = Self::default();
 
-            // Tera needs the template "path" to be a str
-            let Some(name) = name.to_str() else {
-                return Err(TemplateError::DirName(TemplateDirNameError::NotUtf8 {
-                    name: name.to_owned(),
-                }));
-            };
+        if let Some(repo) = &config.templates_repo {
+            let dir = repo.checkout(&config.cache_dir)?;
+            templates.register_dir(&dir)?;
+        }
 
-            templates.register_group(TemplateGroup::Named(name.to_owned()), &subdir)?;
+        if let Some(dir) = &config.templates_dir {
+            templates.register_dir(dir)?;
         }
 
         Ok(templates)
     }
 
-    fn register_group(&mut self, group: TemplateGroup, dir: &Path) -> Result<(), TemplateError> {
-        for id in [TemplateId::NewUp, TemplateId::NewDown] {
-            let path = dir.join(id.name());
+    /// Register every template in this directory tree, named by its path within the directory.
+    ///
+    /// Besides the `new.up.sql` and `new.down.sql` files of each group, any other SQL file can be
+    /// extended (like `{% extends "_base.sql" %}`) or included. Files in the partials directory
+    /// don't have to be SQL.
+    fn register_dir(&mut self, templates_dir: &Path) -> Result<(), TemplateError> {
+        let mut sources = Vec::new();
+
+        for path in files_within(templates_dir)? {
+            let relative = path
+                .strip_prefix(templates_dir)
+                .expect("template is within templates_dir");
+
+            // Tera template names always use forward slashes, even on Windows.
+            let mut parts = Vec::new();
+            for part in relative.iter() {
+                let Some(part) = part.to_str() else {
+                    return Err(TemplateError::DirName(TemplateDirNameError::NotUtf8 {
+                        name: relative.as_os_str().to_owned(),
+                    }));
+                };
+                parts.push(part);
+            }
+
+            let is_partial = parts.len() > 1 && parts[0] == PARTIALS_DIR;
+            let name = parts.join("/");
+            if !is_partial && !name.ends_with(".sql") {
+                continue;
+            }
 
             if let Some(content) = read_file(&path)? {
-                self.register(&group, id, &content)?;
+                sources.push((name, content, path));
             }
         }
 
+        // Tera resolves `extends` as templates are added, so they have to be added all at once for
+        // a template to extend one that's listed after it.
+        self.tera
+            .add_raw_templates(sources.iter().map(|(name, content, _)| (name, content)))
+            .map_err(TemplateError::Parse)?;
+
+        for (name, _, path) in sources {
+            self.paths.insert(name, path);
+        }
+
         Ok(())
     }
 
-    fn register(
-        &mut self,
-        group: &TemplateGroup,
-        id: TemplateId,
-        content: &str,
-    ) -> Result<(), TemplateError> {
-        self.tera
-            .add_raw_template(&group.join(id), content)
-            .map_err(TemplateError::Parse)
+    /// Every template group that has a new migration template, starting with the default group.
+    pub fn groups(&self) -> Vec<TemplateGroup> {
+        let mut named = BTreeSet::new();
+
+        for name in self.tera.get_template_names() {
+            let Some((group, file)) = name.split_once('/') else {
+                continue;
+            };
+
+            let is_new = file == TemplateId::NewUp.name() || file == TemplateId::NewDown.name();
+            if is_new && group != PARTIALS_DIR {
+                named.insert(group.to_owned());
+            }
+        }
+
+        let mut groups = vec![TemplateGroup::Default];
+        groups.extend(named.into_iter().map(TemplateGroup::Named));
+        groups
+    }
+
+    /// The new migration files each group provides and where they were loaded from, in the same
+    /// order as [`Templates::groups`].
+    pub fn files(&self) -> Vec<TemplateFile> {
+        let names: BTreeSet<_> = self.tera.get_template_names().collect();
+
+        let mut files = Vec::new();
+        for group in self.groups() {
+            for id in [TemplateId::NewUp, TemplateId::NewDown] {
+                let name = group.join(id);
+                if !names.contains(name.as_str()) {
+                    continue;
+                }
+
+                let source = match self.paths.get(&name) {
+                    Some(path) => TemplateSource::File(path.clone()),
+                    None => TemplateSource::Embedded,
+                };
+
+                files.push(TemplateFile {
+                    group: group.clone(),
+                    id,
+                    source,
+                });
+            }
+        }
+
+        files
     }
 
     pub fn render(
@@ -141,9 +374,17 @@ impl Templates {
         ctx: &TemplateContext,
     ) -> Result<String, TemplateError> {
         let group = group.borrow();
+        let name = group.join(id);
+
+        if !self.tera.get_template_names().any(|n| n == name) {
+            return Err(TemplateError::NotFound {
+                group: group.to_string(),
+                file: id.name(),
+            });
+        }
 
         self.tera
-            .render(&group.join(id), &ctx.tera_context())
+            .render(&name, &ctx.tera_context())
             .map_err(TemplateError::Render)
     }
 }
@@ -164,11 +405,15 @@ fn read_file(path: impl AsRef<Path>) -> Result<Option<String>, TemplateReadError
 
 impl Default for Templates {
     fn default() -> Self {
-        Self { tera: TERA.clone() }
+        Self {
+            tera: TERA.clone(),
+            paths: BTreeMap::new(),
+        }
     }
 }
 
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum TemplateError {
     #[error(transparent)]
     ReadDir(#[from] TemplateDirError),
@@ -179,34 +424,44 @@ pub enum TemplateError {
     #[error(transparent)]
     ReadFile(#[from] TemplateReadError),
 
-    #[error("failed to parse template file: {0}")]
-    Parse(tera::Error),
+    #[error(transparent)]
+    Remote(#[from] RemoteError),
+
+    #[error("no {file} template in template group {group}")]
+    NotFound { group: String, file: &'static str },
 
-    #[error("failed to render template: {0}")]
-    Render(tera::Error),
+    #[error("failed to parse template file")]
+    Parse(#[source] tera::Error),
+
+    #[error("failed to render template")]
+    Render(#[source] tera::Error),
 }
 
 #[derive(thiserror::Error, Debug)]
-#[error("failed to read template directory: {path}: {err}")]
+#[error("failed to read template directory: {path}")]
 pub struct TemplateDirError {
     path: PathBuf,
+    #[source]
     err: std::io::Error,
 }
 
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum TemplateDirNameError {
     #[error("directory name is not UTF-8: {name:?}")]
     NotUtf8 { name: OsString },
 }
 
 #[derive(thiserror::Error, Debug)]
-#[error("failed to read template file: {path}: {err}")]
+#[error("failed to read template file: {path}")]
 pub struct TemplateReadError {
     path: PathBuf,
+    #[source]
     err: std::io::Error,
 }
 
-fn named_template_dirs(dir: &Path) -> Result<Vec<PathBuf>, TemplateDirError> {
+/// List all the files in this directory and its subdirectories, except hidden ones (like `.git`).
+fn files_within(dir: &Path) -> Result<Vec<PathBuf>, TemplateDirError> {
     let entries = match dir.read_dir() {
         Ok(entries) => entries,
 
@@ -223,18 +478,30 @@ fn named_template_dirs(dir: &Path) -> Result<Vec<PathBuf>, TemplateDirError> {
         }
     };
 
-    let paths: Vec<PathBuf> = entries
-        .filter_map(|entry| {
-            let Ok(path) = entry.as_ref().map(|e| e.path()) else {
-                tracing::debug!("skipping directory entry error: {:?}", entry);
-                return None;
-            };
+    let mut files = Vec::new();
 
-            path.is_dir().then_some(path)
-        })
-        .collect();
+    for entry in entries {
+        let Ok(path) = entry.as_ref().map(|e| e.path()) else {
+            tracing::debug!("skipping directory entry error: {:?}", entry);
+            continue;
+        };
+
+        let hidden = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with('.'));
+        if hidden {
+            continue;
+        }
 
-    Ok(paths)
+        if path.is_dir() {
+            files.extend(files_within(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(files)
 }
 
 #[cfg(test)]
@@ -269,6 +536,11 @@ mod tests {
         let ctx = TemplateContext {
             id: MigrationId(123),
             name: String::from("custom"),
+            vars: Default::default(),
+            schema: None,
+            created_at: None,
+            author: None,
+            profile: None,
         };
 
         for id in [TemplateId::NewUp, TemplateId::NewDown] {
@@ -293,6 +565,11 @@ mod tests {
         let ctx = TemplateContext {
             id: MigrationId(123),
             name: String::from("custom"),
+            vars: Default::default(),
+            schema: None,
+            created_at: None,
+            author: None,
+            profile: None,
         };
 
         let actual_up = templates
@@ -315,6 +592,82 @@ mod tests {
         assert_eq!(expected_down, actual_down);
     }
 
+    #[tokio::test]
+    async fn template_vars() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+        let templates_dir = config.templates_dir.unwrap();
+
+        std::fs::write(
+            templates_dir.join("new.up.sql"),
+            "create table {{ schema }}.{{ name }} (id int);\nalter table {{ schema }}.{{ name }} owner to {{ owner }};\n",
+        )
+        .unwrap();
+
+        let templates = Templates::new(templates_dir).unwrap();
+
+        let ctx = TemplateContext {
+            id: MigrationId(123),
+            name: String::from("users"),
+            vars: BTreeMap::from([
+                (String::from("schema"), String::from("app")),
+                (String::from("owner"), String::from("app_owner")),
+                (String::from("name"), String::from("ignored")),
+            ]),
+            schema: None,
+            created_at: None,
+            author: None,
+            profile: None,
+        };
+
+        let actual = templates
+            .render(TemplateGroup::Default, TemplateId::NewUp, &ctx)
+            .unwrap();
+        assert_eq!(
+            "create table app.users (id int);\nalter table app.users owner to app_owner;\n",
+            actual
+        );
+    }
+
+    #[tokio::test]
+    async fn header_context() {
+        let env = TestEnv::new().await.unwrap();
+        let mut config = env.config();
+        let templates_dir = config.templates_dir.clone().unwrap();
+
+        std::fs::write(
+            templates_dir.join("new.up.sql"),
+            "-- {{ created_at }} by {{ author }} ({{ profile }})\ncomment on table {{ table | quote_ident }} is {{ note | quote_literal }};\n",
+        )
+        .unwrap();
+
+        let templates = Templates::new(templates_dir).unwrap();
+
+        let ctx = TemplateContext {
+            id: MigrationId(123),
+            name: String::from("users"),
+            vars: BTreeMap::from([
+                (String::from("table"), String::from(r#"User "Accounts""#)),
+                (String::from("note"), String::from("Everyone's accounts")),
+            ]),
+            schema: None,
+            created_at: Some(time::macros::datetime!(2024-05-06 07:08:09 UTC)),
+            author: Some(String::from("Ada")),
+            profile: Some(String::from("staging")),
+        };
+
+        let actual = templates
+            .render(TemplateGroup::Default, TemplateId::NewUp, &ctx)
+            .unwrap();
+        assert_eq!(
+            "-- 2024-05-06T07:08:09Z by Ada (staging)\ncomment on table \"User \"\"Accounts\"\"\" is 'Everyone''s accounts';\n",
+            actual
+        );
+
+        config.author = Some(String::from("Grace"));
+        assert_eq!(Some(String::from("Grace")), template_author(&config));
+    }
+
     #[tokio::test]
     async fn custom_templates_both() {
         let env = TestEnv::new().await.unwrap();
@@ -329,6 +682,11 @@ mod tests {
         let ctx = TemplateContext {
             id: MigrationId(123),
             name: String::from("custom"),
+            vars: Default::default(),
+            schema: None,
+            created_at: None,
+            author: None,
+            profile: None,
         };
 
         let actual_up = templates
@@ -368,6 +726,11 @@ custom
         let ctx = TemplateContext {
             id: MigrationId(123),
             name: String::from("custom"),
+            vars: Default::default(),
+            schema: None,
+            created_at: None,
+            author: None,
+            profile: None,
         };
 
         let group = TemplateGroup::Named("create_table".to_owned());
@@ -389,4 +752,212 @@ custom
         assert_eq!(expected_up, actual_up);
         assert_eq!(expected_down, actual_down);
     }
+
+    #[test]
+    fn builtin_library() {
+        let templates = Templates::default();
+
+        let ctx = TemplateContext {
+            id: MigrationId(123),
+            name: String::from("mood"),
+            vars: BTreeMap::from([
+                (String::from("type"), String::from("mood")),
+                (String::from("values"), String::from("happy, sad")),
+            ]),
+            schema: None,
+            created_at: None,
+            author: None,
+            profile: None,
+        };
+
+        let group = TemplateGroup::Named("create_enum".to_owned());
+        let actual = templates.render(&group, TemplateId::NewUp, &ctx).unwrap();
+        assert_eq!(
+            "-- ID:   123\n-- Name: mood\n\ncreate type mood as enum (\n    'happy',\n    'sad'\n);\n",
+            actual
+        );
+
+        let group = TemplateGroup::Named("create_index_concurrently".to_owned());
+        for id in [TemplateId::NewUp, TemplateId::NewDown] {
+            let actual = templates.render(&group, id, &ctx).unwrap();
+            assert!(
+                crate::migrate::skip_transaction(&actual),
+                "{id:?}: {actual}"
+            );
+        }
+
+        let group = TemplateGroup::Named("backfill".to_owned());
+        let actual = templates.render(&group, TemplateId::NewUp, &ctx).unwrap();
+        let directives = crate::directives::MigrationDirectives::parse(&actual).unwrap();
+        assert!(directives.batched, "{actual}");
+        assert!(
+            actual.contains("select ctid from TODO_table_name\n"),
+            "{actual}"
+        );
+
+        let group = TemplateGroup::Named("create_table".to_owned());
+        let actual = templates.render(&group, TemplateId::NewDown, &ctx).unwrap();
+        assert!(actual.contains("drop table TODO_table_name;"), "{actual}");
+
+        let group = TemplateGroup::Named("not_a_template".to_owned());
+        match templates.render(&group, TemplateId::NewUp, &ctx) {
+            Err(TemplateError::NotFound { group, file }) => {
+                assert_eq!("not_a_template", group);
+                assert_eq!("new.up.sql", file);
+            }
+
+            Err(err) => panic!("Unexpected error: {:?}", err),
+            Ok(sql) => panic!("Unexpected success: {sql}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn partials() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+        let templates_dir = config.templates_dir.unwrap();
+
+        std::fs::create_dir_all(templates_dir.join("partials/nested")).unwrap();
+        std::fs::write(
+            templates_dir.join("partials/header.sql"),
+            "-- Migration {{ id }}: {{ name }}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            templates_dir.join("partials/nested/lock.sql"),
+            "set lock_timeout = '5s';\n",
+        )
+        .unwrap();
+
+        std::fs::write(
+            templates_dir.join("new.up.sql"),
+            r#"{% include "partials/header.sql" %}-- Up"#,
+        )
+        .unwrap();
+
+        std::fs::create_dir_all(templates_dir.join("create_table")).unwrap();
+        std::fs::write(
+            templates_dir.join("create_table/new.up.sql"),
+            r#"{% include "partials/header.sql" %}{% include "partials/nested/lock.sql" %}"#,
+        )
+        .unwrap();
+
+        let templates = Templates::new(&templates_dir).unwrap();
+
+        let ctx = TemplateContext {
+            id: MigrationId(123),
+            name: String::from("custom"),
+            vars: Default::default(),
+            schema: None,
+            created_at: None,
+            author: None,
+            profile: None,
+        };
+
+        let actual = templates
+            .render(TemplateGroup::Default, TemplateId::NewUp, &ctx)
+            .unwrap();
+        assert_eq!("-- Migration 123: custom\n-- Up", actual);
+
+        let group = TemplateGroup::Named("create_table".to_owned());
+        let actual = templates.render(&group, TemplateId::NewUp, &ctx).unwrap();
+        assert_eq!(
+            "-- Migration 123: custom\nset lock_timeout = '5s';\n",
+            actual
+        );
+
+        // The partials directory is not a template group.
+        let group = TemplateGroup::Named("partials".to_owned());
+        templates
+            .render(&group, TemplateId::NewUp, &ctx)
+            .unwrap_err();
+
+        let sources: Vec<_> = templates
+            .files()
+            .into_iter()
+            .filter(|f| f.group == TemplateGroup::Named("create_table".to_owned()))
+            .map(|f| (f.id, f.source))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    TemplateId::NewUp,
+                    TemplateSource::File(templates_dir.join("create_table/new.up.sql"))
+                ),
+                (TemplateId::NewDown, TemplateSource::Embedded),
+            ],
+            sources
+        );
+
+        // Built-in groups are listed along with the local ones.
+        assert_eq!(
+            vec![
+                TemplateGroup::Default,
+                TemplateGroup::Named("add_column".to_owned()),
+                TemplateGroup::Named("backfill".to_owned()),
+                TemplateGroup::Named("create_enum".to_owned()),
+                TemplateGroup::Named("create_index_concurrently".to_owned()),
+                TemplateGroup::Named("create_table".to_owned()),
+            ],
+            templates.groups()
+        );
+    }
+
+    #[tokio::test]
+    async fn inheritance() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+        let templates_dir = config.templates_dir.unwrap();
+
+        // The base template sorts after the ones that extend it.
+        std::fs::write(
+            templates_dir.join("new.up.sql"),
+            r#"{% extends "zz_base.sql" %}{% block body %}-- Up{% endblock %}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            templates_dir.join("zz_base.sql"),
+            "-- Migration {{ id }}\nset lock_timeout = '5s';\n{% block body %}{% endblock %}\n",
+        )
+        .unwrap();
+
+        std::fs::create_dir_all(templates_dir.join("seed")).unwrap();
+        std::fs::write(
+            templates_dir.join("seed/new.up.sql"),
+            r#"{% extends "zz_base.sql" %}{% block body %}-- Seed {{ name }}{% endblock %}"#,
+        )
+        .unwrap();
+
+        // Only SQL files are templates, so this isn't parsed.
+        std::fs::write(templates_dir.join("README.md"), "Unmatched brace {%").unwrap();
+
+        let templates = Templates::new(&templates_dir).unwrap();
+
+        let ctx = TemplateContext {
+            id: MigrationId(123),
+            name: String::from("custom"),
+            vars: Default::default(),
+            schema: None,
+            created_at: None,
+            author: None,
+            profile: None,
+        };
+
+        let actual = templates
+            .render(TemplateGroup::Default, TemplateId::NewUp, &ctx)
+            .unwrap();
+        assert_eq!(
+            "-- Migration 123\nset lock_timeout = '5s';\n-- Up\n",
+            actual
+        );
+
+        let group = TemplateGroup::Named("seed".to_owned());
+        let actual = templates.render(&group, TemplateId::NewUp, &ctx).unwrap();
+        assert_eq!(
+            "-- Migration 123\nset lock_timeout = '5s';\n-- Seed custom\n",
+            actual
+        );
+
+        assert!(templates.groups().contains(&group));
+    }
 }
@@ -1,5 +1,8 @@
 drop function if exists _squill_require_migration;
+drop function if exists _squill_record_duration;
 drop function if exists _squill_unclaim_migration;
 drop function if exists _squill_claim_migration;
 
+drop table if exists schema_migration_checkpoints;
+drop table if exists schema_migration_undos;
 drop table if exists schema_migrations;
@@ -36,11 +36,43 @@ defined here.
 */
 --squill:no-transaction
 begin;
-
+{% if schema %}
+-- The schema config puts the migration log (and every migration) in this
+-- schema instead of public.
+create schema if not exists {{ schema | quote_ident }};
+{% endif %}
 create table schema_migrations (
     id bigint primary key,
     name text not null,
-    run_at timestamp not null default current_timestamp
+    run_at timestamp not null default current_timestamp,
+    duration_ms bigint,
+    applied_by text default current_user,
+    -- Squill sets squill.applied_with to its version and the host it's running on.
+    applied_with text default current_setting('squill.applied_with', true)
+);
+
+-- schema_migration_undos keeps the migrations that were undone, so `squill log`
+-- can show the full history.
+create table schema_migration_undos (
+    id bigint not null,
+    name text not null,
+    run_at timestamp not null,
+    applied_by text,
+    applied_with text,
+    undone_at timestamp not null default current_timestamp,
+    undone_by text default current_user
+);
+
+-- schema_migration_checkpoints keeps the statements that finished in a
+-- no-transaction migration with a squill:checkpoints directive, so a failed
+-- run can be resumed with `squill migrate --resume`. Squill clears a
+-- migration's checkpoints once it's applied (or undone).
+create table schema_migration_checkpoints (
+    id bigint not null,
+    statement integer not null,
+    checksum text not null,
+    completed_at timestamp not null default current_timestamp,
+    primary key (id, statement)
 );
 
 -- _squill_claim_migration registers a migration in the schema_migrations
@@ -54,15 +86,28 @@ create function _squill_claim_migration(mid bigint, mname text) returns void as
 $$ language sql;
 
 -- _squill_unclaim_migration removes a migration from the schema_migrations
--- table.
+-- table, moving it to schema_migration_undos.
 --
 -- When iterating on a migration in development, it's useful to have a down
 -- migration to reset back to the previous schema. Squill will call this at the
 -- start of every "down" migration transaction so the "up" migration can run
 -- again. For migrations that cannot be run within transactions, it is the
 -- migration's responsibility to call this.
-create function _squill_unclaim_migration(mid bigint) returns void as $$ delete
-from schema_migrations where id = mid; $$ language sql;
+create function _squill_unclaim_migration(mid bigint) returns void as $$
+    insert into schema_migration_undos (id, name, run_at, applied_by, applied_with)
+    select id, name, run_at, applied_by, applied_with from schema_migrations where id = mid;
+
+    delete from schema_migrations where id = mid;
+$$ language sql;
+
+-- _squill_record_duration saves how long a migration took to apply, in
+-- milliseconds.
+--
+-- Squill will call this at the end of every "up" migration, just before the
+-- transaction commits.
+create function _squill_record_duration(mid bigint, ms bigint) returns void as $$
+    update schema_migrations set duration_ms = ms where id = mid;
+$$ language sql;
 
 -- _squill_require_migration asserts that the migration ID has already been
 -- claimed in the schema_migrations table.
@@ -0,0 +1,5 @@
+-- ID:   {{ id }}
+-- Name: {{ name }}
+
+alter table {{ table | default(value="TODO_table_name") }}
+    add column {{ column | default(value="TODO_column_name") }} {{ type | default(value="text") }};
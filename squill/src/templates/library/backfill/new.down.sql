@@ -0,0 +1,5 @@
+-- ID:   {{ id }}
+-- Name: {{ name }}
+--
+-- TODO: Most backfills can't be undone. Reverse this one here if it can be, or
+-- delete this file to make the migration forward-only.
@@ -0,0 +1,17 @@
+-- ID:   {{ id }}
+-- Name: {{ name }}
+--
+-- Squill runs this file again (committing after each run) until it updates no
+-- more rows, so each batch only holds its locks for a moment. The update has to
+-- skip the rows it already changed, or it will never finish.
+--squill:batched
+{%- set table = table | default(value="TODO_table_name") %}
+{%- set column = column | default(value="TODO_column_name") %}
+
+update {{ table }}
+set {{ column }} = {{ value | default(value="TODO_value") }}
+where ctid in (
+    select ctid from {{ table }}
+    where {{ column }} is null
+    limit {{ batch_size | default(value="1000") }}
+);
@@ -0,0 +1,4 @@
+-- ID:   {{ id }}
+-- Name: {{ name }}
+
+drop type {{ type | default(value="TODO_type_name") }};
@@ -0,0 +1,9 @@
+-- ID:   {{ id }}
+-- Name: {{ name }}
+{%- set values = values | default(value="TODO_value") | split(pat=",") %}
+
+create type {{ type | default(value="TODO_type_name") }} as enum (
+{%- for value in values %}
+    '{{ value | trim }}'{% if not loop.last %},{% endif %}
+{%- endfor %}
+);
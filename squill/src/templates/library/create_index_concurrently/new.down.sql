@@ -0,0 +1,7 @@
+-- ID:   {{ id }}
+-- Name: {{ name }}
+--squill:no-transaction
+
+drop index concurrently if exists {{ index | default(value="TODO_index_name") }};
+
+select _squill_unclaim_migration({{ id }});
@@ -0,0 +1,12 @@
+-- ID:   {{ id }}
+-- Name: {{ name }}
+--
+-- Building the index concurrently doesn't block writes, but it can't happen
+-- inside a transaction. So this migration records itself in the migration log
+-- after the index is built.
+--squill:no-transaction
+
+create index concurrently if not exists {{ index | default(value="TODO_index_name") }}
+    on {{ table | default(value="TODO_table_name") }} ({{ column | default(value="TODO_column_name") }});
+
+select _squill_claim_migration({{ id }}, '{{ name }}');
@@ -0,0 +1,4 @@
+-- ID:   {{ id }}
+-- Name: {{ name }}
+
+drop table {{ table | default(value="TODO_table_name") }};
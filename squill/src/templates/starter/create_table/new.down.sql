@@ -0,0 +1,4 @@
+-- ID:   {{ id }}
+-- Name: {{ name }}
+
+drop table TODO_table_name;
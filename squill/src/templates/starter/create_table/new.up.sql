@@ -0,0 +1,7 @@
+-- ID:   {{ id }}
+-- Name: {{ name }}
+
+create table TODO_table_name (
+    id bigint generated always as identity primary key,
+    created_at timestamptz not null default now()
+);
@@ -1,35 +1,57 @@
-use sqlx::{postgres::PgConnectOptions, ConnectOptions, Executor};
+//! Helpers for integration tests that need a real database.
+//!
+//! These are enabled by the `testing` feature. Each [`TestEnv`] creates its own database on the
+//! server that the standard `PG*` environment variables point to.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use sqlx::postgres::PgConnectOptions;
 use tempfile::TempDir;
-use uuid::Uuid;
 
-use crate::index::MigrationParams;
+use crate::events::Observers;
+use crate::hooks::Hooks;
+use crate::index::{IdStrategy, MigrationParams};
+use crate::lint::LintPolicy;
+use crate::naming::MigrationNaming;
+use crate::sandbox::{Sandbox, SandboxError};
+use crate::status::OutOfOrderPolicy;
 use crate::{create_init_migration, Config};
 
-pub const NO_OP_NO_TX: &str = include_str!("testing/no_op_no_tx.sql");
-pub const NO_OP_YES_TX: &str = include_str!("testing/no_op_yes_tx.sql");
-pub const CUSTOM_UP: &str = include_str!("testing/custom.up.sql");
-pub const CUSTOM_DOWN: &str = include_str!("testing/custom.down.sql");
-pub const CREATE_TABLE_UP: &str = include_str!("testing/create_table.up.sql");
-pub const CREATE_TABLE_DOWN: &str = include_str!("testing/create_table.down.sql");
+pub(crate) const NO_OP_NO_TX: &str = include_str!("testing/no_op_no_tx.sql");
+pub(crate) const NO_OP_YES_TX: &str = include_str!("testing/no_op_yes_tx.sql");
+pub(crate) const CUSTOM_UP: &str = include_str!("testing/custom.up.sql");
+pub(crate) const CUSTOM_DOWN: &str = include_str!("testing/custom.down.sql");
+pub(crate) const CREATE_TABLE_UP: &str = include_str!("testing/create_table.up.sql");
+pub(crate) const CREATE_TABLE_DOWN: &str = include_str!("testing/create_table.down.sql");
 
+/// A disposable database with temporary migrations, templates, and cache directories.
 #[derive(Debug)]
 pub struct TestEnv {
-    pub database: TempDb,
+    pub database: Sandbox,
     pub migrations_dir: TempDir,
     pub templates_dir: TempDir,
+    pub cache_dir: TempDir,
 }
 
 impl TestEnv {
+    /// Create an empty database and temporary directories.
     pub async fn new() -> anyhow::Result<Self> {
-        let opts = PgConnectOptions::new();
+        let server = Config {
+            database_connect_options: Some(PgConnectOptions::new()),
+            ..Config::default()
+        };
 
         Ok(Self {
-            database: TempDb::new(opts).await?,
+            database: Sandbox::create(&server, "squill_test").await?,
             migrations_dir: tempfile::Builder::new().prefix("migrations_").tempdir()?,
             templates_dir: tempfile::Builder::new().prefix("templates_").tempdir()?,
+            cache_dir: tempfile::Builder::new().prefix("cache_").tempdir()?,
         })
     }
 
+    /// Create the environment and apply the init migration, so the migration log exists.
     pub async fn initialized() -> anyhow::Result<Self> {
         let env = Self::new().await?;
         let config = env.config();
@@ -42,48 +64,74 @@ impl TestEnv {
         Ok(env)
     }
 
+    /// A config for your own migrations directory instead of the temporary one.
+    ///
+    /// Pass this to [`migrate_all`](crate::migrate_all) to set up the database for a test.
+    pub fn config_with_migrations(&self, migrations_dir: impl Into<PathBuf>) -> Config {
+        Config {
+            migrations_dir: migrations_dir.into(),
+            ..self.config()
+        }
+    }
+
+    /// Drop the database. The temporary directories are removed when the environment is dropped.
+    pub async fn drop_database(self) -> Result<(), SandboxError> {
+        self.database.drop_database().await
+    }
+
     pub fn config(&self) -> Config {
         Config {
-            database_connect_options: Some(self.database.connect_options.clone()),
+            database_connect_options: Some(self.database.connect_options().clone()),
             migrations_dir: self.migrations_dir.path().into(),
+            extra_migrations_dirs: Vec::new(),
             templates_dir: Some(self.templates_dir.path().into()),
+            templates_repo: None,
+            cache_dir: self.cache_dir.path().into(),
             only_up: true,
+            state_file: None,
+            auto_no_transaction: false,
+            hooks: Hooks::default(),
+            editor: None,
+            collect_stats: false,
+            schema_file: None,
+            notifications: Vec::new(),
+            profile: None,
+            redact_patterns: Vec::new(),
+            id_strategy: IdStrategy::default(),
+            out_of_order: OutOfOrderPolicy::default(),
+            record_metadata: false,
+            connect_retries: 0,
+            connect_backoff: Duration::from_millis(500),
+            connect_wait: None,
+            record_failures: false,
+            template_vars: BTreeMap::new(),
+            id_width: None,
+            skip_applied: false,
+            auto_down: false,
+            lint: LintPolicy::Off,
+            observers: Observers::default(),
+            databases: BTreeMap::new(),
+            schema: None,
+            no_down: false,
+            naming: MigrationNaming::default(),
+            statement_timeout: None,
+            lock_timeout: None,
+            protected: false,
+            protected_patterns: Vec::new(),
+            record_attempts: false,
+            expected_database_name: None,
+            expected_server_fingerprint: None,
+            author: None,
         }
     }
 }
 
-#[derive(Debug)]
-pub struct TempDb {
-    pub connect_options: PgConnectOptions,
-}
-
-impl TempDb {
-    pub async fn new(opts: PgConnectOptions) -> anyhow::Result<Self> {
-        // This name is controlled by this test, so the executed `create database`
-        // statement is okay to execute.
-        //
-        // This has to be done with string interpolation because Postgres doesn't support
-        // using a prepared statement to create a database.
-        let name = format!("squill_test_{}", Uuid::new_v4().simple());
-        let create_database = format!("create database {}", name);
-
-        let mut conn = opts.connect().await?;
-        conn.execute(&*create_database).await?;
-
-        // Now that the target database has actually been created, future connections can use it.
-        let opts = opts.database(&name);
-
-        Ok(Self {
-            connect_options: opts,
-        })
-    }
-}
-
+/// A migration that creates (and drops) a table named after it.
 pub fn fake_migration(id: i64, name: &str) -> MigrationParams {
     MigrationParams {
-        id: id.try_into().unwrap(),
+        id: id.try_into().expect("non-negative migration ID"),
         name: name.into(),
         up_sql: format!("create table tbl_{name} (id_{id} int)"),
-        down_sql: format!("drop table tbl_{name}"),
+        down_sql: Some(format!("drop table tbl_{name}")),
     }
 }
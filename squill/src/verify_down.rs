@@ -0,0 +1,272 @@
+use std::collections::BTreeSet;
+
+use sqlx::{Connection, PgConnection};
+
+use crate::config::{Config, ConnectError};
+use crate::error_chain;
+use crate::index::IndexError;
+use crate::migrate::{MigrateError, MigrateOptions, MigrationDirectory, MigrationId};
+use crate::sandbox::{Sandbox, SandboxError};
+
+/// The part of the up, down, up cycle that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownStep {
+    Up,
+    Down,
+    Reapply,
+}
+
+impl std::fmt::Display for DownStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownStep::Up => write!(f, "up"),
+            DownStep::Down => write!(f, "down"),
+            DownStep::Reapply => write!(f, "up again"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownResult {
+    /// The down migration ran, and the up migration ran again after it.
+    Reversed,
+
+    /// The migration has no down file (or has the only-up directive), so only the up migration ran.
+    Irreversible,
+
+    Failed {
+        step: DownStep,
+        error: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownCheck {
+    pub migration: MigrationDirectory,
+    pub result: DownResult,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyDownReport {
+    /// The name of the disposable database.
+    pub database: String,
+
+    /// The migrations that were checked, in ID order.
+    pub checks: Vec<DownCheck>,
+}
+
+impl VerifyDownReport {
+    pub fn passed(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|c| !matches!(c.result, DownResult::Failed { .. }))
+    }
+}
+
+/// Check that the down migration of each target reverses its up migration.
+///
+/// This creates a new database and applies every migration in order. Each target is run up, then
+/// down, then up again, so a down migration that errors or leaves something behind fails the
+/// check. The first failure stops the run, since later migrations can't be trusted on top of it.
+/// The database is dropped afterward, even if a check failed.
+pub async fn verify_down(
+    config: &Config,
+    targets: &[MigrationDirectory],
+) -> Result<VerifyDownReport, VerifyDownError> {
+    let sandbox = Sandbox::create(config, "squill_verify_down")
+        .await
+        .map_err(VerifyDownError::Sandbox)?;
+
+    let checked = run_checks(&sandbox.config(), targets).await;
+    let database = sandbox.name().to_owned();
+    let dropped = sandbox.drop_database().await;
+
+    let checks = checked?;
+    dropped.map_err(VerifyDownError::Sandbox)?;
+
+    Ok(VerifyDownReport { database, checks })
+}
+
+async fn run_checks(
+    config: &Config,
+    targets: &[MigrationDirectory],
+) -> Result<Vec<DownCheck>, VerifyDownError> {
+    let target_ids: BTreeSet<MigrationId> = targets.iter().map(|m| m.id).collect();
+    let Some(last) = target_ids.last().copied() else {
+        return Ok(Vec::new());
+    };
+
+    let index = config.migration_index().map_err(VerifyDownError::Index)?;
+    let retired = index.retired();
+
+    // The sandbox is thrown away, so its down migrations are always allowed.
+    let opts = MigrateOptions {
+        only_up: false,
+        ..config.migrate_options()
+    };
+
+    let migrations: Vec<_> = index
+        .iter()
+        .filter(|m| m.id <= last && !retired.contains_key(&m.id))
+        .collect();
+
+    let mut conn = config.connect().await.map_err(VerifyDownError::Connect)?;
+    let checks = check_all(&mut conn, &migrations, &target_ids, &opts).await;
+
+    // The sandbox can't be dropped while anything is still connected to it.
+    let _ = conn.close().await;
+
+    checks
+}
+
+async fn check_all(
+    conn: &mut PgConnection,
+    migrations: &[&MigrationDirectory],
+    target_ids: &BTreeSet<MigrationId>,
+    opts: &MigrateOptions,
+) -> Result<Vec<DownCheck>, VerifyDownError> {
+    let mut checks = Vec::new();
+
+    for &migration in migrations {
+        if !target_ids.contains(&migration.id) {
+            migration
+                .up_with(&mut *conn, opts)
+                .await
+                .map_err(|err| VerifyDownError::Setup {
+                    migration: migration.clone(),
+                    err,
+                })?;
+            continue;
+        }
+
+        let result = check_down(conn, migration, opts).await;
+        let failed = matches!(result, DownResult::Failed { .. });
+
+        checks.push(DownCheck {
+            migration: migration.clone(),
+            result,
+        });
+
+        if failed {
+            break;
+        }
+    }
+
+    Ok(checks)
+}
+
+async fn check_down(
+    conn: &mut PgConnection,
+    migration: &MigrationDirectory,
+    opts: &MigrateOptions,
+) -> DownResult {
+    let failed = |step: DownStep| {
+        move |err: MigrateError| DownResult::Failed {
+            step,
+            error: error_chain(&err),
+        }
+    };
+
+    if let Err(err) = migration.up_with(&mut *conn, opts).await {
+        return failed(DownStep::Up)(err);
+    }
+
+    let only_up = migration.directives().is_ok_and(|d| d.only_up);
+    if !migration.is_reversible() || only_up {
+        return DownResult::Irreversible;
+    }
+
+    if let Err(err) = migration.down_with(&mut *conn, opts).await {
+        return failed(DownStep::Down)(err);
+    }
+
+    if let Err(err) = migration.up_with(&mut *conn, opts).await {
+        return failed(DownStep::Reapply)(err);
+    }
+
+    DownResult::Reversed
+}
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum VerifyDownError {
+    #[error(transparent)]
+    Sandbox(SandboxError),
+
+    #[error(transparent)]
+    Connect(ConnectError),
+
+    #[error(transparent)]
+    Index(IndexError),
+
+    #[error("failed to apply earlier migration {migration}")]
+    Setup {
+        migration: MigrationDirectory,
+        #[source]
+        err: MigrateError,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::create_init_migration;
+    use crate::index::{MigrationIndex, MigrationParams};
+    use crate::testing::*;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn up_down_up() {
+        let env = TestEnv::new().await.unwrap();
+        let config = env.config();
+
+        create_init_migration(&config).unwrap();
+        let mut index = MigrationIndex::new(&config.migrations_dir).unwrap();
+        let one = index.create(fake_migration(1, "one")).unwrap();
+
+        let mut params = fake_migration(2, "forward_only");
+        params.down_sql = None;
+        let two = index.create(params).unwrap();
+
+        let three = index
+            .create(MigrationParams {
+                id: MigrationId(3),
+                name: String::from("leaky"),
+                up_sql: String::from("create table leaky (id int)"),
+                down_sql: Some(String::from("select 1")),
+            })
+            .unwrap();
+        let four = index.create(fake_migration(4, "four")).unwrap();
+
+        let report = verify_down(&config, &[one, two]).await.unwrap();
+        assert!(report.passed(), "{report:?}");
+        let results: Vec<_> = report.checks.iter().map(|c| c.result.clone()).collect();
+        assert_eq!(
+            vec![DownResult::Reversed, DownResult::Irreversible],
+            results
+        );
+
+        let report = verify_down(&config, &[three, four]).await.unwrap();
+        assert!(!report.passed());
+        assert_eq!(1, report.checks.len(), "{report:?}");
+        assert!(
+            matches!(
+                report.checks[0].result,
+                DownResult::Failed {
+                    step: DownStep::Reapply,
+                    ..
+                }
+            ),
+            "{report:?}"
+        );
+
+        // The configured database was never migrated.
+        let mut conn = config.connect().await.unwrap();
+        let migrated: Option<String> =
+            sqlx::query_scalar("select to_regclass('schema_migrations')::text")
+                .fetch_one(&mut conn)
+                .await
+                .unwrap();
+        assert_eq!(None, migrated);
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+use std::str::FromStr;
+
+use time::{OffsetDateTime, Time};
+
+/// A daily UTC time range when migrations are allowed to start, like `02:00-04:00Z`.
+///
+/// The end is exclusive. A window whose end is before its start wraps past midnight.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+    pub start: Time,
+    pub end: Time,
+}
+
+impl MaintenanceWindow {
+    pub fn contains(&self, at: OffsetDateTime) -> bool {
+        let t = at.to_offset(time::UtcOffset::UTC).time();
+
+        if self.start <= self.end {
+            self.start <= t && t < self.end
+        } else {
+            self.start <= t || t < self.end
+        }
+    }
+
+    /// Whether a new migration can start right now.
+    pub fn is_open(&self) -> bool {
+        self.contains(OffsetDateTime::now_utc())
+    }
+}
+
+impl FromStr for MaintenanceWindow {
+    type Err = ParseWindowError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseWindowError(s.to_owned());
+
+        // Only UTC is supported, but allow the Z to make that explicit.
+        let range = s.strip_suffix(['Z', 'z']).unwrap_or(s);
+
+        let (start, end) = range.split_once('-').ok_or_else(err)?;
+        let start = parse_time(start).ok_or_else(err)?;
+        let end = parse_time(end).ok_or_else(err)?;
+
+        if start == end {
+            return Err(err());
+        }
+
+        Ok(Self { start, end })
+    }
+}
+
+fn parse_time(s: &str) -> Option<Time> {
+    let (h, m) = s.trim().split_once(':')?;
+    Time::from_hms(h.parse().ok()?, m.parse().ok()?, 0).ok()
+}
+
+impl fmt::Display for MaintenanceWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}Z",
+            self.start.hour(),
+            self.start.minute(),
+            self.end.hour(),
+            self.end.minute()
+        )
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("invalid maintenance window (expected HH:MM-HH:MMZ): {0:?}")]
+pub struct ParseWindowError(String);
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let window: MaintenanceWindow = "02:00-04:30Z".parse().unwrap();
+        assert_eq!(Time::from_hms(2, 0, 0).unwrap(), window.start);
+        assert_eq!(Time::from_hms(4, 30, 0).unwrap(), window.end);
+        assert_eq!("02:00-04:30Z", window.to_string());
+
+        "2:00-4:00".parse::<MaintenanceWindow>().unwrap();
+
+        "02:00".parse::<MaintenanceWindow>().unwrap_err();
+        "02:00-02:00Z".parse::<MaintenanceWindow>().unwrap_err();
+        "25:00-04:00Z".parse::<MaintenanceWindow>().unwrap_err();
+    }
+
+    #[test]
+    fn contains() {
+        let window: MaintenanceWindow = "02:00-04:00Z".parse().unwrap();
+        assert!(!window.contains(datetime!(2024-01-01 01:59 UTC)));
+        assert!(window.contains(datetime!(2024-01-01 02:00 UTC)));
+        assert!(window.contains(datetime!(2024-01-01 03:59 UTC)));
+        assert!(!window.contains(datetime!(2024-01-01 04:00 UTC)));
+
+        // Offsets are converted to UTC first.
+        assert!(window.contains(datetime!(2024-01-01 05:00 +02:00)));
+
+        let overnight: MaintenanceWindow = "23:00-01:00Z".parse().unwrap();
+        assert!(overnight.contains(datetime!(2024-01-01 23:30 UTC)));
+        assert!(overnight.contains(datetime!(2024-01-01 00:30 UTC)));
+        assert!(!overnight.contains(datetime!(2024-01-01 12:00 UTC)));
+    }
+}